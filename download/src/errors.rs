@@ -18,6 +18,17 @@ error_chain! {
         FileNotFound {
             description("file not found")
         }
+        InvalidOptions {
+            description("invalid download options")
+        }
+        TooSlow {
+            description("download too slow")
+            display("the download was slower than the low speed limit for too long")
+        }
+        SavingFile(what: &'static str) {
+            description("unable to save the download")
+            display("{}", what)
+        }
         BackendUnavailable(be: &'static str) {
             description("download backend unavailable")
             display("download backend '{}' unavailable", be)
@@ -1,17 +1,20 @@
 //! Easy file downloading
 
-use std::path::Path;
+use std::cell::RefCell;
+use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
 
 #[allow(deprecated)] // WORKAROUND https://github.com/rust-lang-nursery/error-chain/issues/254
 mod errors;
 pub use crate::errors::*;
 
-#[derive(Debug, Copy, Clone)]
-pub enum Backend {
-    Curl,
-    Reqwest,
-}
+mod options;
+pub use crate::options::*;
 
 #[derive(Debug, Copy, Clone)]
 pub enum Event<'a> {
@@ -20,120 +23,572 @@ pub enum Event<'a> {
     DownloadContentLengthReceived(u64),
     /// Received some data.
     DownloadDataReceived(&'a [u8]),
+    /// The download failed, and is about to be attempted again. Holds
+    /// the number of the next attempt, and how long until it starts.
+    RetryingDownload(u32, Duration),
+    /// Received the entity tag of the file.
+    ETagReceived(&'a str),
+    /// Received the time the file was last modified, as an HTTP date.
+    LastModifiedReceived(&'a str),
+    /// The server sent the whole file instead of the rest of it, because
+    /// it changed since the part already downloaded. The data received
+    /// before this is to be thrown away.
+    DownloadRestarted,
+}
+
+/// Receives the events of a download. An error ends the download.
+pub type Callback<'a> = &'a dyn Fn(Event<'_>) -> Result<()>;
+
+/// A way of downloading files.
+pub trait Backend: Send + Sync {
+    /// The name to tell users the backend by
+    fn name(&self) -> &str;
+
+    /// Downloads `url`, skipping its first `resume_from` bytes, and
+    /// passes what arrives to `callback`. The download is made as
+    /// `options` say, except for retries, which are up to the caller.
+    ///
+    /// `if_range` is the validator that came with the bytes already
+    /// downloaded, if any. When the file no longer matches it, the whole
+    /// file is downloaded instead, after an `Event::DownloadRestarted`.
+    fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        callback: Callback<'_>,
+    ) -> Result<()>;
+
+    /// Whether the backend keeps to `options.max_rate` and fails slow
+    /// downloads itself. Otherwise that is done as the data arrives.
+    fn limits_speed(&self) -> bool {
+        false
+    }
+}
+
+/// Downloads with libcurl.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CurlBackend;
+
+impl Backend for CurlBackend {
+    fn name(&self) -> &str {
+        "curl"
+    }
+
+    fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        callback: Callback<'_>,
+    ) -> Result<()> {
+        curl::download(url, resume_from, if_range, options, callback)
+    }
+
+    fn limits_speed(&self) -> bool {
+        true
+    }
+}
+
+/// Downloads with reqwest.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ReqwestBackend;
+
+impl Backend for ReqwestBackend {
+    fn name(&self) -> &str {
+        "reqwest"
+    }
+
+    fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
+        callback: Callback<'_>,
+    ) -> Result<()> {
+        reqwest_be::download(url, resume_from, if_range, options, callback)
+    }
+}
+
+/// Copies files from `file:` URLs, without going through a network
+/// library. Every download of a `file:` URL uses this backend.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FileBackend;
+
+impl Backend for FileBackend {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &DownloadOptions,
+        callback: Callback<'_>,
+    ) -> Result<()> {
+        file::download(url, resume_from, callback)
+    }
 }
 
 fn download_with_backend(
-    backend: Backend,
+    backend: &dyn Backend,
     url: &Url,
     resume_from: u64,
+    if_range: Option<&str>,
+    options: &DownloadOptions,
     callback: &dyn Fn(Event<'_>) -> Result<()>,
 ) -> Result<()> {
-    match backend {
-        Backend::Curl => curl::download(url, resume_from, callback),
-        Backend::Reqwest => reqwest_be::download(url, resume_from, callback),
+    let backend = if url.scheme() == "file" {
+        &FileBackend
+    } else {
+        backend
+    };
+    if backend.limits_speed() && options.shared_throttle.is_none() {
+        return backend.download(url, resume_from, if_range, options, callback);
+    }
+
+    let throttle = match options.shared_throttle {
+        Some(_) => None,
+        None => options.max_rate.filter(|&rate| rate > 0).map(Throttle::new),
+    };
+    let low_speed = if backend.limits_speed() {
+        None
+    } else {
+        Some(RefCell::new(LowSpeedCheck::new(options)))
+    };
+    backend.download(url, resume_from, if_range, options, &|event| {
+        if let Event::DownloadDataReceived(data) = event {
+            if let Some(ref throttle) = options.shared_throttle {
+                throttle.received(data.len());
+            }
+            if let Some(ref throttle) = throttle {
+                throttle.received(data.len());
+            }
+            if let Some(ref low_speed) = low_speed {
+                low_speed.borrow_mut().received(data.len())?;
+            }
+        }
+        callback(event)
+    })
+}
+
+/// Keeps downloads to `rate` bytes per second on average, by sleeping
+/// whenever data arrives ahead of time. Downloads running at the same
+/// time can share one through `DownloadOptions::shared_throttle`, so
+/// that together they keep to the rate.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    // When the throttle started counting, and the bytes received since
+    started: Mutex<(Instant, u64)>,
+}
+
+// How far downloads may fall behind the rate, while idle, and then
+// catch up with at full speed
+const THROTTLE_MAX_BURST: Duration = Duration::from_secs(1);
+
+impl Throttle {
+    pub fn new(rate: u64) -> Self {
+        Throttle {
+            rate: cmp::max(rate, 1),
+            started: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    fn received(&self, len: usize) {
+        let wait = {
+            let mut started = self.started.lock().unwrap();
+            let (ref mut start, ref mut received) = *started;
+            if self.due(*received) + THROTTLE_MAX_BURST < start.elapsed() {
+                *start = Instant::now();
+                *received = 0;
+            }
+            *received += len as u64;
+            self.due(*received).checked_sub(start.elapsed())
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+
+    // How long receiving `received` bytes should take
+    fn due(&self, received: u64) -> Duration {
+        Duration::from_secs_f64(received as f64 / self.rate as f64)
+    }
+}
+
+// Fails a download that stays below the low speed limit for the low
+// speed time, the way curl does
+struct LowSpeedCheck {
+    limit: u64,
+    time: Duration,
+    since: Instant,
+    received: u64,
+}
+
+impl LowSpeedCheck {
+    fn new(options: &DownloadOptions) -> Self {
+        LowSpeedCheck {
+            limit: u64::from(options.low_speed_limit),
+            time: options.low_speed_time,
+            since: Instant::now(),
+            received: 0,
+        }
+    }
+
+    fn received(&mut self, len: usize) -> Result<()> {
+        self.received += len as u64;
+        let elapsed = self.since.elapsed();
+        if self.limit == 0 || elapsed < self.time {
+            return Ok(());
+        }
+        if (self.received as f64) < self.limit as f64 * elapsed.as_secs_f64() {
+            return Err(ErrorKind::TooSlow.into());
+        }
+        self.since = Instant::now();
+        self.received = 0;
+        Ok(())
     }
 }
 
 pub fn download_to_path_with_backend(
-    backend: Backend,
+    backend: &dyn Backend,
     url: &Url,
     path: &Path,
     resume_from_partial: bool,
-    callback: Option<&dyn Fn(Event<'_>) -> Result<()>>,
+    callback: Option<Callback<'_>>,
 ) -> Result<()> {
-    use std::cell::RefCell;
-    use std::fs::OpenOptions;
-    use std::io::{Read, Seek, SeekFrom, Write};
+    let options = DownloadOptions {
+        retry: RetryPolicy::never(),
+        ..DownloadOptions::default()
+    };
+    download_to_path_with_options(backend, url, path, resume_from_partial, &options, callback)
+}
 
-    || -> Result<()> {
-        let (file, resume_from) = if resume_from_partial {
-            let possible_partial = OpenOptions::new().read(true).open(&path);
+/// Like `download_to_path_with_backend`, but the download is made as
+/// `options` say. One that fails in a way `options.retry` considers
+/// worth retrying is attempted again after a backoff, resuming from
+/// what is already in `path`. The callback sees each byte of the file
+/// once however many attempts it takes, unless the file changes on the
+/// server in between and the download starts again.
+///
+/// While `path` holds part of the file, the validator that came with it
+/// is kept next to it, at `validator_path(path)`. A resumed download
+/// only continues from `path` when the file still matches that.
+pub fn download_to_path_with_options(
+    backend: &dyn Backend,
+    url: &Url,
+    path: &Path,
+    resume_from_partial: bool,
+    options: &DownloadOptions,
+    callback: Option<Callback<'_>>,
+) -> Result<()> {
+    use std::cell::Cell;
 
-            let downloaded_so_far = if let Ok(mut partial) = possible_partial {
-                if let Some(cb) = callback {
-                    cb(Event::ResumingPartialDownload)?;
-
-                    let mut buf = vec![0; 32768];
-                    let mut downloaded_so_far = 0;
-                    loop {
-                        let n = partial.read(&mut buf)?;
-                        downloaded_so_far += n as u64;
-                        if n == 0 {
-                            break;
-                        }
-                        cb(Event::DownloadDataReceived(&buf[..n]))?;
-                    }
+    let retry = &options.retry;
+
+    let resume_from = match callback {
+        _ if !resume_from_partial => 0,
+        Some(cb) => read_partial(path, cb)?,
+        None => std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+    };
 
-                    downloaded_so_far
-                } else {
-                    let file_info = partial.metadata()?;
-                    file_info.len()
+    // How much of the file has been written and passed to the callback
+    let received = Cell::new(resume_from);
+    let callback_failed = Cell::new(false);
+    let mut attempt = 1;
+    loop {
+        let result = download_to_file(backend, url, path, received.get(), options, &|event| {
+            if let Some(cb) = callback {
+                if let Err(e) = cb(event) {
+                    callback_failed.set(true);
+                    return Err(e);
                 }
-            } else {
-                0
-            };
+            }
+            match event {
+                Event::DownloadDataReceived(data) => {
+                    received.set(received.get() + data.len() as u64);
+                }
+                Event::DownloadRestarted => received.set(0),
+                _ => {}
+            }
+            Ok(())
+        });
 
-            let mut possible_partial = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&path)
-                .chain_err(|| "error opening file for download")?;
+        match result {
+            Err(ref e)
+                if attempt < retry.max_attempts
+                    && !callback_failed.get()
+                    && (retry.is_retryable)(e) =>
+            {
+                attempt += 1;
+                let delay = retry.backoff(attempt);
+                if let Some(cb) = callback {
+                    cb(Event::RetryingDownload(attempt, delay))?;
+                }
+                thread::sleep(delay);
+            }
+            Ok(()) => {
+                // The file is complete, so there's nothing left to resume
+                let _ = fs::remove_file(validator_path(path));
+                return Ok(());
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Where the validator of a partial download to `path` is kept.
+pub fn validator_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".validator");
+    path.with_file_name(name)
+}
 
-            possible_partial.seek(SeekFrom::End(0))?;
+// The validators that came with a download. An ETag is preferred, but
+// weak ones can't be used to resume downloads.
+#[derive(Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    saved: bool,
+}
 
-            (possible_partial, downloaded_so_far)
-        } else {
-            (
-                OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(&path)
-                    .chain_err(|| "error creating file for download")?,
-                0,
-            )
-        };
+impl Validators {
+    fn best(&self) -> Option<&str> {
+        match self.etag {
+            Some(ref etag) if !etag.starts_with("W/") => Some(etag),
+            _ => self.last_modified.as_ref().map(|date| &date[..]),
+        }
+    }
 
-        let file = RefCell::new(file);
+    // Keeps the validator next to the partial download in `path`, once
+    // the download has started
+    fn save(&mut self, path: &Path) -> Result<()> {
+        if self.saved {
+            return Ok(());
+        }
+        self.saved = true;
+        if let Some(validator) = self.best() {
+            fs::write(validator_path(path), validator)
+                .chain_err(|| ErrorKind::SavingFile("unable to save the download's validator"))?;
+        }
+        Ok(())
+    }
+}
 
-        download_with_backend(backend, url, resume_from, &|event| {
-            if let Event::DownloadDataReceived(data) = event {
-                file.borrow_mut()
-                    .write_all(data)
-                    .chain_err(|| "unable to write download to disk")?;
-            }
-            match callback {
-                Some(cb) => cb(event),
-                None => Ok(()),
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// Passes what was already downloaded to `path` to the callback, as if
+// it was being downloaded again, returning its length
+fn read_partial(path: &Path, callback: &dyn Fn(Event<'_>) -> Result<()>) -> Result<u64> {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut partial = match File::open(path) {
+        Ok(partial) => partial,
+        Err(_) => return Ok(0),
+    };
+    callback(Event::ResumingPartialDownload)?;
+
+    let mut buf = vec![0; 32768];
+    let mut downloaded_so_far = 0;
+    loop {
+        let n = partial.read(&mut buf)?;
+        downloaded_so_far += n as u64;
+        if n == 0 {
+            break;
+        }
+        callback(Event::DownloadDataReceived(&buf[..n]))?;
+    }
+
+    Ok(downloaded_so_far)
+}
+
+// Downloads `url` into `path`, keeping the first `resume_from` bytes
+// already there and requesting only the rest
+fn download_to_file(
+    backend: &dyn Backend,
+    url: &Url,
+    path: &Path,
+    resume_from: u64,
+    options: &DownloadOptions,
+    callback: &dyn Fn(Event<'_>) -> Result<()>,
+) -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(&path)
+        .chain_err(|| ErrorKind::SavingFile("error opening file for download"))?;
+    // Anything past `resume_from` may not have reached the callback
+    file.set_len(resume_from)
+        .chain_err(|| ErrorKind::SavingFile("error opening file for download"))?;
+    file.seek(SeekFrom::End(0))?;
+
+    let file = RefCell::new(file);
+
+    // A validator is only any use with the part of the file it came with
+    let if_range = if resume_from > 0 {
+        fs::read_to_string(validator_path(path)).ok()
+    } else {
+        remove_if_exists(&validator_path(path))
+            .chain_err(|| ErrorKind::SavingFile("unable to remove the download's validator"))?;
+        None
+    };
+    let validators = RefCell::new(Validators::default());
+
+    download_with_backend(
+        backend,
+        url,
+        resume_from,
+        if_range.as_deref(),
+        options,
+        &|event| {
+            match event {
+                Event::ETagReceived(etag) => validators.borrow_mut().etag = Some(etag.to_owned()),
+                Event::LastModifiedReceived(date) => {
+                    validators.borrow_mut().last_modified = Some(date.to_owned())
+                }
+                Event::DownloadRestarted => {
+                    let mut file = file.borrow_mut();
+                    file.set_len(0).chain_err(|| {
+                        ErrorKind::SavingFile("unable to discard the partial download")
+                    })?;
+                    file.seek(SeekFrom::Start(0))?;
+                    remove_if_exists(&validator_path(path)).chain_err(|| {
+                        ErrorKind::SavingFile("unable to remove the download's validator")
+                    })?;
+                }
+                Event::DownloadDataReceived(data) => {
+                    validators.borrow_mut().save(path)?;
+                    file.borrow_mut()
+                        .write_all(data)
+                        .chain_err(|| ErrorKind::SavingFile("unable to write download to disk"))?;
+                }
+                _ => {}
             }
-        })?;
+            callback(event)
+        },
+    )?;
 
-        file.borrow_mut()
-            .sync_data()
-            .chain_err(|| "unable to sync download to disk")?;
+    file.borrow_mut()
+        .sync_data()
+        .chain_err(|| ErrorKind::SavingFile("unable to sync download to disk"))?;
 
-        Ok(())
-    }()
-    .map_err(|e| {
-        // TODO is there any point clearing up here? What kind of errors will leave us with an unusable partial?
-        e
-    })
+    Ok(())
+}
+
+/// When, and how often, a failed download is attempted again.
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    /// The most times a download is attempted, counting the first
+    pub max_attempts: u32,
+    /// How long to wait before the first retry. The wait doubles for
+    /// each retry after that, up to `max_backoff`.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Whether to wait a random part of the backoff, so that clients
+    /// that failed together don't all retry together
+    pub jitter: bool,
+    /// Whether a download that failed with an error is worth retrying
+    pub is_retryable: fn(&Error) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            jitter: true,
+            is_retryable: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt at each download.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// How long to wait before making attempt number `attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(2).min(31);
+        let backoff = self
+            .backoff
+            .checked_mul(1 << doublings)
+            .map_or(self.max_backoff, |b| b.min(self.max_backoff));
+        if self.jitter {
+            backoff / 2 + (backoff / 2).mul_f64(random_fraction())
+        } else {
+            backoff
+        }
+    }
+}
+
+/// Whether a download that failed with `e` might succeed if attempted
+/// again: the connection failed or the server had an error, rather than
+/// the file not being there or the download not fitting on disk.
+pub fn is_transient(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::HttpStatus(code) => *code >= 500 || *code == 408 || *code == 429,
+        ErrorKind::FileNotFound
+        | ErrorKind::InvalidOptions
+        | ErrorKind::BackendUnavailable(_)
+        | ErrorKind::SavingFile(_)
+        | ErrorKind::Io(_) => false,
+        _ => true,
+    }
+}
+
+// A number in [0, 1) that is different each time, without needing a
+// random number generator
+fn random_fraction() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
 }
 
 /// Download via libcurl; encrypt with the native (or OpenSSl) TLS
 /// stack via libcurl
 #[cfg(feature = "curl-backend")]
 pub mod curl {
-    use super::Event;
+    use super::{DownloadOptions, Event};
     use crate::errors::*;
-    use curl::easy::Easy;
-    use std::cell::RefCell;
+    use crate::options::read_pem_certificates;
+    use curl::easy::{Easy, List};
+    use std::cell::{Cell, RefCell};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
     use std::str;
-    use std::time::Duration;
     use url::Url;
 
     pub fn download(
         url: &Url,
         resume_from: u64,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
         callback: &dyn Fn(Event<'_>) -> Result<()>,
     ) -> Result<()> {
         // Fetch either a cached libcurl handle (which will preserve open
@@ -144,6 +599,8 @@ pub mod curl {
         thread_local!(static EASY: RefCell<Easy> = RefCell::new(Easy::new()));
         EASY.with(|handle| {
             let mut handle = handle.borrow_mut();
+            // Clear the options of the last download, keeping its connections
+            handle.reset();
 
             handle
                 .url(&url.to_string())
@@ -153,55 +610,63 @@ pub mod curl {
                 .chain_err(|| "failed to set follow redirects")?;
 
             if resume_from > 0 {
+                // Unlike `resume_from`, a range lets the server send the
+                // whole file when it doesn't match `if_range`
                 handle
-                    .resume_from(resume_from)
+                    .range(&format!("{}-", resume_from))
                     .chain_err(|| "setting the range header for download resumption")?;
-            } else {
-                // an error here indicates that the range header isn't supported by underlying curl,
-                // so there's nothing to "clear" - safe to ignore this error.
-                let _ = handle.resume_from(0);
+                if let Some(validator) = if_range {
+                    let mut headers = List::new();
+                    headers
+                        .append(&format!("If-Range: {}", validator))
+                        .chain_err(|| "setting the if-range header")?;
+                    handle
+                        .http_headers(headers)
+                        .chain_err(|| "setting the if-range header")?;
+                }
             }
 
-            // Take at most 30s to connect
-            handle
-                .connect_timeout(Duration::new(30, 0))
-                .chain_err(|| "failed to set connect timeout")?;
+            // curl reads the bundle when it connects, so it is kept
+            // until the download is over
+            let _ca_bundle = configure(&mut handle, options)?;
 
             {
                 let cberr = RefCell::new(None);
+                let response = RefCell::new(Response::default());
+                let started = Cell::new(false);
+                // The headers are only reported once the body starts, as
+                // until then they may be those of a redirect or a proxy
+                let start = || -> Result<()> {
+                    if !started.replace(true) {
+                        response.borrow().report(resume_from, callback)?;
+                    }
+                    Ok(())
+                };
                 let mut transfer = handle.transfer();
 
                 // Data callback for libcurl which is called with data that's
                 // downloaded. We just feed it into our hasher and also write it out
                 // to disk.
                 transfer
-                    .write_function(|data| match callback(Event::DownloadDataReceived(data)) {
-                        Ok(()) => Ok(data.len()),
-                        Err(e) => {
-                            *cberr.borrow_mut() = Some(e);
-                            Ok(0)
+                    .write_function(|data| {
+                        // The body of an error isn't part of the file
+                        if !response.borrow().is_success() {
+                            return Ok(data.len());
+                        }
+                        match start().and_then(|_| callback(Event::DownloadDataReceived(data))) {
+                            Ok(()) => Ok(data.len()),
+                            Err(e) => {
+                                *cberr.borrow_mut() = Some(e);
+                                Ok(0)
+                            }
                         }
                     })
                     .chain_err(|| "failed to set write")?;
 
-                // Listen for headers and parse out a `Content-Length` (case-insensitive) if it
-                // comes so we know how much we're downloading.
                 transfer
                     .header_function(|header| {
-                        if let Ok(data) = str::from_utf8(header) {
-                            let prefix = "content-length: ";
-                            if data.to_ascii_lowercase().starts_with(prefix) {
-                                if let Ok(s) = data[prefix.len()..].trim().parse::<u64>() {
-                                    let msg = Event::DownloadContentLengthReceived(s + resume_from);
-                                    match callback(msg) {
-                                        Ok(()) => (),
-                                        Err(e) => {
-                                            *cberr.borrow_mut() = Some(e);
-                                            return false;
-                                        }
-                                    }
-                                }
-                            }
+                        if let Ok(header) = str::from_utf8(header) {
+                            response.borrow_mut().header(header);
                         }
                         true
                     })
@@ -224,6 +689,9 @@ pub mod curl {
                         }
                     }
                 })?;
+                if response.borrow().is_success() {
+                    start()?;
+                }
             }
 
             // If we didn't get a 20x or 0 ("OK" for files) then return an error
@@ -240,29 +708,230 @@ pub mod curl {
             Ok(())
         })
     }
+
+    // What the headers say about the response
+    #[derive(Default)]
+    struct Response {
+        status: u32,
+        content_length: Option<u64>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    }
+
+    impl Response {
+        fn header(&mut self, header: &str) {
+            let header = header.trim_end();
+            if header.starts_with("HTTP/") {
+                // The start of another response, after a redirect
+                *self = Response {
+                    status: header
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|code| code.parse().ok())
+                        .unwrap_or(0),
+                    ..Response::default()
+                };
+                return;
+            }
+
+            let mut parts = header.splitn(2, ':');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+                _ => return,
+            };
+            match &name[..] {
+                "content-length" => self.content_length = value.parse().ok(),
+                "etag" => self.etag = Some(value.to_owned()),
+                "last-modified" => self.last_modified = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        // Whether the response has the file, which it always does for
+        // protocols other than HTTP
+        fn is_success(&self) -> bool {
+            self.status == 0 || (200..300).contains(&self.status)
+        }
+
+        fn report(
+            &self,
+            resume_from: u64,
+            callback: &dyn Fn(Event<'_>) -> Result<()>,
+        ) -> Result<()> {
+            // The server sent the whole file rather than the range
+            let restarted = resume_from > 0 && self.status == 200;
+            if restarted {
+                callback(Event::DownloadRestarted)?;
+            }
+            if let Some(ref etag) = self.etag {
+                callback(Event::ETagReceived(etag))?;
+            }
+            if let Some(ref date) = self.last_modified {
+                callback(Event::LastModifiedReceived(date))?;
+            }
+            if let Some(len) = self.content_length {
+                let len = if restarted { len } else { len + resume_from };
+                callback(Event::DownloadContentLengthReceived(len))?;
+            }
+            Ok(())
+        }
+    }
+
+    fn configure(handle: &mut Easy, options: &DownloadOptions) -> Result<Option<CaBundle>> {
+        if let Some(ref proxy) = options.proxy {
+            handle
+                .proxy(proxy.as_str())
+                .chain_err(|| "failed to set proxy")?;
+        }
+        if !options.no_proxy.is_empty() {
+            handle
+                .noproxy(&options.no_proxy.join(","))
+                .chain_err(|| "failed to set hosts to bypass the proxy")?;
+        }
+        let ca_bundle = if options.ca_certs.is_empty() {
+            None
+        } else {
+            let bundle = CaBundle::new(&options.ca_certs)?;
+            handle
+                .cainfo(bundle.path())
+                .chain_err(|| "failed to set certificate authorities")?;
+            Some(bundle)
+        };
+        if let Some(ref cert) = options.client_cert {
+            handle
+                .ssl_cert(cert)
+                .chain_err(|| "failed to set client certificate")?;
+            handle
+                .ssl_cert_type("P12")
+                .chain_err(|| "failed to set client certificate")?;
+            if let Some(ref password) = options.client_cert_password {
+                handle
+                    .key_password(password)
+                    .chain_err(|| "failed to set client certificate")?;
+            }
+        }
+
+        handle
+            .connect_timeout(options.connect_timeout)
+            .chain_err(|| "failed to set connect timeout")?;
+        if options.low_speed_limit > 0 {
+            handle
+                .low_speed_limit(options.low_speed_limit)
+                .chain_err(|| "failed to set low speed limit")?;
+            handle
+                .low_speed_time(options.low_speed_time)
+                .chain_err(|| "failed to set low speed time")?;
+        }
+        if let Some(rate) = options.max_rate {
+            handle
+                .max_recv_speed(rate)
+                .chain_err(|| "failed to set the download rate limit")?;
+        }
+        Ok(ca_bundle)
+    }
+
+    // curl trusts the certificates in a single file, so the extra ones
+    // are added to a copy of the system's. The copy is made in a new
+    // directory only this process can write to, and removed with it.
+    struct CaBundle {
+        dir: PathBuf,
+    }
+
+    impl CaBundle {
+        #[cfg(all(unix, not(target_os = "macos")))]
+        fn new(ca_certs: &[PathBuf]) -> Result<Self> {
+            use std::fs::{DirBuilder, OpenOptions};
+            use std::io::{ErrorKind as IoErrorKind, Write};
+            use std::os::unix::fs::DirBuilderExt;
+
+            let mut bundle = openssl_probe::probe()
+                .cert_file
+                .and_then(|path| fs::read_to_string(path).ok())
+                .unwrap_or_default();
+            for path in ca_certs {
+                for cert in read_pem_certificates(path)? {
+                    bundle += "\n";
+                    bundle += &cert;
+                }
+            }
+            bundle += "\n";
+
+            // Creating the directory fails if anything is already there,
+            // so nothing another user put in the shared temporary
+            // directory is ever trusted
+            let mut attempt = 0;
+            let dir = loop {
+                let dir = env::temp_dir().join(format!(
+                    "download-ca-{}-{:x}",
+                    process::id(),
+                    (super::random_fraction() * (1u64 << 53) as f64) as u64
+                ));
+                match DirBuilder::new().mode(0o700).create(&dir) {
+                    Ok(()) => break dir,
+                    Err(ref e) if e.kind() == IoErrorKind::AlreadyExists && attempt < 10 => {
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        return Err(e)
+                            .chain_err(|| "could not write the certificate authority bundle")
+                    }
+                }
+            };
+            let bundle_file = CaBundle { dir };
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(bundle_file.path())
+                .and_then(|mut file| file.write_all(bundle.as_bytes()))
+                .chain_err(|| "could not write the certificate authority bundle")?;
+            Ok(bundle_file)
+        }
+
+        // Elsewhere curl doesn't use a bundle of the system's
+        // certificates, so giving it one would stop it trusting them
+        #[cfg(not(all(unix, not(target_os = "macos"))))]
+        fn new(_ca_certs: &[PathBuf]) -> Result<Self> {
+            Err(Error::from(
+                "the curl backend can't trust extra certificate authorities on this platform, \
+                 use the reqwest backend instead",
+            ))
+            .chain_err(|| ErrorKind::InvalidOptions)
+        }
+
+        fn path(&self) -> PathBuf {
+            self.dir.join("ca-bundle.pem")
+        }
+    }
+
+    impl Drop for CaBundle {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
 }
 
 #[cfg(feature = "reqwest-backend")]
 pub mod reqwest_be {
-    use super::Event;
+    use super::{DownloadOptions, Event};
     use crate::errors::*;
+    use crate::options::read_pem_certificates;
     use lazy_static::lazy_static;
-    use reqwest::{header, Client, Proxy, Response};
+    use reqwest::{header, Certificate, Client, Identity, Proxy, Response, StatusCode};
+    use std::fs;
     use std::io;
-    use std::time::Duration;
+    use std::sync::Mutex;
     use url::Url;
 
     pub fn download(
         url: &Url,
         resume_from: u64,
+        if_range: Option<&str>,
+        options: &DownloadOptions,
         callback: &dyn Fn(Event<'_>) -> Result<()>,
     ) -> Result<()> {
-        // Short-circuit reqwest for the "file:" URL scheme
-        if download_from_file_url(url, resume_from, callback)? {
-            return Ok(());
-        }
-
-        let mut res = request(url, resume_from).chain_err(|| "failed to make network request")?;
+        let client = client(options)?;
+        let mut res = request(&client, url, resume_from, if_range)
+            .chain_err(|| "failed to make network request")?;
 
         if !res.status().is_success() {
             let code: u16 = res.status().into();
@@ -272,9 +941,25 @@ pub mod reqwest_be {
         let buffer_size = 0x10000;
         let mut buffer = vec![0u8; buffer_size];
 
+        // The server sent the whole file rather than the range
+        let restarted = resume_from > 0 && res.status() == StatusCode::OK;
+        if restarted {
+            callback(Event::DownloadRestarted)?;
+        }
+        if let Some(etag) = res.headers().get(header::ETAG) {
+            if let Ok(etag) = etag.to_str() {
+                callback(Event::ETagReceived(etag))?;
+            }
+        }
+        if let Some(date) = res.headers().get(header::LAST_MODIFIED) {
+            if let Ok(date) = date.to_str() {
+                callback(Event::LastModifiedReceived(date))?;
+            }
+        }
         if let Some(len) = res.headers().get(header::CONTENT_LENGTH) {
             // TODO possible issues during unwrap?
-            let len = len.to_str().unwrap().parse::<u64>().unwrap() + resume_from;
+            let len = len.to_str().unwrap().parse::<u64>().unwrap();
+            let len = if restarted { len } else { len + resume_from };
             callback(Event::DownloadContentLengthReceived(len))?;
         }
 
@@ -291,75 +976,131 @@ pub mod reqwest_be {
     }
 
     lazy_static! {
-        static ref CLIENT: Client = {
-            let catcher = || {
-                Client::builder()
-                    .gzip(false)
-                    .proxy(Proxy::custom(env_proxy))
-                    .timeout(Duration::from_secs(30))
-                    .build()
-            };
+        // The client of the last download, kept for its connections
+        static ref CLIENT: Mutex<Option<(DownloadOptions, Client)>> = Mutex::new(None);
+    }
 
-            // woah, an unwrap?!
-            // It's OK. This is the same as what is happening in curl.
-            //
-            // The curl::Easy::new() internally assert!s that the initialized
-            // Easy is not null. Inside reqwest, the errors here would be from
-            // the TLS library returning a null pointer as well.
-            catcher().unwrap()
-        };
+    fn client(options: &DownloadOptions) -> Result<Client> {
+        let mut cached = CLIENT.lock().unwrap();
+        if let Some((ref cached_options, ref client)) = *cached {
+            if cached_options.connects_like(options) {
+                return Ok(client.clone());
+            }
+        }
+
+        let client = build_client(options)?;
+        *cached = Some((options.clone(), client.clone()));
+        Ok(client)
     }
 
-    fn env_proxy(url: &Url) -> Option<Url> {
-        ::env_proxy::for_url(url).to_url()
+    fn build_client(options: &DownloadOptions) -> Result<Client> {
+        let proxy_options = options.clone();
+        let mut builder = Client::builder()
+            .gzip(false)
+            .proxy(Proxy::custom(move |url| proxy_for(&proxy_options, url)))
+            .connect_timeout(options.connect_timeout);
+        // reqwest has no low speed limit, but a read that waits longer
+        // than the low speed time is certainly too slow. Slower trickles
+        // are caught as the data arrives.
+        if options.low_speed_limit > 0 {
+            builder = builder.timeout(options.low_speed_time);
+        } else {
+            builder = builder.timeout(None);
+        }
+
+        for path in &options.ca_certs {
+            for pem in read_pem_certificates(path)? {
+                let cert = Certificate::from_pem(pem.as_bytes())
+                    .chain_err(|| format!("invalid certificate in '{}'", path.display()))
+                    .chain_err(|| ErrorKind::InvalidOptions)?;
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(ref path) = options.client_cert {
+            let der = fs::read(path)
+                .chain_err(|| format!("could not read client certificate '{}'", path.display()))
+                .chain_err(|| ErrorKind::InvalidOptions)?;
+            let password = options.client_cert_password.as_ref().map_or("", |p| &p[..]);
+            let identity = Identity::from_pkcs12_der(&der, password)
+                .chain_err(|| format!("invalid client certificate '{}'", path.display()))
+                .chain_err(|| ErrorKind::InvalidOptions)?;
+            builder = builder.identity(identity);
+        }
+
+        builder
+            .build()
+            .chain_err(|| "failed to create the download client")
+            .chain_err(|| ErrorKind::InvalidOptions)
     }
 
-    fn request(url: &Url, resume_from: u64) -> ::reqwest::Result<Response> {
-        let mut req = CLIENT.get(url.as_str());
+    fn proxy_for(options: &DownloadOptions, url: &Url) -> Option<Url> {
+        if options.bypasses_proxy(url.host_str().unwrap_or("")) {
+            return None;
+        }
+        match options.proxy {
+            Some(ref proxy) => Some(proxy.clone()),
+            None => ::env_proxy::for_url(url).to_url(),
+        }
+    }
+
+    fn request(
+        client: &Client,
+        url: &Url,
+        resume_from: u64,
+        if_range: Option<&str>,
+    ) -> ::reqwest::Result<Response> {
+        let mut req = client.get(url.as_str());
 
         if resume_from != 0 {
             req = req.header(header::RANGE, format!("bytes={}-", resume_from));
+            if let Some(validator) = if_range {
+                req = req.header(header::IF_RANGE, validator);
+            }
         }
 
         req.send()
     }
+}
 
-    fn download_from_file_url(
+pub mod file {
+    use super::Event;
+    use crate::errors::*;
+    use std::fs;
+    use std::io;
+    use url::Url;
+
+    pub fn download(
         url: &Url,
         resume_from: u64,
         callback: &dyn Fn(Event<'_>) -> Result<()>,
-    ) -> Result<bool> {
-        use std::fs;
-
-        // The file scheme is mostly for use by tests to mock the dist server
-        if url.scheme() == "file" {
-            let src = url
-                .to_file_path()
-                .map_err(|_| Error::from(format!("bogus file url: '{}'", url)))?;
-            if !src.is_file() {
-                // Because some of rustup's logic depends on checking
-                // the error when a downloaded file doesn't exist, make
-                // the file case return the same error value as the
-                // network case.
-                return Err(ErrorKind::FileNotFound.into());
-            }
+    ) -> Result<()> {
+        let src = url
+            .to_file_path()
+            .map_err(|_| Error::from(format!("bogus file url: '{}'", url)))?;
+        if !src.is_file() {
+            // Because some of rustup's logic depends on checking
+            // the error when a downloaded file doesn't exist, make
+            // the file case return the same error value as the
+            // network case.
+            return Err(ErrorKind::FileNotFound.into());
+        }
 
-            let mut f = fs::File::open(src).chain_err(|| "unable to open downloaded file")?;
-            io::Seek::seek(&mut f, io::SeekFrom::Start(resume_from))?;
+        let mut f = fs::File::open(src).chain_err(|| "unable to open downloaded file")?;
+        let len = f
+            .metadata()
+            .chain_err(|| "unable to open downloaded file")?
+            .len();
+        callback(Event::DownloadContentLengthReceived(len))?;
+        io::Seek::seek(&mut f, io::SeekFrom::Start(resume_from))?;
 
-            let mut buffer = vec![0u8; 0x10000];
-            loop {
-                let bytes_read = io::Read::read(&mut f, &mut buffer)
-                    .chain_err(|| "unable to read downloaded file")?;
-                if bytes_read == 0 {
-                    break;
-                }
-                callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
+        let mut buffer = vec![0u8; 0x10000];
+        loop {
+            let bytes_read = io::Read::read(&mut f, &mut buffer)
+                .chain_err(|| "unable to read downloaded file")?;
+            if bytes_read == 0 {
+                return Ok(());
             }
-
-            Ok(true)
-        } else {
-            Ok(false)
+            callback(Event::DownloadDataReceived(&buffer[0..bytes_read]))?;
         }
     }
 }
@@ -367,13 +1108,15 @@ pub mod reqwest_be {
 #[cfg(not(feature = "curl-backend"))]
 pub mod curl {
 
-    use super::Event;
+    use super::{DownloadOptions, Event};
     use crate::errors::*;
     use url::Url;
 
     pub fn download(
         _url: &Url,
         _resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &DownloadOptions,
         _callback: &Fn(Event) -> Result<()>,
     ) -> Result<()> {
         Err(ErrorKind::BackendUnavailable("curl").into())
@@ -383,13 +1126,15 @@ pub mod curl {
 #[cfg(not(feature = "reqwest-backend"))]
 pub mod reqwest_be {
 
-    use super::Event;
+    use super::{DownloadOptions, Event};
     use crate::errors::*;
     use url::Url;
 
     pub fn download(
         _url: &Url,
         _resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &DownloadOptions,
         _callback: &Fn(Event) -> Result<()>,
     ) -> Result<()> {
         Err(ErrorKind::BackendUnavailable("reqwest").into())
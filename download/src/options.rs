@@ -0,0 +1,116 @@
+use crate::errors::*;
+use crate::{RetryPolicy, Throttle};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// How downloads are made. Both backends honour every option, in the
+/// same way, except that on macOS and Windows the curl backend can't
+/// trust `ca_certs` as well as the system's certificate authorities,
+/// and fails with `InvalidOptions` instead.
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// The proxy to download through. Without one, the proxy is taken
+    /// from the `http_proxy`, `https_proxy` and `all_proxy` environment
+    /// variables.
+    pub proxy: Option<Url>,
+    /// The hosts to download from directly instead of through a proxy.
+    /// Each also covers its subdomains, and `*` covers every host.
+    pub no_proxy: Vec<String>,
+    /// PEM files of certificate authorities to trust as well as the
+    /// system's
+    pub ca_certs: Vec<PathBuf>,
+    /// A PKCS #12 archive of the certificate and private key to show
+    /// servers that ask for one
+    pub client_cert: Option<PathBuf>,
+    /// The password the `client_cert` archive is encrypted with
+    pub client_cert_password: Option<String>,
+    pub connect_timeout: Duration,
+    /// A download fails when it stays slower than `low_speed_limit`
+    /// bytes per second for `low_speed_time`. A limit of 0 lets
+    /// downloads be as slow as they like.
+    pub low_speed_limit: u32,
+    pub low_speed_time: Duration,
+    /// The most bytes per second to download
+    pub max_rate: Option<u64>,
+    /// A rate limit shared with other downloads, which applies as well
+    /// as `max_rate`
+    pub shared_throttle: Option<Arc<Throttle>>,
+    pub retry: RetryPolicy,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            proxy: None,
+            no_proxy: vec![],
+            ca_certs: vec![],
+            client_cert: None,
+            client_cert_password: None,
+            connect_timeout: Duration::from_secs(30),
+            low_speed_limit: 1,
+            low_speed_time: Duration::from_secs(30),
+            max_rate: None,
+            shared_throttle: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Whether `host` is downloaded from without a proxy.
+    pub fn bypasses_proxy(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.no_proxy.iter().any(|pattern| {
+            let pattern = pattern.trim().trim_start_matches('.').to_ascii_lowercase();
+            pattern == "*"
+                || host == pattern
+                || host
+                    .strip_suffix(&pattern)
+                    .map_or(false, |sub| sub.ends_with('.'))
+        })
+    }
+
+    // Whether downloads with `other` can share a client with these,
+    // which are only told apart by how they connect
+    pub(crate) fn connects_like(&self, other: &DownloadOptions) -> bool {
+        self.proxy == other.proxy
+            && self.no_proxy == other.no_proxy
+            && self.ca_certs == other.ca_certs
+            && self.client_cert == other.client_cert
+            && self.client_cert_password == other.client_cert_password
+            && self.connect_timeout == other.connect_timeout
+            && self.low_speed_limit == other.low_speed_limit
+            && self.low_speed_time == other.low_speed_time
+    }
+}
+
+// The PEM-encoded certificates in the file at `path`
+pub(crate) fn read_pem_certificates(path: &Path) -> Result<Vec<String>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let contents = fs::read_to_string(path)
+        .chain_err(|| format!("could not read certificates from '{}'", path.display()))
+        .chain_err(|| ErrorKind::InvalidOptions)?;
+    let mut certs = vec![];
+    let mut rest = &contents[..];
+    while let Some(start) = rest.find(BEGIN) {
+        let end = match rest[start..].find(END) {
+            Some(end) => start + end + END.len(),
+            None => break,
+        };
+        certs.push(rest[start..end].to_owned());
+        rest = &rest[end..];
+    }
+    if certs.is_empty() {
+        return Err(Error::from(format!(
+            "no certificates in '{}'",
+            path.display()
+        )))
+        .chain_err(|| ErrorKind::InvalidOptions);
+    }
+    Ok(certs)
+}
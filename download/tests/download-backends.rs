@@ -0,0 +1,111 @@
+use std::sync::Mutex;
+
+use url::Url;
+
+use download::*;
+
+// Only some of the support code is for backends that don't need a server
+#[allow(dead_code)]
+mod support;
+use crate::support::{file_contents, tmp_dir, write_file};
+
+// Serves `contents` for any URL, remembering what it was asked for
+struct MemoryBackend {
+    contents: &'static [u8],
+    requests: Mutex<Vec<(String, u64)>>,
+}
+
+impl MemoryBackend {
+    fn new(contents: &'static [u8]) -> Self {
+        MemoryBackend {
+            contents,
+            requests: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn download(
+        &self,
+        url: &Url,
+        resume_from: u64,
+        _if_range: Option<&str>,
+        _options: &DownloadOptions,
+        callback: Callback<'_>,
+    ) -> Result<()> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((url.to_string(), resume_from));
+        callback(Event::DownloadContentLengthReceived(
+            self.contents.len() as u64
+        ))?;
+        callback(Event::DownloadDataReceived(
+            &self.contents[resume_from as usize..],
+        ))
+    }
+}
+
+#[test]
+fn downloads_go_through_a_custom_backend() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+
+    let backend = MemoryBackend::new(b"12345");
+    let from_url = "artifacts://store/file".parse().unwrap();
+    download_to_path_with_backend(&backend, &from_url, &target_path, true, None)
+        .expect("Test download failed");
+
+    assert_eq!(
+        backend.requests.into_inner().unwrap(),
+        vec![("artifacts://store/file".to_owned(), 3)]
+    );
+    assert_eq!(file_contents(&target_path), "12345");
+}
+
+#[test]
+fn file_urls_are_copied_without_the_backend() {
+    let tmpdir = tmp_dir();
+    let from_path = tmpdir.path().join("download-source");
+    write_file(&from_path, "xxx45");
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+
+    let backend = MemoryBackend::new(b"wrong");
+    let from_url = Url::from_file_path(&from_path).unwrap();
+    let content_length = Mutex::new(None);
+    download_to_path_with_backend(
+        &backend,
+        &from_url,
+        &target_path,
+        true,
+        Some(&|msg| {
+            if let Event::DownloadContentLengthReceived(len) = msg {
+                *content_length.lock().unwrap() = Some(len);
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert!(backend.requests.into_inner().unwrap().is_empty());
+    assert_eq!(content_length.into_inner().unwrap(), Some(5));
+    assert_eq!(file_contents(&target_path), "12345");
+}
+
+#[test]
+fn missing_files_are_not_found() {
+    let tmpdir = tmp_dir();
+    let from_url = Url::from_file_path(tmpdir.path().join("missing")).unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let err = download_to_path_with_backend(&FileBackend, &from_url, &target_path, false, None)
+        .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::FileNotFound));
+}
@@ -1,14 +1,21 @@
 #![cfg(feature = "curl-backend")]
 
+use std::env;
+use std::fs;
+use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use url::Url;
 
 use download::*;
 
 mod support;
-use crate::support::{file_contents, serve_file, tmp_dir, write_file};
+use crate::support::{
+    file_contents, serve_file, serve_file_dropping_connections, serve_file_with_etag, tmp_dir,
+    write_file,
+};
 
 #[test]
 fn partially_downloaded_file_gets_resumed_from_byte_offset() {
@@ -20,7 +27,7 @@ fn partially_downloaded_file_gets_resumed_from_byte_offset() {
     write_file(&target_path, "123");
 
     let from_url = Url::from_file_path(&from_path).unwrap();
-    download_to_path_with_backend(Backend::Curl, &from_url, &target_path, true, None)
+    download_to_path_with_backend(&CurlBackend, &from_url, &target_path, true, None)
         .expect("Test download failed");
 
     assert_eq!(file_contents(&target_path), "12345");
@@ -41,7 +48,7 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
     let received_in_callback = Mutex::new(Vec::new());
 
     download_to_path_with_backend(
-        Backend::Curl,
+        &CurlBackend,
         &from_url,
         &target_path,
         true,
@@ -61,6 +68,9 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
                         received_in_callback.lock().unwrap().push(b.clone());
                     }
                 }
+                Event::RetryingDownload(..) => panic!("the download was retried"),
+                Event::DownloadRestarted => panic!("the download was restarted"),
+                Event::ETagReceived(_) | Event::LastModifiedReceived(_) => {}
             }
 
             Ok(())
@@ -74,3 +84,290 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
     assert_eq!(observed_bytes, vec![b'1', b'2', b'3', b'4', b'5']);
     assert_eq!(file_contents(&target_path), "12345");
 }
+
+#[test]
+fn dropped_connections_are_retried_from_where_they_stopped() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let addr = serve_file_dropping_connections(b"0123456789".to_vec(), 2);
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let retries = Mutex::new(Vec::new());
+    let received_in_callback = Mutex::new(Vec::new());
+    let options = DownloadOptions {
+        retry: RetryPolicy {
+            backoff: Duration::from_millis(1),
+            jitter: false,
+            ..RetryPolicy::default()
+        },
+        ..DownloadOptions::default()
+    };
+
+    download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &options,
+        Some(&|msg| {
+            match msg {
+                Event::RetryingDownload(attempt, delay) => {
+                    retries.lock().unwrap().push((attempt, delay));
+                }
+                Event::DownloadDataReceived(data) => {
+                    received_in_callback.lock().unwrap().extend_from_slice(data);
+                }
+                _ => {}
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(
+        retries.into_inner().unwrap(),
+        vec![(2, Duration::from_millis(1)), (3, Duration::from_millis(2))]
+    );
+    assert_eq!(received_in_callback.into_inner().unwrap(), b"0123456789");
+    assert_eq!(file_contents(&target_path), "0123456789");
+}
+
+#[test]
+fn downloads_are_kept_to_the_maximum_rate() {
+    let tmpdir = tmp_dir();
+    let addr = serve_file(vec![b'x'; 256 * 1024]);
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let start = Instant::now();
+    download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            max_rate: Some(128 * 1024),
+            retry: RetryPolicy::never(),
+            ..DownloadOptions::default()
+        },
+        None,
+    )
+    .expect("Test download failed");
+
+    // Two seconds at the maximum rate, give or take how curl measures
+    // it, as the first part may arrive before curl slows it down
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(file_contents(&target_path).len(), 256 * 1024);
+}
+
+#[test]
+fn downloads_go_through_the_proxy() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    // The proxy serves the file whatever is asked for, so the download
+    // only succeeds through it
+    let addr = serve_file(b"proxied".to_vec());
+    let from_url = "http://rustup.invalid/file".parse().unwrap();
+
+    download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            proxy: Some(format!("http://{}", addr).parse().unwrap()),
+            retry: RetryPolicy::never(),
+            ..DownloadOptions::default()
+        },
+        None,
+    )
+    .expect("Test download failed");
+
+    assert_eq!(file_contents(&target_path), "proxied");
+}
+
+#[test]
+fn unreadable_ca_certificates_are_not_retried() {
+    let tmpdir = tmp_dir();
+    let ca_path = tmpdir.path().join("ca.pem");
+    write_file(&ca_path, "not a certificate");
+    let addr = serve_file(b"xxx".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let err = download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            ca_certs: vec![ca_path],
+            ..DownloadOptions::default()
+        },
+        Some(&|msg| match msg {
+            Event::RetryingDownload(..) => panic!("the download was retried"),
+            _ => Ok(()),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::InvalidOptions));
+}
+
+#[test]
+fn files_that_cannot_be_saved_are_not_retried() {
+    let tmpdir = tmp_dir();
+    let addr = serve_file(b"xxx".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("missing").join("downloaded");
+
+    let err = download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions::default(),
+        Some(&|msg| match msg {
+            Event::RetryingDownload(..) => panic!("the download was retried"),
+            _ => Ok(()),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::SavingFile(_)));
+}
+
+#[test]
+#[cfg(all(unix, not(target_os = "macos")))]
+fn ca_bundles_are_private_to_the_download() {
+    let tmpdir = tmp_dir();
+    let ca_path = tmpdir.path().join("ca.pem");
+    write_file(
+        &ca_path,
+        "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n",
+    );
+    let addr = serve_file(b"12345".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    download_to_path_with_options(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            ca_certs: vec![ca_path],
+            ..DownloadOptions::default()
+        },
+        None,
+    )
+    .expect("Test download failed");
+
+    assert_eq!(file_contents(&target_path), "12345");
+    let bundle_prefix = format!("download-ca-{}-", process::id());
+    let left_behind = fs::read_dir(env::temp_dir())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&bundle_prefix)
+        });
+    assert!(!left_behind);
+}
+
+#[test]
+fn validators_are_kept_next_to_partial_downloads() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let addr = serve_file_with_etag(b"12345".to_vec(), Some("\"v1\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    download_to_path_with_backend(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        false,
+        Some(&|msg| match msg {
+            Event::DownloadDataReceived(_) => Err("interrupted".into()),
+            _ => Ok(()),
+        }),
+    )
+    .expect_err("the download wasn't interrupted");
+
+    assert_eq!(file_contents(&validator_path(&target_path)), "\"v1\"");
+}
+
+#[test]
+fn partial_downloads_are_resumed_when_the_file_is_unchanged() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&validator_path(&target_path), "\"v1\"");
+
+    let addr = serve_file_with_etag(b"xxx45".to_vec(), Some("\"v1\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    download_to_path_with_backend(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        true,
+        Some(&|msg| match msg {
+            Event::DownloadRestarted => panic!("the download was restarted"),
+            _ => Ok(()),
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(file_contents(&target_path), "12345");
+    // The download is complete, so there's nothing left to resume
+    assert!(!validator_path(&target_path).exists());
+}
+
+#[test]
+fn partial_downloads_are_discarded_when_the_file_changed() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&validator_path(&target_path), "\"old\"");
+
+    let addr = serve_file_with_etag(b"abcde".to_vec(), Some("\"new\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let restarted = AtomicBool::new(false);
+    let callback_len = Mutex::new(None);
+    let received_in_callback = Mutex::new(Vec::new());
+    download_to_path_with_backend(
+        &CurlBackend,
+        &from_url,
+        &target_path,
+        true,
+        Some(&|msg| {
+            match msg {
+                Event::DownloadRestarted => {
+                    restarted.store(true, Ordering::SeqCst);
+                    received_in_callback.lock().unwrap().clear();
+                }
+                Event::DownloadContentLengthReceived(len) => {
+                    *callback_len.lock().unwrap() = Some(len);
+                }
+                Event::DownloadDataReceived(data) => {
+                    received_in_callback.lock().unwrap().extend_from_slice(data);
+                }
+                _ => {}
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert!(restarted.into_inner());
+    assert_eq!(callback_len.into_inner().unwrap(), Some(5));
+    assert_eq!(received_in_callback.into_inner().unwrap(), b"abcde");
+    assert_eq!(file_contents(&target_path), "abcde");
+}
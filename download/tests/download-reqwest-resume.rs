@@ -1,14 +1,19 @@
 #![cfg(feature = "reqwest-backend")]
 
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use url::Url;
 
 use download::*;
 
 mod support;
-use crate::support::{file_contents, serve_file, tmp_dir, write_file};
+use crate::support::{
+    file_contents, serve_file, serve_file_dropping_connections, serve_file_with_etag, tmp_dir,
+    write_file,
+};
 
 #[test]
 fn resume_partial_from_file_url() {
@@ -20,7 +25,7 @@ fn resume_partial_from_file_url() {
     write_file(&target_path, "123");
 
     let from_url = Url::from_file_path(&from_path).unwrap();
-    download_to_path_with_backend(Backend::Reqwest, &from_url, &target_path, true, None)
+    download_to_path_with_backend(&ReqwestBackend, &from_url, &target_path, true, None)
         .expect("Test download failed");
 
     assert_eq!(file_contents(&target_path), "12345");
@@ -41,7 +46,7 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
     let received_in_callback = Mutex::new(Vec::new());
 
     download_to_path_with_backend(
-        Backend::Reqwest,
+        &ReqwestBackend,
         &from_url,
         &target_path,
         true,
@@ -61,6 +66,9 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
                         received_in_callback.lock().unwrap().push(b.clone());
                     }
                 }
+                Event::RetryingDownload(..) => panic!("the download was retried"),
+                Event::DownloadRestarted => panic!("the download was restarted"),
+                Event::ETagReceived(_) | Event::LastModifiedReceived(_) => {}
             }
 
             Ok(())
@@ -74,3 +82,357 @@ fn callback_gets_all_data_as_if_the_download_happened_all_at_once() {
     assert_eq!(observed_bytes, vec![b'1', b'2', b'3', b'4', b'5']);
     assert_eq!(file_contents(&target_path), "12345");
 }
+
+#[test]
+fn dropped_connections_are_retried_from_where_they_stopped() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let addr = serve_file_dropping_connections(b"0123456789".to_vec(), 2);
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let retries = Mutex::new(Vec::new());
+    let received_in_callback = Mutex::new(Vec::new());
+    let options = DownloadOptions {
+        retry: RetryPolicy {
+            backoff: Duration::from_millis(1),
+            jitter: false,
+            ..RetryPolicy::default()
+        },
+        ..DownloadOptions::default()
+    };
+
+    download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &options,
+        Some(&|msg| {
+            match msg {
+                Event::RetryingDownload(attempt, delay) => {
+                    retries.lock().unwrap().push((attempt, delay));
+                }
+                Event::DownloadDataReceived(data) => {
+                    received_in_callback.lock().unwrap().extend_from_slice(data);
+                }
+                _ => {}
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(
+        retries.into_inner().unwrap(),
+        vec![(2, Duration::from_millis(1)), (3, Duration::from_millis(2))]
+    );
+    assert_eq!(received_in_callback.into_inner().unwrap(), b"0123456789");
+    assert_eq!(file_contents(&target_path), "0123456789");
+}
+
+#[test]
+fn missing_files_are_not_retried() {
+    let tmpdir = tmp_dir();
+    let from_url = Url::from_file_path(tmpdir.path().join("missing")).unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let err = download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions::default(),
+        Some(&|msg| match msg {
+            Event::RetryingDownload(..) => panic!("the download was retried"),
+            _ => Ok(()),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::FileNotFound));
+}
+
+#[test]
+fn files_that_cannot_be_saved_are_not_retried() {
+    let tmpdir = tmp_dir();
+    let addr = serve_file(b"xxx".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("missing").join("downloaded");
+
+    let err = download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions::default(),
+        Some(&|msg| match msg {
+            Event::RetryingDownload(..) => panic!("the download was retried"),
+            _ => Ok(()),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::SavingFile(_)));
+}
+
+#[test]
+fn downloads_are_kept_to_the_maximum_rate() {
+    let tmpdir = tmp_dir();
+    let from_path = tmpdir.path().join("download-source");
+    write_file(&from_path, &"x".repeat(64 * 1024));
+    let from_url = Url::from_file_path(&from_path).unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let start = Instant::now();
+    download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            max_rate: Some(128 * 1024),
+            retry: RetryPolicy::never(),
+            ..DownloadOptions::default()
+        },
+        None,
+    )
+    .expect("Test download failed");
+
+    // The whole file arrives at once, so there is a wait of about half
+    // a second before the download finishes
+    assert!(start.elapsed() >= Duration::from_millis(250));
+    assert_eq!(file_contents(&target_path).len(), 64 * 1024);
+}
+
+#[test]
+fn downloads_sharing_a_throttle_keep_to_its_rate_together() {
+    let tmpdir = tmp_dir();
+    let from_path = tmpdir.path().join("download-source");
+    write_file(&from_path, &"x".repeat(64 * 1024));
+    let from_url = Url::from_file_path(&from_path).unwrap();
+    let options = DownloadOptions {
+        shared_throttle: Some(Arc::new(Throttle::new(256 * 1024))),
+        retry: RetryPolicy::never(),
+        ..DownloadOptions::default()
+    };
+
+    let start = Instant::now();
+    let downloads: Vec<_> = (0..2)
+        .map(|i| {
+            let from_url = from_url.clone();
+            let target_path = tmpdir.path().join(format!("downloaded-{}", i));
+            let options = options.clone();
+            thread::spawn(move || {
+                download_to_path_with_options(
+                    &ReqwestBackend,
+                    &from_url,
+                    &target_path,
+                    false,
+                    &options,
+                    None,
+                )
+            })
+        })
+        .collect();
+    for download in downloads {
+        download.join().unwrap().expect("Test download failed");
+    }
+
+    // Either download alone would take about a quarter of a second
+    assert!(start.elapsed() >= Duration::from_millis(375));
+}
+
+#[test]
+fn downloads_after_an_idle_throttle_keep_to_its_rate() {
+    let tmpdir = tmp_dir();
+    let from_path = tmpdir.path().join("download-source");
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&from_path, &"x".repeat(128 * 1024));
+    let from_url = Url::from_file_path(&from_path).unwrap();
+    let options = DownloadOptions {
+        shared_throttle: Some(Arc::new(Throttle::new(128 * 1024))),
+        retry: RetryPolicy::never(),
+        ..DownloadOptions::default()
+    };
+
+    // Nothing is downloaded for longer than the download takes, as
+    // when waiting for another process
+    thread::sleep(Duration::from_secs(2));
+
+    let start = Instant::now();
+    download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &options,
+        None,
+    )
+    .expect("Test download failed");
+
+    // The download alone takes about a second
+    assert!(start.elapsed() >= Duration::from_millis(750));
+}
+
+#[test]
+fn downloads_go_through_the_proxy() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    // The proxy serves the file whatever is asked for, so the download
+    // only succeeds through it
+    let addr = serve_file(b"proxied".to_vec());
+    let from_url = "http://rustup.invalid/file".parse().unwrap();
+
+    download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            proxy: Some(format!("http://{}", addr).parse().unwrap()),
+            retry: RetryPolicy::never(),
+            ..DownloadOptions::default()
+        },
+        None,
+    )
+    .expect("Test download failed");
+
+    assert_eq!(file_contents(&target_path), "proxied");
+}
+
+#[test]
+fn unreadable_ca_certificates_are_not_retried() {
+    let tmpdir = tmp_dir();
+    let ca_path = tmpdir.path().join("ca.pem");
+    write_file(&ca_path, "not a certificate");
+    let addr = serve_file(b"xxx".to_vec());
+    let from_url = format!("http://{}", addr).parse().unwrap();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let err = download_to_path_with_options(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        &DownloadOptions {
+            ca_certs: vec![ca_path],
+            ..DownloadOptions::default()
+        },
+        Some(&|msg| match msg {
+            Event::RetryingDownload(..) => panic!("the download was retried"),
+            _ => Ok(()),
+        }),
+    )
+    .unwrap_err();
+
+    assert!(matches!(err.kind(), ErrorKind::InvalidOptions));
+}
+
+#[test]
+fn no_proxy_hosts_cover_their_subdomains() {
+    let options = DownloadOptions {
+        no_proxy: vec!["example.com".to_owned(), ".internal".to_owned()],
+        ..DownloadOptions::default()
+    };
+
+    assert!(options.bypasses_proxy("example.com"));
+    assert!(options.bypasses_proxy("static.example.com"));
+    assert!(options.bypasses_proxy("dist.INTERNAL."));
+    assert!(!options.bypasses_proxy("badexample.com"));
+    assert!(!options.bypasses_proxy("static.rust-lang.org"));
+}
+
+#[test]
+fn validators_are_kept_next_to_partial_downloads() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+
+    let addr = serve_file_with_etag(b"12345".to_vec(), Some("\"v1\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    download_to_path_with_backend(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        false,
+        Some(&|msg| match msg {
+            Event::DownloadDataReceived(_) => Err("interrupted".into()),
+            _ => Ok(()),
+        }),
+    )
+    .expect_err("the download wasn't interrupted");
+
+    assert_eq!(file_contents(&validator_path(&target_path)), "\"v1\"");
+}
+
+#[test]
+fn partial_downloads_are_resumed_when_the_file_is_unchanged() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&validator_path(&target_path), "\"v1\"");
+
+    let addr = serve_file_with_etag(b"xxx45".to_vec(), Some("\"v1\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    download_to_path_with_backend(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        true,
+        Some(&|msg| match msg {
+            Event::DownloadRestarted => panic!("the download was restarted"),
+            _ => Ok(()),
+        }),
+    )
+    .expect("Test download failed");
+
+    assert_eq!(file_contents(&target_path), "12345");
+    // The download is complete, so there's nothing left to resume
+    assert!(!validator_path(&target_path).exists());
+}
+
+#[test]
+fn partial_downloads_are_discarded_when_the_file_changed() {
+    let tmpdir = tmp_dir();
+    let target_path = tmpdir.path().join("downloaded");
+    write_file(&target_path, "123");
+    write_file(&validator_path(&target_path), "\"old\"");
+
+    let addr = serve_file_with_etag(b"abcde".to_vec(), Some("\"new\""));
+    let from_url = format!("http://{}", addr).parse().unwrap();
+
+    let restarted = AtomicBool::new(false);
+    let callback_len = Mutex::new(None);
+    let received_in_callback = Mutex::new(Vec::new());
+    download_to_path_with_backend(
+        &ReqwestBackend,
+        &from_url,
+        &target_path,
+        true,
+        Some(&|msg| {
+            match msg {
+                Event::DownloadRestarted => {
+                    restarted.store(true, Ordering::SeqCst);
+                    received_in_callback.lock().unwrap().clear();
+                }
+                Event::DownloadContentLengthReceived(len) => {
+                    *callback_len.lock().unwrap() = Some(len);
+                }
+                Event::DownloadDataReceived(data) => {
+                    received_in_callback.lock().unwrap().extend_from_slice(data);
+                }
+                _ => {}
+            }
+            Ok(())
+        }),
+    )
+    .expect("Test download failed");
+
+    assert!(restarted.into_inner());
+    assert_eq!(callback_len.into_inner().unwrap(), Some(5));
+    assert_eq!(received_in_callback.into_inner().unwrap(), b"abcde");
+    assert_eq!(file_contents(&target_path), "abcde");
+}
@@ -33,6 +33,11 @@ pub fn write_file(path: &Path, contents: &str) {
 }
 
 pub fn serve_file(contents: Vec<u8>) -> SocketAddr {
+    serve_file_with_etag(contents, None)
+}
+
+/// Serves `contents` with `etag` as its entity tag, honouring `If-Range`.
+pub fn serve_file_with_etag(contents: Vec<u8>, etag: Option<&'static str>) -> SocketAddr {
     use futures::Future;
     use std::thread;
 
@@ -43,12 +48,13 @@ pub fn serve_file(contents: Vec<u8>) -> SocketAddr {
         // XXX: multiple clone below
         fn serve(
             contents: Vec<u8>,
+            etag: Option<&'static str>,
         ) -> impl Fn(hyper::Request<hyper::Body>) -> hyper::Response<hyper::Body> {
-            move |req| serve_contents(req, contents.clone())
+            move |req| serve_contents(req, contents.clone(), etag)
         }
 
         let server = hyper::server::Server::bind(&addr)
-            .serve(move || hyper::service::service_fn_ok(serve(contents.clone())));
+            .serve(move || hyper::service::service_fn_ok(serve(contents.clone(), etag)));
         let addr = server.local_addr();
         addr_tx.send(addr).unwrap();
         hyper::rt::run(server.map_err(|e| panic!(e)));
@@ -59,9 +65,15 @@ pub fn serve_file(contents: Vec<u8>) -> SocketAddr {
 fn serve_contents(
     req: hyper::Request<hyper::Body>,
     contents: Vec<u8>,
+    etag: Option<&'static str>,
 ) -> hyper::Response<hyper::Body> {
+    // A range is only for the version of the file given in `If-Range`
+    let range = match req.headers().get(hyper::header::IF_RANGE) {
+        Some(if_range) if Some(if_range.to_str().unwrap()) != etag => None,
+        _ => req.headers().get(hyper::header::RANGE),
+    };
     let mut range_header = None;
-    let (status, body) = if let Some(range) = req.headers().get(hyper::header::RANGE) {
+    let (status, body) = if let Some(range) = range {
         // extract range "bytes={start}-"
         let range = range.to_str().expect("unexpected Range header");
         assert!(range.starts_with("bytes="));
@@ -89,5 +101,66 @@ fn serve_contents(
         res.headers_mut()
             .insert(hyper::header::CONTENT_RANGE, range.parse().unwrap());
     }
+    if let Some(etag) = etag {
+        res.headers_mut()
+            .insert(hyper::header::ETAG, etag.parse().unwrap());
+    }
     res
 }
+
+/// Serves `contents` over HTTP, but the first `drops` responses close
+/// the connection half way through the body.
+pub fn serve_file_dropping_connections(contents: Vec<u8>, drops: usize) -> SocketAddr {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Shutdown, TcpListener};
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for (i, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let mut start = 0;
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let lower = line.to_ascii_lowercase();
+                if lower.starts_with("range: bytes=") {
+                    start = lower["range: bytes=".len()..]
+                        .trim_end_matches('-')
+                        .parse()
+                        .expect("unexpected Range header");
+                }
+            }
+
+            let body = &contents[start..];
+            let status = if start > 0 {
+                format!(
+                    "206 Partial Content\r\nContent-Range: bytes {}-{len}/{len}",
+                    start,
+                    len = contents.len()
+                )
+            } else {
+                "200 OK".to_owned()
+            };
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            if i < drops {
+                stream.write_all(&body[..body.len() / 2]).unwrap();
+            } else {
+                stream.write_all(body).unwrap();
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    });
+    addr
+}
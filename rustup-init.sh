@@ -29,6 +29,7 @@ OPTIONS:
         --default-host <default-host>              Choose a default host triple
         --default-toolchain <default-toolchain>    Choose a default toolchain to install
         --default-toolchain none                   Do not install any toolchains
+        --profile [minimal|default|complete]       Choose a profile
 EOF
 }
 
@@ -164,6 +164,10 @@ fn show_channel_updates(
                 banner = "unchanged";
                 color = None;
             }
+            Ok(UpdateStatus::Pinned) => {
+                banner = "pinned";
+                color = Some(term2::color::BRIGHT_YELLOW);
+            }
             Err(_) => {
                 banner = "update failed";
                 color = Some(term2::color::BRIGHT_RED);
@@ -217,17 +221,17 @@ pub fn update_all_channels(cfg: &Cfg, do_self_update: bool, force_update: bool)
     };
 
     if do_self_update {
-        self_update(show_channel_updates)
+        self_update(cfg, show_channel_updates)
     } else {
         show_channel_updates()
     }
 }
 
-pub fn self_update<F>(before_restart: F) -> Result<()>
+pub fn self_update<F>(cfg: &Cfg, before_restart: F) -> Result<()>
 where
     F: FnOnce() -> Result<()>,
 {
-    let setup_path = self_update::prepare_update()?;
+    let setup_path = self_update::prepare_update(cfg)?;
 
     before_restart()?;
 
@@ -372,18 +376,20 @@ pub fn list_toolchains(cfg: &Cfg) -> Result<()> {
 
     if toolchains.is_empty() {
         println!("no installed toolchains");
-    } else if let Ok(Some(def_toolchain)) = cfg.find_default() {
+    } else {
+        let default = cfg.find_default().ok().and_then(|t| t);
+        let pinned = cfg.pinned_toolchains()?;
         for toolchain in toolchains {
-            let if_default = if def_toolchain.name() == &*toolchain {
-                " (default)"
+            let if_default = match default {
+                Some(ref def_toolchain) if def_toolchain.name() == &*toolchain => " (default)",
+                _ => "",
+            };
+            let if_pinned = if pinned.contains(&toolchain) {
+                " (pinned)"
             } else {
                 ""
             };
-            println!("{}{}", &toolchain, if_default);
-        }
-    } else {
-        for toolchain in toolchains {
-            println!("{}", &toolchain);
+            println!("{}{}{}", &toolchain, if_default, if_pinned);
         }
     }
     Ok(())
@@ -35,6 +35,18 @@ pub struct DownloadTracker {
     /// If we have displayed progress, this is the number of characters we
     /// rendered, so we can erase it cleanly.
     displayed_charcount: Option<usize>,
+    /// Components being downloaded at the same time, whose progress is
+    /// combined into a single display.
+    components: Vec<ComponentProgress>,
+    /// The limit on the download rate, if the download is throttled.
+    max_rate: Option<u64>,
+}
+
+/// Progress of one of several components being downloaded at once.
+struct ComponentProgress {
+    name: String,
+    content_len: Option<u64>,
+    finished: bool,
 }
 
 impl DownloadTracker {
@@ -49,6 +61,8 @@ impl DownloadTracker {
             last_sec: None,
             term: term::stdout(),
             displayed_charcount: None,
+            components: Vec::new(),
+            max_rate: None,
         }
     }
 
@@ -65,14 +79,63 @@ impl DownloadTracker {
                 }
                 true
             }
+            Notification::Install(In::Utils(Un::DownloadRestarted(_))) => {
+                // The progress of several components can't be told apart
+                if self.components.is_empty() {
+                    self.total_downloaded = 0;
+                }
+                // Still reported
+                false
+            }
+            Notification::Install(In::Utils(Un::DownloadRateLimited(rate))) => {
+                self.max_rate = Some(rate);
+                // Still reported in verbose mode
+                false
+            }
             Notification::Install(In::Utils(Un::DownloadFinished)) => {
                 self.download_finished();
                 true
             }
+            Notification::Install(In::ComponentDownloadContentLength(component, content_len)) => {
+                self.component(component).content_len = Some(content_len);
+                self.content_len = self
+                    .components
+                    .iter()
+                    .map(|c| c.content_len)
+                    .sum::<Option<u64>>();
+                true
+            }
+            Notification::Install(In::ComponentDownloadDataReceived(component, len)) => {
+                self.component(component);
+                if tty::stdout_isatty() && self.term.is_some() {
+                    self.data_received(len);
+                }
+                true
+            }
+            Notification::Install(In::ComponentDownloadFinished(component)) => {
+                self.component(component).finished = true;
+                if self.components.iter().all(|c| c.finished) {
+                    self.download_finished();
+                }
+                true
+            }
             _ => false,
         }
     }
 
+    /// Returns the progress of the named component, starting to track it
+    /// if it wasn't already.
+    fn component(&mut self, name: &str) -> &mut ComponentProgress {
+        if let Some(i) = self.components.iter().position(|c| c.name == name) {
+            return &mut self.components[i];
+        }
+        self.components.push(ComponentProgress {
+            name: name.to_owned(),
+            content_len: None,
+            finished: false,
+        });
+        self.components.last_mut().unwrap()
+    }
     /// Notifies self that Content-Length information has been received.
     pub fn content_length_received(&mut self, content_len: u64) {
         self.content_len = Some(content_len);
@@ -121,6 +184,8 @@ impl DownloadTracker {
         self.seconds_elapsed = 0;
         self.last_sec = None;
         self.displayed_charcount = None;
+        self.components.clear();
+        self.max_rate = None;
     }
     /// Display the tracked download information to the terminal.
     fn display(&mut self) {
@@ -148,7 +213,7 @@ impl DownloadTracker {
             let _ = self.term.as_mut().unwrap().carriage_return();
         }
 
-        let output = match self.content_len {
+        let mut output = match self.content_len {
             Some(content_len) => {
                 let content_len = content_len as f64;
                 let percent = (self.total_downloaded as f64 / content_len) * 100.;
@@ -162,6 +227,16 @@ impl DownloadTracker {
             }
             None => format!("Total: {} Speed: {}/s", total_h, speed_h),
         };
+        if !self.components.is_empty() {
+            let finished = self.components.iter().filter(|c| c.finished).count();
+            output += &format!(" ({}/{} components)", finished, self.components.len());
+        }
+        if let Some(rate) = self.max_rate {
+            output += &format!(
+                " (limited to {}/s)",
+                HumanReadable(rate as f64).to_string().trim()
+            );
+        }
 
         let _ = write!(self.term.as_mut().unwrap(), "{}", output);
         // Since stdout is typically line-buffered and we don't print a newline, we manually flush.
@@ -171,7 +246,7 @@ impl DownloadTracker {
 }
 
 /// Human readable representation of data size in bytes
-struct HumanReadable(f64);
+pub struct HumanReadable(pub f64);
 
 impl fmt::Display for HumanReadable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -30,6 +30,10 @@ error_chain! {
             description("toolchain is not installed")
             display("toolchain '{}' is not installed", t)
         }
+        ToolchainDamaged(t: String) {
+            description("toolchain has missing or modified files")
+            display("toolchain '{}' has missing or modified files, which `rustup toolchain repair {}` can fix", t, t)
+        }
         InvalidToolchainName(t: String) {
             description("invalid toolchain name")
             display("invalid toolchain name: '{}'", t)
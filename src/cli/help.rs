@@ -34,6 +34,14 @@ pub static UPDATE_HELP: &str = r"DISCUSSION:
     If given a toolchain argument then `update` updates that
     toolchain, the same as `rustup toolchain install`.";
 
+pub static CHECK_HELP: &str = r"DISCUSSION:
+    Checks each of the installed toolchains that track a release
+    channel, and rustup itself, for updates without installing them.
+    Only the channel manifests are downloaded.
+
+    Exits with status 100 if there are updates available, so that
+    scripts can act on them.";
+
 pub static INSTALL_HELP: &str = r"DISCUSSION:
     Installs a specific rust toolchain.
 
@@ -99,6 +107,122 @@ pub static TOOLCHAIN_LINK_HELP: &str = r"DISCUSSION:
     If you now compile a crate in the current directory, the custom
     toolchain 'latest-stage1' will be used.";
 
+pub static TOOLCHAIN_DIFF_HELP: &str = r"DISCUSSION:
+    Compares the manifest a toolchain was installed from with the
+    latest one for its channel, or with the manifest archived on the
+    date given with `--date`. Every package is listed with its version
+    in both, noting those unavailable for the toolchain's target, followed
+    by the components that were added or removed.
+
+        $ rustup toolchain diff nightly
+        $ rustup toolchain diff nightly --date 2019-05-01
+
+    With `--json` the comparison is printed as a JSON object instead.";
+
+pub static TOOLCHAIN_ROLLBACK_HELP: &str = r"DISCUSSION:
+    Each time an update replaces a toolchain's installation, rustup
+    keeps the manifest and component list it replaced, up to the last
+    three. Rolling back reinstalls the most recent of them, with exactly
+    the components it had, into the same toolchain directory. Packages
+    still in the download cache are not downloaded again.
+
+        $ rustup toolchain rollback nightly
+
+    Rolling back again goes one installation further back. The next
+    `rustup update` installs the latest release again.";
+
+pub static TOOLCHAIN_VERIFY_HELP: &str = r"DISCUSSION:
+    rustup records the hash of every file it installs. `rustup toolchain
+    verify` checks a toolchain against them, listing for each component
+    the files that are missing, that were modified, or that were added
+    to its directories since.
+
+        $ rustup toolchain verify nightly
+
+    `rustup toolchain repair` downloads and reinstalls only the
+    components with such files, from the same release as before.
+
+        $ rustup toolchain repair nightly
+
+    Components installed by older versions of rustup have no recorded
+    hashes, so only their missing files are found.";
+
+pub static TOOLCHAIN_PIN_HELP: &str = r"DISCUSSION:
+    A plain `rustup update` updates every toolchain that tracks a
+    release channel. Pinning one leaves it as it is, for example to
+    keep a nightly that a particular crate still builds with:
+
+        $ rustup toolchain pin nightly
+
+    `rustup update nightly` still updates a pinned toolchain, since it
+    is named. `rustup toolchain list` marks the pinned toolchains, and
+    `rustup toolchain unpin` lets `rustup update` update them again.";
+
+pub static CACHE_HELP: &str = r"DISCUSSION:
+    rustup downloads component packages to `~/.rustup/downloads`, named
+    by their hash, and by default removes them once they are installed.
+    Downloads that fail or are interrupted leave `.partial` files
+    behind, which `rustup cache prune` removes along with any package
+    that no installed toolchain, or rollback of one, can reuse. Pass
+    `--older-than <days>` to keep files used more recently.
+
+        $ rustup cache size
+        $ rustup cache prune --older-than 7
+
+    `rustup cache clear` removes everything in the cache. Neither
+    removes files that another rustup is downloading or installing.
+
+    To keep packages around for reinstalls and rollbacks, set how many
+    days they are kept after installing them:
+
+        $ rustup set download-retention 30
+
+    Downloads not used for that long are then removed after each
+    install, and `rustup cache prune` keeps the others by default.
+
+    `rustup cache stats` also shows the space used by the store of
+    files shared between toolchains, turned on with `rustup set
+    shared-store on`, and how much it saves.";
+
+pub static SET_DIST_SERVERS_HELP: &str = r"DISCUSSION:
+    Toolchains are downloaded from the first server that has them. When
+    a server doesn't have a manifest or package, can't be reached, or
+    sends a file with the wrong checksum, rustup moves on to the next
+    one. This lets a local mirror that is missing some files be listed
+    ahead of the official server:
+
+        $ rustup set dist-servers http://mirror.lan/rust https://static.rust-lang.org
+
+    `rustup set dist-servers default` goes back to the official server
+    alone. The `RUSTUP_DIST_SERVER` environment variable, which can hold
+    a comma-separated list of servers, takes precedence over this
+    setting.";
+
+pub static SET_DOWNLOAD_RETRIES_HELP: &str = r"DISCUSSION:
+    A download that fails because the connection dropped, timed out, or
+    the server had an error is tried again, resuming from where it
+    stopped. Each retry waits about twice as long as the one before.
+    By default a download is retried 3 times; to stop retrying:
+
+        $ rustup set download-retries 0
+
+    `rustup set download-retries default` goes back to the default. The
+    `RUSTUP_DOWNLOAD_RETRIES` environment variable takes precedence over
+    this setting.";
+
+pub static SET_MAX_DOWNLOAD_RATE_HELP: &str = r"DISCUSSION:
+    Limits how fast toolchains are downloaded, so that installing one
+    doesn't take all of a shared connection. The rate is in bytes per
+    second, and may be given in kibibytes, mebibytes or gibibytes:
+
+        $ rustup set max-download-rate 2M
+
+    When several components are downloaded at once they share the
+    limit. The progress bar shows the limit while it applies. `rustup
+    set max-download-rate none` removes the limit, and the
+    `RUSTUP_MAX_DOWNLOAD_RATE` environment variable takes precedence
+    over this setting.";
+
 pub static OVERRIDE_HELP: &str = r"DISCUSSION:
     Overrides configure rustup to use a specific toolchain when
     running in a specific directory.
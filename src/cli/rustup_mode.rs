@@ -1,13 +1,17 @@
 use crate::common;
+use crate::download_tracker::HumanReadable;
 use crate::errors::*;
 use crate::help::*;
 use crate::self_update;
 use crate::term2;
 use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
-use rustup::dist::dist::{PartialTargetTriple, PartialToolchainDesc, TargetTriple};
+use rustup::dist::cache::CachedDownload;
+use rustup::dist::dist::{PartialTargetTriple, PartialToolchainDesc, Profile, TargetTriple};
 use rustup::dist::manifest::Component;
+use rustup::dist::signatures::SignatureCheck;
 use rustup::utils::utils::{self, ExitCode};
-use rustup::{command, Cfg, Toolchain};
+use rustup::{command, parse_download_rate, Cfg, Toolchain, UpdateStatus};
+use serde_json::json;
 use std::error::Error;
 use std::fmt;
 use std::io::Write;
@@ -15,6 +19,7 @@ use std::iter;
 use std::path::{Path, PathBuf};
 use std::process::{self, Command};
 use std::str::FromStr;
+use std::time::Duration;
 
 fn handle_epipe(res: Result<()>) -> Result<()> {
     match res {
@@ -30,7 +35,7 @@ pub fn main() -> Result<()> {
 
     let matches = cli().get_matches();
     let verbose = matches.is_present("verbose");
-    let cfg = &common::set_globals(verbose)?;
+    let cfg = &mut common::set_globals(verbose)?;
 
     if maybe_upgrade_data(cfg, &matches)? {
         return Ok(());
@@ -45,6 +50,7 @@ pub fn main() -> Result<()> {
         },
         ("install", Some(m)) => update(cfg, m)?,
         ("update", Some(m)) => update(cfg, m)?,
+        ("check", Some(m)) => check_updates(cfg, m)?,
         ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
         ("default", Some(m)) => default_(cfg, m)?,
         ("toolchain", Some(c)) => match c.subcommand() {
@@ -52,6 +58,12 @@ pub fn main() -> Result<()> {
             ("list", Some(_)) => handle_epipe(common::list_toolchains(cfg))?,
             ("link", Some(m)) => toolchain_link(cfg, m)?,
             ("uninstall", Some(m)) => toolchain_remove(cfg, m)?,
+            ("diff", Some(m)) => handle_epipe(toolchain_diff(cfg, m))?,
+            ("rollback", Some(m)) => toolchain_rollback(cfg, m)?,
+            ("verify", Some(m)) => handle_epipe(toolchain_verify(cfg, m))?,
+            ("repair", Some(m)) => toolchain_repair(cfg, m)?,
+            ("pin", Some(m)) => toolchain_pin(cfg, m, true)?,
+            ("unpin", Some(m)) => toolchain_pin(cfg, m, false)?,
             (_, _) => unreachable!(),
         },
         ("target", Some(c)) => match c.subcommand() {
@@ -76,13 +88,28 @@ pub fn main() -> Result<()> {
         ("which", Some(m)) => which(cfg, m)?,
         ("doc", Some(m)) => doc(cfg, m)?,
         ("man", Some(m)) => man(cfg, m)?,
+        ("cache", Some(c)) => match c.subcommand() {
+            ("list", Some(_)) => handle_epipe(cache_list(cfg))?,
+            ("size", Some(_)) => cache_size(cfg)?,
+            ("stats", Some(_)) => cache_stats(cfg)?,
+            ("prune", Some(m)) => cache_prune(cfg, m)?,
+            ("clear", Some(_)) => cache_clear(cfg)?,
+            (_, _) => unreachable!(),
+        },
         ("self", Some(c)) => match c.subcommand() {
-            ("update", Some(_)) => self_update::update()?,
+            ("update", Some(_)) => self_update::update(cfg)?,
             ("uninstall", Some(m)) => self_uninstall(m)?,
             (_, _) => unreachable!(),
         },
         ("set", Some(c)) => match c.subcommand() {
-            ("default-host", Some(m)) => set_default_host_triple(&cfg, m)?,
+            ("default-host", Some(m)) => set_default_host_triple(cfg, m)?,
+            ("signature-check", Some(m)) => set_signature_check(cfg, m)?,
+            ("profile", Some(m)) => set_profile(cfg, m)?,
+            ("shared-store", Some(m)) => set_shared_store(cfg, m)?,
+            ("download-retention", Some(m)) => set_download_retention(cfg, m)?,
+            ("dist-servers", Some(m)) => set_dist_servers(cfg, m)?,
+            ("download-retries", Some(m)) => set_download_retries(cfg, m)?,
+            ("max-download-rate", Some(m)) => set_max_download_rate(cfg, m)?,
             (_, _) => unreachable!(),
         },
         ("completions", Some(c)) => {
@@ -138,6 +165,42 @@ pub fn cli() -> App<'static, 'static> {
                         .required(true)
                         .multiple(true),
                 )
+                .arg(
+                    Arg::with_name("profile")
+                        .help("The set of components to install on a new toolchain")
+                        .long("profile")
+                        .takes_value(true)
+                        .possible_values(Profile::names()),
+                )
+                .arg(
+                    Arg::with_name("with-components")
+                        .help("Install the latest nightly that has all of these components")
+                        .long("with-components")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .require_delimiter(true),
+                )
+                .arg(
+                    Arg::with_name("components")
+                        .help("Add specific components on installation")
+                        .long("component")
+                        .short("c")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .require_delimiter(true),
+                )
+                .arg(
+                    Arg::with_name("targets")
+                        .help("Add specific targets on installation")
+                        .long("target")
+                        .short("t")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .require_delimiter(true),
+                )
                 .arg(
                     Arg::with_name("no-self-update")
                         .help("Don't perform self-update when running the `rustup install` command")
@@ -185,6 +248,17 @@ pub fn cli() -> App<'static, 'static> {
                         .takes_value(false),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check for updates to Rust toolchains and rustup")
+                .after_help(CHECK_HELP)
+                .arg(
+                    Arg::with_name("no-self-update")
+                        .help("Don't check for a rustup update")
+                        .long("no-self-update")
+                        .takes_value(false),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("default")
                 .about("Set the default toolchain")
@@ -213,6 +287,42 @@ pub fn cli() -> App<'static, 'static> {
                                 .required(true)
                                 .multiple(true),
                         )
+                        .arg(
+                            Arg::with_name("profile")
+                                .help("The set of components to install on a new toolchain")
+                                .long("profile")
+                                .takes_value(true)
+                                .possible_values(Profile::names()),
+                        )
+                        .arg(
+                            Arg::with_name("with-components")
+                                .help("Install the latest nightly that has all of these components")
+                                .long("with-components")
+                                .takes_value(true)
+                                .multiple(true)
+                                .use_delimiter(true)
+                                .require_delimiter(true),
+                        )
+                        .arg(
+                            Arg::with_name("components")
+                                .help("Add specific components on installation")
+                                .long("component")
+                                .short("c")
+                                .takes_value(true)
+                                .multiple(true)
+                                .use_delimiter(true)
+                                .require_delimiter(true),
+                        )
+                        .arg(
+                            Arg::with_name("targets")
+                                .help("Add specific targets on installation")
+                                .long("target")
+                                .short("t")
+                                .takes_value(true)
+                                .multiple(true)
+                                .use_delimiter(true)
+                                .require_delimiter(true),
+                        )
                         .arg(
                             Arg::with_name("no-self-update")
                                 .help("Don't perform self update when running the `rustup toolchain install` command")
@@ -241,6 +351,77 @@ pub fn cli() -> App<'static, 'static> {
                                 .required(true),
                         )
                         .arg(Arg::with_name("path").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("diff")
+                        .about("Compare a toolchain's installed manifest with a newer one")
+                        .after_help(TOOLCHAIN_DIFF_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("date")
+                                .help("Compare with the manifest archived on this date (YYYY-MM-DD)")
+                                .long("date")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .help("Print the comparison as JSON")
+                                .long("json"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Reinstall the installation a toolchain had before its last update")
+                        .after_help(TOOLCHAIN_ROLLBACK_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Check a toolchain's files for missing or modified ones")
+                        .after_help(TOOLCHAIN_VERIFY_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("repair")
+                        .about("Reinstall the components of a toolchain with missing or modified files")
+                        .after_help(TOOLCHAIN_VERIFY_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("pin")
+                        .about("Keep `rustup update` from updating a toolchain unless it is named")
+                        .after_help(TOOLCHAIN_PIN_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("unpin")
+                        .about("Let `rustup update` update a pinned toolchain again")
+                        .after_help(TOOLCHAIN_PIN_HELP)
+                        .arg(
+                            Arg::with_name("toolchain")
+                                .help(TOOLCHAIN_ARG_HELP)
+                                .required(true),
+                        ),
                 ),
         )
         .subcommand(
@@ -454,6 +635,36 @@ pub fn cli() -> App<'static, 'static> {
     }
 
     app = app
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Inspect and clean up the download cache")
+                .after_help(CACHE_HELP)
+                .setting(AppSettings::VersionlessSubcommands)
+                .setting(AppSettings::DeriveDisplayOrder)
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List the cached downloads"))
+                .subcommand(
+                    SubCommand::with_name("size").about("Show the size of the download cache"),
+                )
+                .subcommand(
+                    SubCommand::with_name("stats")
+                        .about("Show the space used by downloads and shared toolchain files"),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Remove partial downloads and packages no toolchain can reuse")
+                        .arg(
+                            Arg::with_name("older-than")
+                                .help("Only remove files not modified in this many days")
+                                .long("older-than")
+                                .takes_value(true)
+                                .value_name("days"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear").about("Remove everything in the download cache"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("self")
                 .about("Modify the rustup installation")
@@ -481,6 +692,75 @@ pub fn cli() -> App<'static, 'static> {
                     SubCommand::with_name("default-host")
                         .about("The triple used to identify toolchains when not specified")
                         .arg(Arg::with_name("host_triple").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("signature-check")
+                        .about("Whether to refuse or warn about unsigned dist server downloads")
+                        .arg(
+                            Arg::with_name("signature-check")
+                                .required(true)
+                                .possible_values(SignatureCheck::names()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("profile")
+                        .about("The set of components installed on new toolchains")
+                        .arg(
+                            Arg::with_name("profile-name")
+                                .required(true)
+                                .possible_values(Profile::names()),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("shared-store")
+                        .about("Whether toolchains share identical files through hard links")
+                        .arg(
+                            Arg::with_name("enabled")
+                                .required(true)
+                                .possible_values(&["on", "off"]),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("download-retention")
+                        .about("How many days to keep downloads after installing them")
+                        .arg(
+                            Arg::with_name("days")
+                                .help("A number of days, or 'none' to remove them straight away")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("dist-servers")
+                        .about(
+                            "The servers to download toolchains from, in the order they are tried",
+                        )
+                        .after_help(SET_DIST_SERVERS_HELP)
+                        .arg(
+                            Arg::with_name("servers")
+                                .help("Server urls, or 'default' to use the default server")
+                                .required(true)
+                                .multiple(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("download-retries")
+                        .about("How many times to retry a download that failed part way")
+                        .after_help(SET_DOWNLOAD_RETRIES_HELP)
+                        .arg(
+                            Arg::with_name("retries")
+                                .help("A number of retries, or 'default'")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("max-download-rate")
+                        .about("The most bytes per second to download")
+                        .after_help(SET_MAX_DOWNLOAD_RATE_HELP)
+                        .arg(
+                            Arg::with_name("rate")
+                                .help("A rate such as 500K or 2M, or 'none' for no limit")
+                                .required(true),
+                        ),
                 ),
         );
 
@@ -632,15 +912,33 @@ fn default_(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     Ok(())
 }
 
-fn update(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+fn update(cfg: &mut Cfg, m: &ArgMatches<'_>) -> Result<()> {
     let self_update = !m.is_present("no-self-update") && !self_update::NEVER_SELF_UPDATE;
+    if let Some(p) = m.value_of("profile") {
+        let p = Profile::from_str(p)?;
+        cfg.set_profile_override(p);
+    }
+    let cfg: &Cfg = cfg;
     if let Some(names) = m.values_of("toolchain") {
         for name in names {
             update_bare_triple_check(cfg, name)?;
             let toolchain = cfg.get_toolchain(name, false)?;
 
-            let status = if !toolchain.is_custom() {
-                Some(toolchain.install_from_dist(m.is_present("force"))?)
+            let status = if let Some(search) = m.values_of("with-components") {
+                let search: Vec<_> = search.map(ToString::to_string).collect();
+                let components = install_components(&toolchain, m)?;
+                Some(toolchain.install_nightly_with_components(
+                    m.is_present("force"),
+                    &search,
+                    &components,
+                )?)
+            } else if !toolchain.is_custom() {
+                let components = install_components(&toolchain, m)?;
+                Some(toolchain.install_from_dist_with_components(
+                    m.is_present("force"),
+                    cfg.get_profile()?,
+                    &components,
+                )?)
             } else if !toolchain.exists() {
                 return Err(ErrorKind::InvalidToolchainName(toolchain.name().to_string()).into());
             } else {
@@ -653,7 +951,7 @@ fn update(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
             }
         }
         if self_update {
-            common::self_update(|| Ok(()))?;
+            common::self_update(cfg, || Ok(()))?;
         }
     } else {
         common::update_all_channels(cfg, self_update, m.is_present("force"))?;
@@ -662,6 +960,106 @@ fn update(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     Ok(())
 }
 
+// The components and targets requested with `--component` and `--target`
+// when installing `toolchain`
+fn install_components(toolchain: &Toolchain<'_>, m: &ArgMatches<'_>) -> Result<Vec<Component>> {
+    let mut components = Vec::new();
+    if let Some(names) = m.values_of("components") {
+        let target = toolchain.desc()?.target;
+        components.extend(names.map(|c| Component::new(c.to_string(), Some(target.clone()))));
+    }
+    if let Some(targets) = m.values_of("targets") {
+        components.extend(
+            targets
+                .map(|t| Component::new("rust-std".to_string(), Some(TargetTriple::from_str(t)))),
+        );
+    }
+    Ok(components)
+}
+
+// The exit status of `rustup check` when there are updates available
+const UPDATES_AVAILABLE_EXIT_CODE: i32 = 100;
+
+fn check_updates(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let mut t = term2::stdout();
+    let mut updates_available = false;
+
+    for (name, toolchain) in cfg.list_channels()? {
+        let versions = toolchain.and_then(|t| Ok((t.show_version()?, t.show_dist_version()?)));
+        let (current_version, dist_version) = match versions {
+            Ok(versions) => versions,
+            Err(e) => {
+                let _ = t.attr(term2::Attr::Bold);
+                write!(t, "{} - ", name)?;
+                let _ = t.fg(term2::color::BRIGHT_RED);
+                writeln!(t, "Cannot check for updates")?;
+                let _ = t.reset();
+                t.flush()?;
+                err!("{}", e);
+                for cause in e.iter().skip(1) {
+                    info!("caused by: {}", cause);
+                }
+                continue;
+            }
+        };
+
+        let _ = t.attr(term2::Attr::Bold);
+        write!(t, "{} - ", name)?;
+        match (current_version, dist_version) {
+            (None, None) => {
+                let _ = t.fg(term2::color::BRIGHT_RED);
+                writeln!(t, "Cannot identify installed or update versions")?;
+            }
+            (Some(cv), None) => {
+                let _ = t.fg(term2::color::BRIGHT_GREEN);
+                write!(t, "Up to date")?;
+                let _ = t.reset();
+                writeln!(t, " : {}", cv)?;
+            }
+            (cv, Some(dv)) => {
+                updates_available = true;
+                let _ = t.fg(term2::color::BRIGHT_YELLOW);
+                write!(t, "Update available")?;
+                let _ = t.reset();
+                match cv {
+                    // Only the build changed, like when a release is redone
+                    Some(ref cv) if *cv == dv => writeln!(t, " : {} (new build)", cv)?,
+                    Some(cv) => writeln!(t, " : {} -> {}", cv, dv)?,
+                    None => writeln!(t, " : (unknown) -> {}", dv)?,
+                }
+            }
+        }
+        let _ = t.reset();
+    }
+
+    if !m.is_present("no-self-update") && !self_update::NEVER_SELF_UPDATE {
+        let current_version = env!("CARGO_PKG_VERSION");
+        let available_version = self_update::get_available_rustup_version(cfg)?;
+
+        let _ = t.attr(term2::Attr::Bold);
+        write!(t, "rustup - ")?;
+        if current_version == available_version {
+            let _ = t.fg(term2::color::BRIGHT_GREEN);
+            write!(t, "Up to date")?;
+            let _ = t.reset();
+            writeln!(t, " : {}", current_version)?;
+        } else {
+            updates_available = true;
+            let _ = t.fg(term2::color::BRIGHT_YELLOW);
+            write!(t, "Update available")?;
+            let _ = t.reset();
+            writeln!(t, " : {} -> {}", current_version, available_version)?;
+        }
+    }
+
+    if updates_available {
+        t.flush()?;
+        process::exit(UPDATES_AVAILABLE_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
 fn run(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     let toolchain = m.value_of("toolchain").expect("");
     let args = m.values_of("command").unwrap();
@@ -952,6 +1350,160 @@ fn toolchain_remove(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     Ok(())
 }
 
+fn toolchain_rollback(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    toolchain.rollback()?;
+
+    println!();
+    common::show_channel_update(cfg, toolchain.name(), Ok(UpdateStatus::Updated))?;
+
+    Ok(())
+}
+
+fn toolchain_verify(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    let mut t = term2::stdout();
+    let mut damaged = false;
+
+    for (name, verification) in toolchain.verify_components()? {
+        if verification.is_intact() {
+            if verification.hashed {
+                writeln!(t, "{}: ok", name)?;
+            } else {
+                writeln!(
+                    t,
+                    "{}: ok (no file hashes, only checked for missing files)",
+                    name
+                )?;
+            }
+            continue;
+        }
+
+        damaged = true;
+        t.attr(term2::Attr::Bold)?;
+        write!(t, "{}", name)?;
+        t.reset()?;
+        writeln!(t, ": damaged")?;
+        for (kind, paths) in &[
+            ("missing", &verification.missing),
+            ("modified", &verification.modified),
+            ("extra", &verification.extra),
+        ] {
+            for path in paths.iter() {
+                writeln!(t, "    {}: {}", kind, path.display())?;
+            }
+        }
+    }
+
+    if damaged {
+        return Err(ErrorKind::ToolchainDamaged(toolchain.name().to_owned()).into());
+    }
+    Ok(())
+}
+
+fn toolchain_repair(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    let repaired = toolchain.repair_components()?;
+
+    if repaired.is_empty() {
+        info!(
+            "toolchain '{}' has no missing or modified files",
+            toolchain.name()
+        );
+    } else {
+        info!("reinstalled {}", repaired.join(", "));
+    }
+
+    Ok(())
+}
+
+fn toolchain_pin(cfg: &Cfg, m: &ArgMatches<'_>, pinned: bool) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    if pinned && !toolchain.exists() {
+        return Err(ErrorKind::ToolchainNotInstalled(toolchain.name().to_owned()).into());
+    }
+
+    cfg.set_pinned(toolchain.name(), pinned)?;
+    Ok(())
+}
+
+fn toolchain_diff(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let toolchain = cfg.get_toolchain(m.value_of("toolchain").expect(""), false)?;
+    let diff = toolchain.diff_manifest(m.value_of("date"))?;
+
+    if m.is_present("json") {
+        let packages: Vec<_> = diff
+            .packages
+            .iter()
+            .map(|p| {
+                json!({
+                    "name": p.name,
+                    "old_version": p.old_version,
+                    "new_version": p.new_version,
+                    "old_available": p.old_available,
+                    "new_available": p.new_available,
+                })
+            })
+            .collect();
+        let diff = json!({
+            "toolchain": toolchain.name(),
+            "old_date": diff.old_date,
+            "new_date": diff.new_date,
+            "packages": packages,
+            "added_components": diff.added_components,
+            "removed_components": diff.removed_components,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).expect("valid json")
+        );
+        return Ok(());
+    }
+
+    fn version(v: &Option<String>, available: bool) -> String {
+        match (v, available) {
+            (None, _) => "(none)".to_string(),
+            (Some(v), true) => v.clone(),
+            (Some(v), false) => format!("{} (unavailable)", v),
+        }
+    }
+
+    let mut t = term2::stdout();
+    let _ = t.attr(term2::Attr::Bold);
+    writeln!(
+        t,
+        "{}: {} -> {}",
+        toolchain.name(),
+        diff.old_date,
+        diff.new_date
+    )?;
+    let _ = t.reset();
+    writeln!(t)?;
+    for p in &diff.packages {
+        let old = version(&p.old_version, p.old_available);
+        let new = version(&p.new_version, p.new_available);
+        if old == new {
+            writeln!(t, "{}: {} (unchanged)", p.name, old)?;
+        } else {
+            writeln!(t, "{}: {} -> {}", p.name, old, new)?;
+        }
+    }
+    if !diff.added_components.is_empty() {
+        writeln!(t)?;
+        writeln!(t, "added components: {}", diff.added_components.join(", "))?;
+    }
+    if !diff.removed_components.is_empty() {
+        writeln!(t)?;
+        writeln!(
+            t,
+            "removed components: {}",
+            diff.removed_components.join(", ")
+        )?;
+    }
+
+    Ok(())
+}
+
 fn override_add(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     let toolchain = m.value_of("toolchain").expect("");
     let toolchain = cfg.get_toolchain(toolchain, false)?;
@@ -1094,6 +1646,141 @@ fn set_default_host_triple(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
     Ok(())
 }
 
+fn set_signature_check(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let signature_check = m.value_of("signature-check").expect("").parse()?;
+    cfg.set_signature_check(signature_check)?;
+    Ok(())
+}
+
+fn set_profile(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let profile = m.value_of("profile-name").expect("").parse()?;
+    cfg.set_profile(profile)?;
+    Ok(())
+}
+
+fn set_shared_store(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    cfg.set_shared_store(m.value_of("enabled") == Some("on"))?;
+    Ok(())
+}
+
+fn set_download_retention(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let days = match m.value_of("days").expect("") {
+        "none" => None,
+        days => Some(parse_days(days)?),
+    };
+    cfg.set_download_retention(days)?;
+    Ok(())
+}
+
+fn set_dist_servers(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let servers: Vec<_> = m.values_of("servers").expect("").collect();
+    let servers: Vec<_> = if servers == ["default"] {
+        vec![]
+    } else {
+        servers
+            .into_iter()
+            .map(|s| s.trim_end_matches('/').to_owned())
+            .collect()
+    };
+    cfg.set_dist_servers(&servers)?;
+    Ok(())
+}
+
+fn set_download_retries(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let retries = match m.value_of("retries").expect("") {
+        "default" => None,
+        retries => Some(
+            retries
+                .parse()
+                .map_err(|_| format!("'{}' is not a number of retries", retries))?,
+        ),
+    };
+    cfg.set_download_retries(retries)?;
+    Ok(())
+}
+
+fn set_max_download_rate(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let rate = match m.value_of("rate").expect("") {
+        "none" => None,
+        rate => Some(parse_download_rate(rate)?),
+    };
+    cfg.set_max_download_rate(rate)?;
+    Ok(())
+}
+
+fn parse_days(days: &str) -> Result<u32> {
+    days.parse()
+        .map_err(|_| format!("'{}' is not a number of days", days).into())
+}
+
+fn cache_list(cfg: &Cfg) -> Result<()> {
+    let used = cfg.used_download_hashes()?;
+    let mut t = term2::stdout();
+    for download in cfg.cached_downloads()? {
+        let size = HumanReadable(download.size as f64).to_string();
+        if download.is_partial() {
+            writeln!(t, "{} {} (partial)", download.name(), size.trim())?;
+        } else if used.contains(download.hash()) {
+            writeln!(t, "{} {} (in use)", download.name(), size.trim())?;
+        } else {
+            writeln!(t, "{} {}", download.name(), size.trim())?;
+        }
+    }
+    Ok(())
+}
+
+fn cache_size(cfg: &Cfg) -> Result<()> {
+    let downloads = cfg.cached_downloads()?;
+    let size: u64 = downloads.iter().map(|d| d.size).sum();
+    println!(
+        "{} in {} files",
+        HumanReadable(size as f64).to_string().trim(),
+        downloads.len()
+    );
+    Ok(())
+}
+
+fn cache_stats(cfg: &Cfg) -> Result<()> {
+    let downloads = cfg.cached_downloads()?;
+    let size: u64 = downloads.iter().map(|d| d.size).sum();
+    let store = cfg.store.stats()?;
+    let human = |size: u64| HumanReadable(size as f64).to_string().trim().to_owned();
+
+    println!("downloads: {} files, {}", downloads.len(), human(size));
+    println!("shared store: {} files, {}", store.files, human(store.size));
+    println!(
+        "linked into toolchains: {}, saving {}",
+        human(store.linked_size),
+        human(store.saved_size)
+    );
+    Ok(())
+}
+
+fn cache_prune(cfg: &Cfg, m: &ArgMatches<'_>) -> Result<()> {
+    let older_than = match m.value_of("older-than") {
+        Some(days) => Some(Duration::from_secs(
+            u64::from(parse_days(days)?) * 24 * 60 * 60,
+        )),
+        None => None,
+    };
+    show_removed_downloads(&cfg.prune_downloads(older_than)?);
+    Ok(())
+}
+
+fn cache_clear(cfg: &Cfg) -> Result<()> {
+    show_removed_downloads(&cfg.clear_downloads()?);
+    Ok(())
+}
+
+fn show_removed_downloads(removed: &[CachedDownload]) {
+    let size: u64 = removed.iter().map(|d| d.size).sum();
+    println!(
+        "removed {} files, freeing {}",
+        removed.len(),
+        HumanReadable(size as f64).to_string().trim()
+    );
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CompletionCommand {
     Rustup,
@@ -35,7 +35,7 @@ use crate::errors::*;
 use crate::term2;
 use rustup::dist::dist;
 use rustup::utils::utils;
-use rustup::{DUP_TOOLS, TOOLS};
+use rustup::{Cfg, DUP_TOOLS, TOOLS};
 use same_file::Handle;
 use std::env;
 use std::env::consts::EXE_SUFFIX;
@@ -47,6 +47,7 @@ use tempdir::TempDir;
 pub struct InstallOpts {
     pub default_host_triple: String,
     pub default_toolchain: String,
+    pub profile: String,
     pub no_modify_path: bool,
 }
 
@@ -267,7 +268,12 @@ pub fn install(no_prompt: bool, verbose: bool, mut opts: InstallOpts) -> Result<
             do_add_to_path(&get_add_path_methods())?;
         }
         utils::create_rustup_home()?;
-        maybe_install_rust(&opts.default_toolchain, &opts.default_host_triple, verbose)?;
+        maybe_install_rust(
+            &opts.default_toolchain,
+            &opts.profile,
+            &opts.default_host_triple,
+            verbose,
+        )?;
 
         if cfg!(unix) {
             let env_file = utils::cargo_home()?.join("env");
@@ -575,10 +581,12 @@ fn current_install_opts(opts: &InstallOpts) -> String {
 
 - ` `default host triple: `{}`
 - `   `default toolchain: `{}`
+- `             `profile: `{}`
 - modify PATH variable: `{}`
 ",
         opts.default_host_triple,
         opts.default_toolchain,
+        opts.profile,
         if !opts.no_modify_path { "yes" } else { "no" }
     )
 }
@@ -701,8 +709,14 @@ pub fn install_proxies() -> Result<()> {
     Ok(())
 }
 
-fn maybe_install_rust(toolchain_str: &str, default_host_triple: &str, verbose: bool) -> Result<()> {
+fn maybe_install_rust(
+    toolchain_str: &str,
+    profile_str: &str,
+    default_host_triple: &str,
+    verbose: bool,
+) -> Result<()> {
     let cfg = common::set_globals(verbose)?;
+    cfg.set_profile(profile_str.parse()?)?;
 
     // If there is already an install, then `toolchain_str` may not be
     // a toolchain the user actually wants. Don't do anything.  FIXME:
@@ -1341,13 +1355,13 @@ fn do_remove_from_path(methods: &[PathUpdateMethod]) -> Result<()> {
 /// (and on windows this process will not be running to do it),
 /// rustup-init is stored in `CARGO_HOME`/bin, and then deleted next
 /// time rustup runs.
-pub fn update() -> Result<()> {
+pub fn update(cfg: &Cfg) -> Result<()> {
     if NEVER_SELF_UPDATE {
         err!("self-update is disabled for this build of rustup");
         err!("you should probably use your system package manager to update rustup");
         process::exit(1);
     }
-    let setup_path = prepare_update()?;
+    let setup_path = prepare_update(cfg)?;
     if let Some(ref p) = setup_path {
         let version = match get_new_rustup_version(p) {
             Some(new_version) => parse_new_rustup_version(new_version),
@@ -1393,7 +1407,7 @@ fn parse_new_rustup_version(version: String) -> String {
     String::from(matched_version)
 }
 
-pub fn prepare_update() -> Result<Option<PathBuf>> {
+pub fn prepare_update(cfg: &Cfg) -> Result<Option<PathBuf>> {
     let cargo_home = utils::cargo_home()?;
     let rustup_path = cargo_home.join(&format!("bin/rustup{}", EXE_SUFFIX));
     let setup_path = cargo_home.join(&format!("bin/rustup-init{}", EXE_SUFFIX));
@@ -1421,19 +1435,68 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
         build_triple
     };
 
-    let update_root = env::var("RUSTUP_UPDATE_ROOT").unwrap_or_else(|_| String::from(UPDATE_ROOT));
-
-    let tempdir = TempDir::new("rustup-update").chain_err(|| "error creating temp directory")?;
+    let update_root = update_root();
 
     // Get current version
     let current_version = env!("CARGO_PKG_VERSION");
 
-    // Download available version
+    // Get available version
     info!("checking for self-updates");
+    let available_version = get_available_rustup_version(cfg)?;
+
+    // If up-to-date
+    if available_version == current_version {
+        return Ok(None);
+    }
+
+    // Get download URL
+    let url = format!(
+        "{}/archive/{}/{}/rustup-init{}",
+        update_root, available_version, triple, EXE_SUFFIX
+    );
+
+    // Get download path
+    let download_url = utils::parse_url(&url)?;
+
+    // Download new version
+    info!("downloading self-update");
+    utils::download_file(
+        &download_url,
+        &setup_path,
+        None,
+        &*utils::download_backend(),
+        cfg.download_options()?,
+        &|_| (),
+    )?;
+
+    // Mark as executable
+    utils::make_executable(&setup_path)?;
+
+    Ok(Some(setup_path))
+}
+
+fn update_root() -> String {
+    env::var("RUSTUP_UPDATE_ROOT").unwrap_or_else(|_| String::from(UPDATE_ROOT))
+}
+
+/// Reads the version of the latest rustup release from
+/// `release-stable.toml` on the update server.
+pub fn get_available_rustup_version(cfg: &Cfg) -> Result<String> {
+    let update_root = update_root();
+    let tempdir = TempDir::new("rustup-update").chain_err(|| "error creating temp directory")?;
+
+    // Download available version
     let release_file_url = format!("{}/release-stable.toml", update_root);
     let release_file_url = utils::parse_url(&release_file_url)?;
     let release_file = tempdir.path().join("release-stable.toml");
-    utils::download_file(&release_file_url, &release_file, None, &|_| ())?;
+    utils::download_file(
+        &release_file_url,
+        &release_file,
+        None,
+        &*utils::download_backend(),
+        cfg.download_options()?,
+        &|_| (),
+    )?;
     let release_toml_str = utils::read_file("rustup release", &release_file)?;
     let release_toml: toml::Value = toml::from_str(&release_toml_str)
         .map_err(|_| Error::from("unable to parse rustup release file"))?;
@@ -1457,28 +1520,7 @@ pub fn prepare_update() -> Result<Option<PathBuf>> {
         )));
     }
 
-    // If up-to-date
-    if available_version == current_version {
-        return Ok(None);
-    }
-
-    // Get download URL
-    let url = format!(
-        "{}/archive/{}/{}/rustup-init{}",
-        update_root, available_version, triple, EXE_SUFFIX
-    );
-
-    // Get download path
-    let download_url = utils::parse_url(&url)?;
-
-    // Download new version
-    info!("downloading self-update");
-    utils::download_file(&download_url, &setup_path, None, &|_| ())?;
-
-    // Mark as executable
-    utils::make_executable(&setup_path)?;
-
-    Ok(Some(setup_path))
+    Ok(available_version.to_string())
 }
 
 /// Tell the upgrader to replace the rustup bins, then delete
@@ -1,17 +1,26 @@
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::env;
 use std::fmt::{self, Display};
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::dist::cache::{self, CachedDownload};
+use crate::dist::component::Store;
+use crate::dist::manifest::Component;
+use crate::dist::signatures::SignatureCheck;
 use crate::dist::{dist, temp};
 use crate::errors::*;
 use crate::notifications::*;
 use crate::settings::{Settings, SettingsFile, DEFAULT_METADATA_VERSION};
+use crate::toml_utils::*;
 use crate::toolchain::{Toolchain, UpdateStatus};
 use crate::utils::utils;
+use download::{Backend, DownloadOptions, RetryPolicy};
 
 #[derive(Debug)]
 pub enum OverrideReason {
@@ -32,17 +41,82 @@ impl Display for OverrideReason {
     }
 }
 
+// The toolchain an override asks for. The TOML form of a
+// `rust-toolchain` file can also ask for the components, targets and
+// profile to install the toolchain with.
+#[derive(Debug, Default, PartialEq)]
+struct OverrideCfg {
+    toolchain: String,
+    components: Vec<String>,
+    targets: Vec<String>,
+    profile: Option<dist::Profile>,
+}
+
+impl OverrideCfg {
+    fn from_toolchain(toolchain: String) -> Self {
+        OverrideCfg {
+            toolchain,
+            ..Default::default()
+        }
+    }
+
+    // A `rust-toolchain` file is either a single line naming the
+    // toolchain, or a TOML document with a `[toolchain]` table.
+    fn parse_toolchain_file(contents: &str, path: &Path) -> Result<Option<Self>> {
+        let first_line = match contents.lines().map(str::trim).find(|l| !l.is_empty()) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+
+        match toml::from_str(contents) {
+            Ok(table) => Self::from_toml(table, "")
+                .chain_err(|| format!("invalid toolchain file '{}'", path.display()))
+                .map(Some),
+            Err(e) => {
+                if first_line.starts_with('[') || first_line.starts_with('#') {
+                    Err(ErrorKind::ParsingToolchainFile(path.to_owned(), e).into())
+                } else {
+                    Ok(Some(Self::from_toolchain(first_line.to_owned())))
+                }
+            }
+        }
+    }
+
+    fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let mut toolchain = get_table(&mut table, "toolchain", path)?;
+        let path = format!("{}toolchain.", path);
+        let profile = get_opt_string(&mut toolchain, "profile", &path)?
+            .map(|s| s.parse())
+            .transpose()?;
+        Ok(OverrideCfg {
+            toolchain: get_string(&mut toolchain, "channel", &path)?,
+            components: get_string_array(&mut toolchain, "components", &path)?,
+            targets: get_string_array(&mut toolchain, "targets", &path)?,
+            profile,
+        })
+    }
+}
+
 pub struct Cfg {
     pub rustup_dir: PathBuf,
     pub settings_file: SettingsFile,
     pub toolchains_dir: PathBuf,
     pub update_hash_dir: PathBuf,
     pub download_dir: PathBuf,
+    pub store: Store,
     pub temp_cfg: temp::Cfg,
     pub gpg_key: Cow<'static, str>,
     pub env_override: Option<String>,
-    pub dist_root_url: String,
-    pub dist_root_server: String,
+    pub profile_override: Option<dist::Profile>,
+    /// The `dist` directory of each of `dist_servers`
+    pub dist_root_urls: Vec<String>,
+    /// The servers to download toolchains from, in the order they are
+    /// tried
+    pub dist_servers: Vec<String>,
+    // An error is only returned by `download_options`, so that bad
+    // download settings only fail commands that download
+    download_options: std::result::Result<DownloadOptions, String>,
+    pub download_backend: Arc<dyn Backend>,
     pub notify_handler: Arc<dyn Fn(Notification<'_>)>,
 }
 
@@ -53,11 +127,13 @@ impl Cfg {
 
         utils::ensure_dir_exists("home", &rustup_dir, &|n| notify_handler(n.into()))?;
 
-        let settings_file = SettingsFile::new(rustup_dir.join("settings.toml"));
+        let settings_file =
+            SettingsFile::new(rustup_dir.join("settings.toml"), notify_handler.clone());
 
         let toolchains_dir = rustup_dir.join("toolchains");
         let update_hash_dir = rustup_dir.join("update-hashes");
         let download_dir = rustup_dir.join("downloads");
+        let store = Store::new(rustup_dir.join("store"));
 
         // GPG key
         let gpg_key =
@@ -72,27 +148,43 @@ impl Cfg {
             .ok()
             .and_then(utils::if_not_empty);
 
-        let dist_root_server = match env::var("RUSTUP_DIST_SERVER") {
-            Ok(ref s) if !s.is_empty() => s.clone(),
-            _ => {
+        // The environment takes precedence over the settings
+        let dist_servers = match env::var("RUSTUP_DIST_SERVER") {
+            Ok(ref s) if !s.is_empty() => s
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            _ => match env::var("RUSTUP_DIST_ROOT")
+                .ok()
+                .and_then(utils::if_not_empty)
+            {
                 // For backward compatibility
-                env::var("RUSTUP_DIST_ROOT")
-                    .ok()
-                    .and_then(utils::if_not_empty)
-                    .map_or(Cow::Borrowed(dist::DEFAULT_DIST_ROOT), Cow::Owned)
-                    .as_ref()
-                    .trim_end_matches("/dist")
-                    .to_owned()
-            }
+                Some(root) => vec![root.trim_end_matches("/dist").to_owned()],
+                None => settings_file.with(|s| Ok(s.dist_servers.clone()))?,
+            },
+        };
+        let dist_servers = if dist_servers.is_empty() {
+            vec![dist::DEFAULT_DIST_SERVER.to_owned()]
+        } else {
+            dist_servers
         };
 
         let notify_clone = notify_handler.clone();
         let temp_cfg = temp::Cfg::new(
             rustup_dir.join("tmp"),
-            dist_root_server.as_str(),
+            dist_servers[0].as_str(),
             Box::new(move |n| (notify_clone)(n.into())),
         );
-        let dist_root = dist_root_server.clone() + "/dist";
+        let dist_root_urls = dist_servers.iter().map(|s| s.clone() + "/dist").collect();
+
+        let download_options = settings_file.with(download_options).map_err(|e| {
+            e.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": ")
+        });
 
         let cfg = Cfg {
             rustup_dir,
@@ -100,12 +192,16 @@ impl Cfg {
             toolchains_dir,
             update_hash_dir,
             download_dir,
+            store,
             temp_cfg,
             gpg_key,
             notify_handler,
             env_override,
-            dist_root_url: dist_root,
-            dist_root_server,
+            profile_override: None,
+            dist_root_urls,
+            dist_servers,
+            download_options,
+            download_backend: utils::download_backend(),
         };
 
         // Run some basic checks against the constructed configuration
@@ -126,6 +222,28 @@ impl Cfg {
         Ok(())
     }
 
+    pub fn set_profile_override(&mut self, profile: dist::Profile) {
+        self.profile_override = Some(profile);
+    }
+
+    pub fn set_profile(&self, profile: dist::Profile) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.profile = Some(profile);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetProfile(profile));
+        Ok(())
+    }
+
+    // The profile to install new toolchains with
+    pub fn get_profile(&self) -> Result<dist::Profile> {
+        if let Some(p) = self.profile_override {
+            return Ok(p);
+        }
+        self.settings_file
+            .with(|s| Ok(s.profile.unwrap_or_default()))
+    }
+
     pub fn get_toolchain(&self, name: &str, create_parent: bool) -> Result<Toolchain<'_>> {
         if create_parent {
             utils::ensure_dir_exists("toolchains", &self.toolchains_dir, &|n| {
@@ -233,7 +351,10 @@ impl Cfg {
 
         // First check RUSTUP_TOOLCHAIN
         if let Some(ref name) = self.env_override {
-            override_ = Some((name.to_string(), OverrideReason::Environment));
+            override_ = Some((
+                OverrideCfg::from_toolchain(name.to_string()),
+                OverrideReason::Environment,
+            ));
         }
 
         // Then walk up the directory tree from 'path' looking for either the
@@ -246,7 +367,9 @@ impl Cfg {
             })?;
         }
 
-        if let Some((name, reason)) = override_ {
+        if let Some((override_cfg, reason)) = override_ {
+            let name = &override_cfg.toolchain;
+
             // This is hackishly using the error chain to provide a bit of
             // extra context about what went wrong. The CLI will display it
             // on a line after the proximate error.
@@ -266,7 +389,7 @@ impl Cfg {
                 ),
             };
 
-            match self.get_toolchain(&name, false) {
+            match self.get_toolchain(name, false) {
                 Ok(toolchain) => {
                     if toolchain.exists() {
                         Ok(Some((toolchain, reason)))
@@ -277,7 +400,7 @@ impl Cfg {
                             ErrorKind::OverrideToolchainNotInstalled(name.to_string())
                         })
                     } else {
-                        toolchain.install_from_dist(false)?;
+                        self.install_override_toolchain(&toolchain, &override_cfg)?;
                         Ok(Some((toolchain, reason)))
                     }
                 }
@@ -290,11 +413,39 @@ impl Cfg {
         }
     }
 
+    // Installs the toolchain an override asks for, together with its
+    // components and targets
+    fn install_override_toolchain(
+        &self,
+        toolchain: &Toolchain<'_>,
+        override_cfg: &OverrideCfg,
+    ) -> Result<()> {
+        let profile = match override_cfg.profile {
+            Some(profile) => profile,
+            None => self.get_profile()?,
+        };
+        let target = toolchain.desc()?.target;
+        let components = override_cfg
+            .components
+            .iter()
+            .map(|c| Component::new(c.to_string(), Some(target.clone())));
+        let targets = override_cfg.targets.iter().map(|t| {
+            Component::new(
+                "rust-std".to_string(),
+                Some(dist::TargetTriple::from_str(t)),
+            )
+        });
+        let components: Vec<_> = components.chain(targets).collect();
+
+        toolchain.install_from_dist_with_components(false, profile, &components)?;
+        Ok(())
+    }
+
     fn find_override_from_dir_walk(
         &self,
         dir: &Path,
         settings: &Settings,
-    ) -> Result<Option<(String, OverrideReason)>> {
+    ) -> Result<Option<(OverrideCfg, OverrideReason)>> {
         let notify = self.notify_handler.as_ref();
         let dir = utils::canonicalize_path(dir, &|n| notify(n.into()));
         let mut dir = Some(&*dir);
@@ -303,24 +454,28 @@ impl Cfg {
             // First check the override database
             if let Some(name) = settings.dir_override(d, notify) {
                 let reason = OverrideReason::OverrideDB(d.to_owned());
-                return Ok(Some((name, reason)));
+                return Ok(Some((OverrideCfg::from_toolchain(name), reason)));
             }
 
-            // Then look for 'rust-toolchain'
-            let toolchain_file = d.join("rust-toolchain");
-            if let Ok(s) = utils::read_file("toolchain file", &toolchain_file) {
-                if let Some(s) = s.lines().next() {
-                    let toolchain_name = s.trim();
-                    dist::validate_channel_name(&toolchain_name).chain_err(|| {
-                        format!(
-                            "invalid channel name '{}' in '{}'",
-                            toolchain_name,
-                            toolchain_file.display()
-                        )
-                    })?;
-
-                    let reason = OverrideReason::ToolchainFile(toolchain_file);
-                    return Ok(Some((toolchain_name.to_string(), reason)));
+            // Then look for 'rust-toolchain' or 'rust-toolchain.toml'
+            for file_name in &["rust-toolchain", "rust-toolchain.toml"] {
+                let toolchain_file = d.join(file_name);
+                if let Ok(s) = utils::read_file("toolchain file", &toolchain_file) {
+                    if let Some(override_cfg) =
+                        OverrideCfg::parse_toolchain_file(&s, &toolchain_file)?
+                    {
+                        let toolchain_name = &override_cfg.toolchain;
+                        dist::validate_channel_name(toolchain_name).chain_err(|| {
+                            format!(
+                                "invalid channel name '{}' in '{}'",
+                                toolchain_name,
+                                toolchain_file.display()
+                            )
+                        })?;
+
+                        let reason = OverrideReason::ToolchainFile(toolchain_file);
+                        return Ok(Some((override_cfg, reason)));
+                    }
                 }
             }
 
@@ -367,10 +522,8 @@ impl Cfg {
         }
     }
 
-    pub fn update_all_channels(
-        &self,
-        force_update: bool,
-    ) -> Result<Vec<(String, Result<UpdateStatus>)>> {
+    // The installed toolchains that track a release channel
+    pub fn list_channels(&self) -> Result<Vec<(String, Result<Toolchain<'_>>)>> {
         let toolchains = self.list_toolchains()?;
 
         // Convert the toolchain strings to Toolchain values
@@ -378,11 +531,23 @@ impl Cfg {
         let toolchains = toolchains.map(|n| (n.clone(), self.get_toolchain(&n, true)));
 
         // Filter out toolchains that don't track a release channel
-        let toolchains = toolchains
-            .filter(|&(_, ref t)| t.as_ref().map(Toolchain::is_tracking).unwrap_or(false));
+        Ok(toolchains
+            .filter(|&(_, ref t)| t.as_ref().map(Toolchain::is_tracking).unwrap_or(false))
+            .collect())
+    }
+
+    pub fn update_all_channels(
+        &self,
+        force_update: bool,
+    ) -> Result<Vec<(String, Result<UpdateStatus>)>> {
+        let toolchains = self.list_channels()?;
+        let pinned = self.pinned_toolchains()?;
 
         // Update toolchains and collect the results
-        let toolchains = toolchains.map(|(n, t)| {
+        let toolchains = toolchains.into_iter().map(|(n, t)| {
+            if pinned.contains(&n) {
+                return (n, Ok(UpdateStatus::Pinned));
+            }
             let t = t.and_then(|t| {
                 let t = t.install_from_dist(force_update);
                 if let Err(ref e) = t {
@@ -491,6 +656,159 @@ impl Cfg {
         })
     }
 
+    pub fn set_signature_check(&self, signature_check: SignatureCheck) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.signature_check = Some(signature_check);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetSignatureCheck(signature_check));
+        Ok(())
+    }
+
+    pub fn signature_check(&self) -> Result<SignatureCheck> {
+        self.settings_file
+            .with(|s| Ok(s.signature_check.unwrap_or_default()))
+    }
+
+    pub fn set_dist_servers(&self, servers: &[String]) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.dist_servers = servers.to_vec();
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetDistServers(servers));
+        Ok(())
+    }
+
+    pub fn set_download_retention(&self, days: Option<u32>) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.download_retention = days;
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetDownloadRetention(days));
+        Ok(())
+    }
+
+    pub fn set_download_retries(&self, retries: Option<u32>) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.download_retries = retries;
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetDownloadRetries(retries));
+        Ok(())
+    }
+
+    pub fn set_max_download_rate(&self, rate: Option<u64>) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.max_download_rate = rate;
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetMaxDownloadRate(rate));
+        Ok(())
+    }
+
+    /// How downloads are made, from the settings and the `RUSTUP_*`
+    /// variables that override them
+    pub fn download_options(&self) -> Result<&DownloadOptions> {
+        self.download_options
+            .as_ref()
+            .map_err(|e| e.to_owned().into())
+    }
+
+    // How long to keep packages in the download cache after installing
+    // them, if at all
+    pub fn download_retention(&self) -> Result<Option<Duration>> {
+        self.settings_file.with(|s| {
+            Ok(s.download_retention
+                .map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60)))
+        })
+    }
+
+    pub fn set_shared_store(&self, enabled: bool) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.shared_store = Some(enabled);
+            Ok(())
+        })?;
+        (self.notify_handler)(Notification::SetSharedStore(enabled));
+        Ok(())
+    }
+
+    // Toolchains that `update_all_channels` leaves as they are
+    pub fn pinned_toolchains(&self) -> Result<Vec<String>> {
+        self.settings_file.with(|s| Ok(s.pinned.clone()))
+    }
+
+    pub fn set_pinned(&self, toolchain: &str, pinned: bool) -> Result<()> {
+        self.settings_file.with_mut(|s| {
+            s.pinned.retain(|name| name != toolchain);
+            if pinned {
+                s.pinned.push(toolchain.to_owned());
+                s.pinned.sort();
+            }
+            Ok(())
+        })?;
+        if pinned {
+            (self.notify_handler)(Notification::PinnedToolchain(toolchain));
+        } else {
+            (self.notify_handler)(Notification::UnpinnedToolchain(toolchain));
+        }
+        Ok(())
+    }
+
+    // Whether toolchains share identical files through `self.store`
+    pub fn shared_store(&self) -> Result<bool> {
+        self.settings_file
+            .with(|s| Ok(s.shared_store.unwrap_or(false)))
+    }
+
+    pub fn cached_downloads(&self) -> Result<Vec<CachedDownload>> {
+        cache::list(&self.download_dir)
+    }
+
+    // The hashes of the cached packages that installed toolchains, or
+    // rollbacks of them, can reuse
+    pub fn used_download_hashes(&self) -> Result<HashSet<String>> {
+        let mut hashes = HashSet::new();
+        for name in self.list_toolchains()? {
+            let toolchain = self.get_toolchain(&name, false)?;
+            hashes.extend(toolchain.cached_package_hashes()?);
+        }
+        Ok(hashes)
+    }
+
+    /// Removes partial downloads and the packages that no installed
+    /// toolchain can reuse, keeping those modified within `older_than`,
+    /// or within the retention period if not given. Returns the
+    /// removed files.
+    pub fn prune_downloads(&self, older_than: Option<Duration>) -> Result<Vec<CachedDownload>> {
+        let older_than = match older_than {
+            Some(age) => Some(age),
+            None => self.download_retention()?,
+        };
+        let used = self.used_download_hashes()?;
+
+        let mut removed = vec![];
+        for download in self.cached_downloads()? {
+            let unused = download.is_partial() || !used.contains(download.hash());
+            let expired = older_than.map_or(true, |age| download.is_older_than(age));
+            if unused && expired && download.remove()? {
+                removed.push(download);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes everything in the download cache that no download or
+    /// install is using, returning the removed files.
+    pub fn clear_downloads(&self) -> Result<Vec<CachedDownload>> {
+        let mut removed = vec![];
+        for download in self.cached_downloads()? {
+            if download.remove()? {
+                removed.push(download);
+            }
+        }
+        Ok(removed)
+    }
+
     pub fn get_default_host_triple(&self) -> Result<dist::TargetTriple> {
         Ok(self
             .settings_file
@@ -511,3 +829,114 @@ impl Cfg {
         }
     }
 }
+
+// How downloads are made. Each `RUSTUP_*` variable for an option takes
+// precedence over its setting.
+fn download_options(settings: &Settings) -> Result<DownloadOptions> {
+    let defaults = DownloadOptions::default();
+
+    let proxy = match download_env("RUSTUP_PROXY").or_else(|| settings.proxy.clone()) {
+        Some(proxy) => {
+            Some(utils::parse_url(&proxy).chain_err(|| format!("invalid proxy '{}'", proxy))?)
+        }
+        None => None,
+    };
+    let no_proxy = match download_env("RUSTUP_NO_PROXY") {
+        Some(hosts) => hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        None => settings.no_proxy.clone(),
+    };
+    let ca_certs = match env::var_os("RUSTUP_CA_CERTS").and_then(utils::if_not_empty) {
+        Some(paths) => env::split_paths(&paths).collect(),
+        None => settings.ca_certs.iter().map(PathBuf::from).collect(),
+    };
+    let client_cert = env::var_os("RUSTUP_CLIENT_CERT")
+        .and_then(utils::if_not_empty)
+        .map(PathBuf::from)
+        .or_else(|| settings.client_cert.as_ref().map(PathBuf::from));
+
+    let connect_timeout = match download_env("RUSTUP_CONNECT_TIMEOUT") {
+        Some(secs) => Some(parse_env_number("RUSTUP_CONNECT_TIMEOUT", &secs)?),
+        None => settings.connect_timeout,
+    };
+    let low_speed_limit = match download_env("RUSTUP_LOW_SPEED_LIMIT") {
+        Some(limit) => Some(parse_env_number("RUSTUP_LOW_SPEED_LIMIT", &limit)?),
+        None => settings.low_speed_limit,
+    };
+    let low_speed_time = match download_env("RUSTUP_LOW_SPEED_TIME") {
+        Some(secs) => Some(parse_env_number("RUSTUP_LOW_SPEED_TIME", &secs)?),
+        None => settings.low_speed_time,
+    };
+
+    let max_rate = match download_env("RUSTUP_MAX_DOWNLOAD_RATE") {
+        Some(rate) => {
+            Some(parse_download_rate(&rate).chain_err(|| "invalid RUSTUP_MAX_DOWNLOAD_RATE")?)
+        }
+        None => settings.max_download_rate,
+    };
+
+    let retries = match download_env("RUSTUP_DOWNLOAD_RETRIES") {
+        Some(retries) => Some(retries.parse::<u32>().map_err(|_| {
+            format!(
+                "RUSTUP_DOWNLOAD_RETRIES must be a number of retries, not '{}'",
+                retries
+            )
+        })?),
+        None => settings.download_retries,
+    };
+    let mut retry = RetryPolicy::default();
+    if let Some(retries) = retries {
+        retry.max_attempts = retries.saturating_add(1);
+    }
+
+    Ok(DownloadOptions {
+        proxy,
+        no_proxy,
+        ca_certs,
+        client_cert,
+        // The password is a secret, so it is never kept in the settings
+        client_cert_password: download_env("RUSTUP_CLIENT_CERT_PASSWORD"),
+        connect_timeout: connect_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.connect_timeout),
+        low_speed_limit: low_speed_limit.unwrap_or(defaults.low_speed_limit),
+        low_speed_time: low_speed_time
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.low_speed_time),
+        max_rate,
+        shared_throttle: None,
+        retry,
+    })
+}
+
+fn download_env(name: &str) -> Option<String> {
+    env::var(name).ok().and_then(utils::if_not_empty)
+}
+
+fn parse_env_number<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} must be a number, not '{}'", name, value).into())
+}
+
+/// Parses a download rate in bytes per second, which may be given in
+/// kibibytes, mebibytes or gibibytes with a `K`, `M` or `G` suffix.
+pub fn parse_download_rate(rate: &str) -> Result<u64> {
+    let (number, unit) = match rate.trim().to_ascii_uppercase() {
+        r if r.ends_with('K') => (r[..r.len() - 1].to_owned(), 1 << 10),
+        r if r.ends_with('M') => (r[..r.len() - 1].to_owned(), 1 << 20),
+        r if r.ends_with('G') => (r[..r.len() - 1].to_owned(), 1 << 30),
+        r => (r, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("'{}' is not a download rate", rate).into())
+}
@@ -0,0 +1,135 @@
+//! The download cache, where component packages are kept by hash
+//! while they are being downloaded and installed.
+
+use crate::errors::*;
+use crate::utils::lock::FileLock;
+use crate::utils::notifications::Notification;
+use crate::utils::utils;
+use download::validator_path;
+use filetime::{set_file_times, FileTime};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const PARTIAL_SUFFIX: &str = ".partial";
+// The locks of the downloads in progress. `list` skips directories, so
+// they are never taken for downloads themselves.
+const LOCKS_DIR: &str = "locks";
+
+#[derive(Debug)]
+pub struct CachedDownload {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl CachedDownload {
+    pub fn name(&self) -> &str {
+        self.path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+    }
+
+    /// The hash of the package, which names the file.
+    pub fn hash(&self) -> &str {
+        let name = self.name();
+        name.trim_end_matches(PARTIAL_SUFFIX)
+    }
+
+    /// Whether this is a download that was interrupted or failed.
+    pub fn is_partial(&self) -> bool {
+        self.name().ends_with(PARTIAL_SUFFIX)
+    }
+
+    /// Whether the file was last written or reused more than `age` ago.
+    pub fn is_older_than(&self, age: Duration) -> bool {
+        SystemTime::now()
+            .duration_since(self.modified)
+            .map_or(false, |elapsed| elapsed > age)
+    }
+
+    /// Removes the file, unless a download or install is using it.
+    /// Returns whether it was removed.
+    pub fn remove(&self) -> Result<bool> {
+        let download_dir = self.path.parent().expect("downloads are in a directory");
+        let _lock = match FileLock::try_exclusive(&lock_path(download_dir, self.hash()), &|_| ())? {
+            Some(lock) => lock,
+            None => return Ok(false),
+        };
+        let validator = validator_path(&self.path);
+        if self.is_partial() && utils::is_file(&validator) {
+            utils::remove_file("cached download", &validator)?;
+        }
+        utils::remove_file("cached download", &self.path)?;
+        Ok(true)
+    }
+}
+
+/// Locks the download of `hash` into `download_dir`, waiting while
+/// another process downloads it. Nothing is removed from the cache
+/// for `hash` while the lock is held.
+pub fn lock_download(
+    download_dir: &Path,
+    hash: &str,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<FileLock> {
+    FileLock::exclusive(&lock_path(download_dir, hash), notify_handler)
+}
+
+/// Marks a cached package as used now, so that it is kept for the
+/// retention period after the install that reused it.
+pub fn mark_used(path: &Path) -> Result<()> {
+    let now = FileTime::from_system_time(SystemTime::now());
+    set_file_times(path, now, now).chain_err(|| ErrorKind::WritingFile {
+        name: "cached download",
+        path: path.to_owned(),
+    })
+}
+
+fn lock_path(download_dir: &Path, hash: &str) -> PathBuf {
+    download_dir.join(LOCKS_DIR).join(format!("{}.lock", hash))
+}
+
+/// The files in `download_dir`, sorted by name.
+pub fn list(download_dir: &Path) -> Result<Vec<CachedDownload>> {
+    if !utils::is_directory(download_dir) {
+        return Ok(vec![]);
+    }
+
+    let mut downloads = vec![];
+    for entry in utils::read_dir("downloads", download_dir)? {
+        let entry = entry.chain_err(|| ErrorKind::ReadingDirectory {
+            name: "downloads",
+            path: download_dir.to_owned(),
+        })?;
+        let metadata = entry.metadata().chain_err(|| ErrorKind::ReadingFile {
+            name: "cached download",
+            path: entry.path(),
+        })?;
+        // Validators are part of the partial download next to them
+        let path = entry.path();
+        if !metadata.is_file() || path == validator_path(&path.with_extension("")) {
+            continue;
+        }
+        downloads.push(CachedDownload {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        });
+    }
+    downloads.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(downloads)
+}
+
+/// Removes the files in `download_dir` last used more than `age` ago,
+/// returning those removed.
+pub fn expire(download_dir: &Path, age: Duration) -> Result<Vec<CachedDownload>> {
+    let mut removed = vec![];
+    for download in list(download_dir)? {
+        if download.is_older_than(age) && download.remove()? {
+            removed.push(download);
+        }
+    }
+    Ok(removed)
+}
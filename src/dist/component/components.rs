@@ -7,9 +7,12 @@ use crate::utils::utils;
 
 use crate::dist::component::package::{INSTALLER_VERSION, VERSION_FILE};
 use crate::dist::component::transaction::Transaction;
+use crate::dist::download::file_hash;
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
 const COMPONENTS_FILE: &str = "components";
 
@@ -37,6 +40,9 @@ impl Components {
     fn rel_component_manifest(&self, name: &str) -> PathBuf {
         self.prefix.rel_manifest_file(&format!("manifest-{}", name))
     }
+    fn rel_component_hashes(&self, name: &str) -> PathBuf {
+        self.prefix.rel_manifest_file(&format!("hashes-{}", name))
+    }
     fn read_version(&self) -> Result<Option<String>> {
         let p = self.prefix.manifest_file(VERSION_FILE);
         if utils::is_file(&p) {
@@ -84,6 +90,34 @@ impl Components {
     pub fn prefix(&self) -> InstallPrefix {
         self.prefix.clone()
     }
+    // The hashes of the files installed for `part`, by relative path
+    fn hash_part(&self, part: &ComponentPart) -> Result<Vec<(PathBuf, String)>> {
+        let mut hashes = vec![];
+        for path in self.part_files(part)? {
+            let hash = file_hash(&self.prefix.abs_path(&path))?;
+            hashes.push((path, hash));
+        }
+        Ok(hashes)
+    }
+    // The relative paths of the files installed for `part`
+    fn part_files(&self, part: &ComponentPart) -> Result<Vec<PathBuf>> {
+        if part.0 != "dir" {
+            return Ok(vec![part.1.clone()]);
+        }
+        let root = self.prefix.path();
+        let mut files = vec![];
+        for entry in WalkDir::new(self.prefix.abs_path(&part.1)) {
+            let entry = entry.chain_err(|| ErrorKind::ReadingDirectory {
+                name: "component",
+                path: self.prefix.abs_path(&part.1),
+            })?;
+            if entry.file_type().is_file() {
+                let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                files.push(path.to_owned());
+            }
+        }
+        Ok(files)
+    }
 }
 
 pub struct ComponentBuilder<'a> {
@@ -124,12 +158,23 @@ impl<'a> ComponentBuilder<'a> {
         let path = self.components.rel_component_manifest(&self.name);
         let abs_path = self.components.prefix.abs_path(&path);
         let mut file = self.tx.add_file(&self.name, path)?;
-        for part in self.parts {
+        for part in &self.parts {
             // FIXME: This writes relative paths to the component manifest,
             // but rust-installer writes absolute paths.
             utils::write_line("component", &mut file, &abs_path, &part.encode())?;
         }
 
+        // Record the hash of every installed file, for `Component::verify`
+        let path = self.components.rel_component_hashes(&self.name);
+        let abs_path = self.components.prefix.abs_path(&path);
+        let mut file = self.tx.add_file(&self.name, path)?;
+        for part in &self.parts {
+            for (path, hash) in self.components.hash_part(part)? {
+                let line = format!("{}:{}", hash, path.to_string_lossy());
+                utils::write_line("component hashes", &mut file, &abs_path, &line)?;
+            }
+        }
+
         // Add component to components file
         let path = self.components.rel_components_file();
         let abs_path = self.components.prefix.abs_path(&path);
@@ -146,6 +191,24 @@ impl<'a> ComponentBuilder<'a> {
 #[derive(Debug)]
 pub struct ComponentPart(pub String, pub PathBuf);
 
+/// How the files of an installed component differ from those it was
+/// installed with. Paths are relative to the installation prefix.
+#[derive(Debug, Default)]
+pub struct Verification {
+    pub missing: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub extra: Vec<PathBuf>,
+    /// Whether the component was installed with file hashes. Without
+    /// them only missing files can be found.
+    pub hashed: bool,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty() && self.extra.is_empty()
+    }
+}
+
 impl ComponentPart {
     pub fn encode(&self) -> String {
         format!("{}:{}", &self.0, &self.1.to_string_lossy())
@@ -174,9 +237,70 @@ impl Component {
             .prefix
             .rel_manifest_file(&self.manifest_name())
     }
+    fn hashes_file(&self) -> PathBuf {
+        let path = self.components.rel_component_hashes(&self.name);
+        self.components.prefix.abs_path(path)
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
+    /// The hash of each installed file, by relative path, or `None` for
+    /// components installed before hashes were recorded.
+    pub fn hashes(&self) -> Result<Option<HashMap<PathBuf, String>>> {
+        let path = self.hashes_file();
+        if !utils::is_file(&path) {
+            return Ok(None);
+        }
+        let mut hashes = HashMap::new();
+        for line in utils::read_file("component hashes", &path)?.lines() {
+            let pos = line
+                .find(':')
+                .ok_or_else(|| ErrorKind::CorruptComponent(self.name.clone()))?;
+            hashes.insert(PathBuf::from(&line[(pos + 1)..]), line[..pos].to_owned());
+        }
+        Ok(Some(hashes))
+    }
+    /// Compares the installed files with the hashes recorded when the
+    /// component was installed.
+    pub fn verify(&self) -> Result<Verification> {
+        let mut result = Verification::default();
+        let parts = self.parts()?;
+        let hashes = match self.hashes()? {
+            Some(hashes) => hashes,
+            None => {
+                for part in parts {
+                    if !utils::path_exists(self.components.prefix.abs_path(&part.1)) {
+                        result.missing.push(part.1);
+                    }
+                }
+                return Ok(result);
+            }
+        };
+        result.hashed = true;
+
+        let mut recorded: Vec<_> = hashes.iter().collect();
+        recorded.sort();
+        for (path, hash) in recorded {
+            let abs_path = self.components.prefix.abs_path(path);
+            if !utils::is_file(&abs_path) {
+                result.missing.push(path.clone());
+            } else if file_hash(&abs_path)? != *hash {
+                result.modified.push(path.clone());
+            }
+        }
+        for part in parts.iter().filter(|p| p.0 == "dir") {
+            if !utils::path_exists(self.components.prefix.abs_path(&part.1)) {
+                continue;
+            }
+            for path in self.components.part_files(part)? {
+                if !hashes.contains_key(&path) {
+                    result.extra.push(path);
+                }
+            }
+        }
+        result.extra.sort();
+        Ok(result)
+    }
     pub fn parts(&self) -> Result<Vec<ComponentPart>> {
         let mut result = Vec::new();
         for line in utils::read_file("component", &self.manifest_file())?.lines() {
@@ -300,6 +424,11 @@ impl Component {
             prefix: self.components.prefix.abs_path(""),
         };
         for part in self.parts()?.into_iter().rev() {
+            // Damaged installations may have lost parts already
+            if !utils::path_exists(self.components.prefix.abs_path(&part.1)) {
+                pset.seen(part.1);
+                continue;
+            }
             match &*part.0 {
                 "file" => tx.remove_file(&self.name, part.1.clone())?,
                 "dir" => tx.remove_dir(&self.name, part.1.clone())?,
@@ -311,8 +440,12 @@ impl Component {
             tx.remove_dir(&self.name, empty_dir)?;
         }
 
-        // Remove component manifest
+        // Remove component manifest, and the hashes recorded with it
         tx.remove_file(&self.name, self.rel_manifest_file())?;
+        if utils::is_file(&self.hashes_file()) {
+            let path = self.components.rel_component_hashes(&self.name);
+            tx.remove_file(&self.name, path)?;
+        }
 
         Ok(tx)
     }
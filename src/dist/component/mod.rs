@@ -1,5 +1,6 @@
 pub use self::components::*;
 pub use self::package::*;
+pub use self::store::*;
 /// An interpreter for the rust-installer [1] installation format.
 ///
 /// https://github.com/rust-lang/rust-installer
@@ -11,3 +12,5 @@ mod transaction;
 mod package;
 // The representation of *installed* components, and uninstallation
 mod components;
+// Files shared between installations
+mod store;
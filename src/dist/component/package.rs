@@ -197,12 +197,11 @@ pub struct TarPackage<'a>(DirectoryPackage, temp::Dir<'a>);
 impl<'a> TarPackage<'a> {
     pub fn new<R: Read>(stream: R, temp_cfg: &'a temp::Cfg) -> Result<Self> {
         let temp_dir = temp_cfg.new_directory()?;
-        let mut archive = tar::Archive::new(stream);
-        // The rust-installer packages unpack to a directory called
-        // $pkgname-$version-$target. Skip that directory when
-        // unpacking.
-        unpack_without_first_dir(&mut archive, &*temp_dir)?;
-
+        unpack_tar(stream, &*temp_dir)?;
+        Self::unpacked(temp_dir)
+    }
+    /// The package that `unpack_tar` has already unpacked into `temp_dir`.
+    pub fn unpacked(temp_dir: temp::Dir<'a>) -> Result<Self> {
         Ok(TarPackage(
             DirectoryPackage::new(temp_dir.to_owned(), false)?,
             temp_dir,
@@ -210,6 +209,21 @@ impl<'a> TarPackage<'a> {
     }
 }
 
+/// Unpacks a tarball into `path`, as `TarPackage` expects to find it,
+/// replacing anything already there.
+pub fn unpack_tar<R: Read>(stream: R, path: &Path) -> Result<()> {
+    // Left behind by a download that failed part way through
+    if utils::is_directory(path) && utils::read_dir("component", path)?.next().is_some() {
+        utils::remove_dir("component", path, &|_| ())?;
+        utils::ensure_dir_exists("component", path, &|_| ())?;
+    }
+    let mut archive = tar::Archive::new(stream);
+    // The rust-installer packages unpack to a directory called
+    // $pkgname-$version-$target. Skip that directory when
+    // unpacking.
+    unpack_without_first_dir(&mut archive, path)
+}
+
 fn unpack_without_first_dir<R: Read>(archive: &mut tar::Archive<R>, path: &Path) -> Result<()> {
     let entries = archive
         .entries()
@@ -269,6 +283,9 @@ impl<'a> TarGzPackage<'a> {
 
         Ok(TarGzPackage(TarPackage::new(stream, temp_cfg)?))
     }
+    pub fn unpack<R: Read>(stream: R, path: &Path) -> Result<()> {
+        unpack_tar(flate2::read::GzDecoder::new(stream), path)
+    }
     pub fn new_file(path: &Path, temp_cfg: &'a temp::Cfg) -> Result<Self> {
         let file = File::open(path).chain_err(|| ErrorKind::ExtractingPackage)?;
         Self::new(file, temp_cfg)
@@ -302,6 +319,9 @@ impl<'a> TarXzPackage<'a> {
 
         Ok(TarXzPackage(TarPackage::new(stream, temp_cfg)?))
     }
+    pub fn unpack<R: Read>(stream: R, path: &Path) -> Result<()> {
+        unpack_tar(xz2::read::XzDecoder::new(stream), path)
+    }
     pub fn new_file(path: &Path, temp_cfg: &'a temp::Cfg) -> Result<Self> {
         let file = File::open(path).chain_err(|| ErrorKind::ExtractingPackage)?;
         Self::new(file, temp_cfg)
@@ -325,3 +345,45 @@ impl<'a> Package for TarXzPackage<'a> {
         self.0.components()
     }
 }
+
+#[cfg(feature = "zstd")]
+#[derive(Debug)]
+pub struct TarZstdPackage<'a>(TarPackage<'a>);
+
+#[cfg(feature = "zstd")]
+impl<'a> TarZstdPackage<'a> {
+    pub fn new<R: Read>(stream: R, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+        let stream =
+            zstd::stream::read::Decoder::new(stream).chain_err(|| ErrorKind::ExtractingPackage)?;
+
+        Ok(TarZstdPackage(TarPackage::new(stream, temp_cfg)?))
+    }
+    pub fn unpack<R: Read>(stream: R, path: &Path) -> Result<()> {
+        let stream =
+            zstd::stream::read::Decoder::new(stream).chain_err(|| ErrorKind::ExtractingPackage)?;
+        unpack_tar(stream, path)
+    }
+    pub fn new_file(path: &Path, temp_cfg: &'a temp::Cfg) -> Result<Self> {
+        let file = File::open(path).chain_err(|| ErrorKind::ExtractingPackage)?;
+        Self::new(file, temp_cfg)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<'a> Package for TarZstdPackage<'a> {
+    fn contains(&self, component: &str, short_name: Option<&str>) -> bool {
+        self.0.contains(component, short_name)
+    }
+    fn install<'b>(
+        &self,
+        target: &Components,
+        component: &str,
+        short_name: Option<&str>,
+        tx: Transaction<'b>,
+    ) -> Result<Transaction<'b>> {
+        self.0.install(target, component, short_name, tx)
+    }
+    fn components(&self) -> Vec<String> {
+        self.0.components()
+    }
+}
@@ -0,0 +1,203 @@
+//! A content-addressed store of installed files, shared between
+//! toolchains.
+//!
+//! Each file is kept once under the hash of its contents and hard
+//! linked into every installation prefix that contains it. A file the
+//! store holds the only link to is no longer installed anywhere, and
+//! is removed by `collect_garbage`.
+
+use crate::dist::download::file_hash;
+use crate::errors::*;
+use crate::utils::raw;
+use crate::utils::utils;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[derive(Clone, Debug)]
+pub struct Store {
+    path: PathBuf,
+}
+
+/// The space taken by the store, and saved by sharing its files.
+#[derive(Debug, Default)]
+pub struct StoreStats {
+    pub files: u64,
+    /// The size of the files in the store
+    pub size: u64,
+    /// The size of all the copies linked into installation prefixes
+    pub linked_size: u64,
+    /// The size of the copies that would be needed without the store
+    pub saved_size: u64,
+}
+
+impl Store {
+    pub fn new(path: PathBuf) -> Self {
+        Store { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.path.join(&hash[..2]).join(hash)
+    }
+
+    /// Puts the contents of `src` into the store, moving the file
+    /// unless `copy` is set, and links it to `dest`.
+    pub fn link_file(&self, src: &Path, dest: &Path, copy: bool) -> Result<()> {
+        let blob = self.blob_path(&file_hash(src)?);
+        if utils::is_file(&blob) {
+            match raw::hardlink(&blob, dest) {
+                Ok(()) => {
+                    if !copy {
+                        utils::remove_file("component", src)?;
+                    }
+                    return Ok(());
+                }
+                // Another process collected it since, so add it again
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).chain_err(|| ErrorKind::LinkingFile {
+                        src: blob.clone(),
+                        dest: dest.to_owned(),
+                    })
+                }
+            }
+        }
+
+        let dir = blob.parent().expect("blobs are in a subdirectory");
+        utils::ensure_dir_exists("store", dir, &|_| ())?;
+        // Go through a temporary name so that a failure never leaves a
+        // blob with the wrong contents behind, and link `dest` before
+        // the blob appears so that it is never collected unlinked
+        let partial = blob.with_extension(format!("{}.partial", process::id()));
+        if copy {
+            utils::copy_file(src, &partial)?;
+        } else {
+            utils::rename_file("component", src, &partial)?;
+        }
+        utils::hardlink_file(&partial, dest)?;
+        utils::rename_file("store", &partial, &blob)
+    }
+
+    /// Links every file under `src` into the same place under `dest`,
+    /// the way `link_file` does.
+    pub fn link_dir(&self, src: &Path, dest: &Path, copy: bool) -> Result<()> {
+        utils::ensure_dir_exists("component", dest, &|_| ())?;
+        for entry in utils::read_dir("component", src)? {
+            let entry = entry.chain_err(|| ErrorKind::ReadingDirectory {
+                name: "component",
+                path: src.to_owned(),
+            })?;
+            let src = entry.path();
+            let dest = dest.join(entry.file_name());
+            let kind = entry.file_type().chain_err(|| ErrorKind::ReadingFile {
+                name: "component",
+                path: src.clone(),
+            })?;
+            if kind.is_dir() {
+                self.link_dir(&src, &dest, copy)?;
+            } else if kind.is_file() {
+                self.link_file(&src, &dest, copy)?;
+            } else {
+                utils::copy_file(&src, &dest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the file kept for `hash` if its contents no longer match,
+    /// so that installing it again puts a good copy in the store.
+    /// Installations linked to it keep their damaged copy.
+    pub fn remove_damaged(&self, hash: &str) -> Result<bool> {
+        let blob = self.blob_path(hash);
+        if !utils::is_file(&blob) || file_hash(&blob)? == hash {
+            return Ok(false);
+        }
+        utils::remove_file("store", &blob)?;
+        Ok(true)
+    }
+
+    /// Removes the files no installation links to any more, returning
+    /// how many were removed.
+    pub fn collect_garbage(&self) -> Result<u64> {
+        let mut removed = 0;
+        for (blob, _, links) in self.blobs()? {
+            if links == Some(1) {
+                utils::remove_file("store", &blob)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn stats(&self) -> Result<StoreStats> {
+        let mut stats = StoreStats::default();
+        for (_, size, links) in self.blobs()? {
+            stats.files += 1;
+            stats.size += size;
+            if let Some(links) = links {
+                stats.linked_size += size * links.saturating_sub(1);
+                stats.saved_size += size * links.saturating_sub(2);
+            }
+        }
+        Ok(stats)
+    }
+
+    // Every file in the store with its size and, where the platform
+    // can tell, its number of links
+    fn blobs(&self) -> Result<Vec<(PathBuf, u64, Option<u64>)>> {
+        let mut blobs = vec![];
+        if !utils::is_directory(&self.path) {
+            return Ok(blobs);
+        }
+
+        for dir in utils::read_dir("store", &self.path)? {
+            let dir = dir.chain_err(|| ErrorKind::ReadingDirectory {
+                name: "store",
+                path: self.path.clone(),
+            })?;
+            for blob in utils::read_dir("store", &dir.path())? {
+                let blob = blob.chain_err(|| ErrorKind::ReadingDirectory {
+                    name: "store",
+                    path: dir.path(),
+                })?;
+                // Files still being added belong to whoever is adding them
+                if blob.path().extension().map_or(false, |e| e == "partial") {
+                    continue;
+                }
+                let meta = fs::metadata(blob.path()).chain_err(|| ErrorKind::ReadingFile {
+                    name: "store",
+                    path: blob.path(),
+                })?;
+                let links = link_count(&blob.path(), &meta);
+                blobs.push((blob.path(), meta.len(), links));
+            }
+        }
+        Ok(blobs)
+    }
+}
+
+#[cfg(unix)]
+fn link_count(_path: &Path, meta: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.nlink())
+}
+
+#[cfg(windows)]
+fn link_count(path: &Path, _meta: &fs::Metadata) -> Option<u64> {
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+
+    let file = fs::File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    Some(u64::from(info.nNumberOfLinks))
+}
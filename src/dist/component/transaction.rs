@@ -6,18 +6,24 @@
 //! operations. If the Transaction is dropped without committing then
 //! it will *attempt* to roll back the transaction.
 //!
-//! FIXME: This uses ensure_dir_exists in some places but rollback
-//! does not remove any dirs created by it.
+//! Each change is written to a journal in the install prefix before it
+//! is made, so that if rustup is killed part way through, the next
+//! `recover` can roll the changes back, or finish committing them.
 
+use crate::dist::component::store::Store;
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 use crate::errors::*;
 use crate::utils::utils;
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
+const JOURNAL_FILE: &str = "rustup-journal";
+const COMMITTED: &str = "committed";
+
 /// A Transaction tracks changes to the file system, allowing them to
 /// be rolled back in case of an error. Instead of deleting or
 /// overwriting file, the old copies are moved to a temporary
@@ -27,14 +33,19 @@ use std::path::{Path, PathBuf};
 ///
 /// All operations that create files will automatically create any
 /// intermediate directories in the path to the file if they do not
-/// already exist.
+/// already exist. Rolling back removes them again.
 ///
 /// All operations that create files will fail if the destination
 /// already exists.
+///
+/// With a `Store`, the files that packages copy or move into the prefix
+/// are put in the store and hard linked from there instead.
 pub struct Transaction<'a> {
     prefix: InstallPrefix,
     changes: Vec<ChangedItem<'a>>,
+    journal: Journal,
     temp_cfg: &'a temp::Cfg,
+    store: Option<&'a Store>,
     notify_handler: &'a dyn Fn(Notification<'_>),
     committed: bool,
 }
@@ -46,30 +57,119 @@ impl<'a> Transaction<'a> {
         notify_handler: &'a dyn Fn(Notification<'_>),
     ) -> Self {
         Transaction {
+            journal: Journal::new(&prefix),
             prefix,
             changes: Vec::new(),
             temp_cfg,
+            store: None,
             notify_handler,
             committed: false,
         }
     }
 
+    /// Finishes a transaction that was interrupted in `prefix`, if there
+    /// is one. Transactions interrupted while committing are committed,
+    /// any others rolled back.
+    pub fn recover(
+        prefix: &InstallPrefix,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<()> {
+        let mut journal = Journal::new(prefix);
+        if !utils::is_file(&journal.path) {
+            return Ok(());
+        }
+
+        let (entries, committed) = journal.read()?;
+        if committed {
+            notify_handler(Notification::FinishingInterruptedTransaction(prefix.path()));
+            for entry in &entries {
+                entry.finish()?;
+            }
+            journal.remove(&[], prefix)
+        } else {
+            notify_handler(Notification::RollingBackInterruptedTransaction(
+                prefix.path(),
+            ));
+            for entry in entries.iter().rev() {
+                if let Err(e) = entry.roll_back(prefix) {
+                    notify_handler(Notification::NonFatalError(&e));
+                }
+            }
+            journal.remove(&entries, prefix)
+        }
+    }
+
+    /// Shares the files installed by this transaction through `store`.
+    pub fn use_store(&mut self, store: &'a Store) {
+        self.store = Some(store);
+    }
+
     /// Commit must be called for all successful transactions. If not
     /// called the transaction will be rolled back on drop.
     pub fn commit(mut self) {
         self.committed = true;
+
+        // From here on, an interrupted transaction is finished rather
+        // than rolled back
+        if let Err(e) = self.journal.commit() {
+            (self.notify_handler)(Notification::NonFatalError(&e));
+        }
+
+        // Dropping the transaction deletes the backups of removed files,
+        // after which the store can tell which of its files are unused
+        let store = self.store.take();
+        let notify_handler = self.notify_handler;
+        let prefix = self.prefix.clone();
+        let mut journal = std::mem::replace(&mut self.journal, Journal::new(&prefix));
+        drop(self);
+        if let Err(e) = journal.remove(&[], &prefix) {
+            notify_handler(Notification::NonFatalError(&e));
+        }
+        if let Some(store) = store {
+            if let Err(e) = store.collect_garbage() {
+                notify_handler(Notification::NonFatalError(&e));
+            }
+        }
     }
 
     fn change(&mut self, item: ChangedItem<'a>) {
         self.changes.push(item);
     }
 
+    fn journal_entries(&self) -> Vec<JournalEntry> {
+        self.changes
+            .iter()
+            .map(ChangedItem::journal_entry)
+            .collect()
+    }
+
+    // Creates the directories missing above `relpath`, so that rolling
+    // back removes them again
+    fn create_parent_dirs(&mut self, relpath: &Path) -> Result<()> {
+        let mut missing = vec![];
+        let mut parent = relpath.parent();
+        while let Some(dir) = parent {
+            if dir.as_os_str().is_empty() || utils::is_directory(&self.prefix.abs_path(dir)) {
+                break;
+            }
+            missing.push(dir.to_owned());
+            parent = dir.parent();
+        }
+        for dir in missing.into_iter().rev() {
+            let item = ChangedItem::create_dir(&self.prefix, dir, &mut self.journal)?;
+            self.change(item);
+        }
+        Ok(())
+    }
+
     /// Add a file at a relative path to the install prefix. Returns a
     /// `File` that may be used to subsequently write the
     /// contents.
     pub fn add_file(&mut self, component: &str, relpath: PathBuf) -> Result<File> {
         assert!(relpath.is_relative());
-        let (item, file) = ChangedItem::add_file(&self.prefix, component, relpath)?;
+        self.create_parent_dirs(&relpath)?;
+        let (item, file) =
+            ChangedItem::add_file(&self.prefix, component, relpath, &mut self.journal)?;
         self.change(item);
         Ok(file)
     }
@@ -77,7 +177,14 @@ impl<'a> Transaction<'a> {
     /// Copy a file to a relative path of the install prefix.
     pub fn copy_file(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::copy_file(&self.prefix, component, relpath, src)?;
+        self.create_parent_dirs(&relpath)?;
+        let journal = &mut self.journal;
+        let item = match self.store {
+            Some(store) => {
+                ChangedItem::link_file(&self.prefix, store, component, relpath, src, true, journal)?
+            }
+            None => ChangedItem::copy_file(&self.prefix, component, relpath, src, journal)?,
+        };
         self.change(item);
         Ok(())
     }
@@ -85,7 +192,14 @@ impl<'a> Transaction<'a> {
     /// Recursively copy a directory to a relative path of the install prefix.
     pub fn copy_dir(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::copy_dir(&self.prefix, component, relpath, src)?;
+        self.create_parent_dirs(&relpath)?;
+        let journal = &mut self.journal;
+        let item = match self.store {
+            Some(store) => {
+                ChangedItem::link_dir(&self.prefix, store, component, relpath, src, true, journal)?
+            }
+            None => ChangedItem::copy_dir(&self.prefix, component, relpath, src, journal)?,
+        };
         self.change(item);
         Ok(())
     }
@@ -93,7 +207,13 @@ impl<'a> Transaction<'a> {
     /// Remove a file from a relative path to the install prefix.
     pub fn remove_file(&mut self, component: &str, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::remove_file(&self.prefix, component, relpath, &self.temp_cfg)?;
+        let item = ChangedItem::remove_file(
+            &self.prefix,
+            component,
+            relpath,
+            &self.temp_cfg,
+            &mut self.journal,
+        )?;
         self.change(item);
         Ok(())
     }
@@ -102,7 +222,13 @@ impl<'a> Transaction<'a> {
     /// install prefix.
     pub fn remove_dir(&mut self, component: &str, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::remove_dir(&self.prefix, component, relpath, &self.temp_cfg)?;
+        let item = ChangedItem::remove_dir(
+            &self.prefix,
+            component,
+            relpath,
+            &self.temp_cfg,
+            &mut self.journal,
+        )?;
         self.change(item);
         Ok(())
     }
@@ -111,7 +237,9 @@ impl<'a> Transaction<'a> {
     /// the install prefix.
     pub fn write_file(&mut self, component: &str, relpath: PathBuf, content: String) -> Result<()> {
         assert!(relpath.is_relative());
-        let (item, mut file) = ChangedItem::add_file(&self.prefix, component, relpath.clone())?;
+        self.create_parent_dirs(&relpath)?;
+        let (item, mut file) =
+            ChangedItem::add_file(&self.prefix, component, relpath.clone(), &mut self.journal)?;
         self.change(item);
         utils::write_str(
             "component",
@@ -128,7 +256,9 @@ impl<'a> Transaction<'a> {
     /// This is used for arbitrarily manipulating a file.
     pub fn modify_file(&mut self, relpath: PathBuf) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::modify_file(&self.prefix, relpath, &self.temp_cfg)?;
+        self.create_parent_dirs(&relpath)?;
+        let item =
+            ChangedItem::modify_file(&self.prefix, relpath, &self.temp_cfg, &mut self.journal)?;
         self.change(item);
         Ok(())
     }
@@ -136,7 +266,20 @@ impl<'a> Transaction<'a> {
     /// Move a file to a relative path of the install prefix.
     pub fn move_file(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::move_file(&self.prefix, component, relpath, src)?;
+        self.create_parent_dirs(&relpath)?;
+        let journal = &mut self.journal;
+        let item = match self.store {
+            Some(store) => ChangedItem::link_file(
+                &self.prefix,
+                store,
+                component,
+                relpath,
+                src,
+                false,
+                journal,
+            )?,
+            None => ChangedItem::move_file(&self.prefix, component, relpath, src, journal)?,
+        };
         self.change(item);
         Ok(())
     }
@@ -144,7 +287,14 @@ impl<'a> Transaction<'a> {
     /// Recursively move a directory to a relative path of the install prefix.
     pub fn move_dir(&mut self, component: &str, relpath: PathBuf, src: &Path) -> Result<()> {
         assert!(relpath.is_relative());
-        let item = ChangedItem::move_dir(&self.prefix, component, relpath, src)?;
+        self.create_parent_dirs(&relpath)?;
+        let journal = &mut self.journal;
+        let item = match self.store {
+            Some(store) => {
+                ChangedItem::link_dir(&self.prefix, store, component, relpath, src, false, journal)?
+            }
+            None => ChangedItem::move_dir(&self.prefix, component, relpath, src, journal)?,
+        };
         self.change(item);
         Ok(())
     }
@@ -163,16 +313,20 @@ impl<'a> Drop for Transaction<'a> {
     fn drop(&mut self) {
         if !self.committed {
             (self.notify_handler)(Notification::RollingBack);
-            for item in self.changes.iter().rev() {
+            let entries = self.journal_entries();
+            for entry in entries.iter().rev() {
                 // ok_ntfy!(self.notify_handler,
                 //          Notification::NonFatalError,
-                match item.roll_back(&self.prefix) {
+                match entry.roll_back(&self.prefix) {
                     Ok(()) => {}
                     Err(e) => {
                         (self.notify_handler)(Notification::NonFatalError(&e));
                     }
                 }
             }
+            if let Err(e) = self.journal.remove(&entries, &self.prefix) {
+                (self.notify_handler)(Notification::NonFatalError(&e));
+            }
         }
     }
 }
@@ -188,28 +342,22 @@ enum ChangedItem<'a> {
     RemovedFile(PathBuf, temp::File<'a>),
     RemovedDir(PathBuf, temp::Dir<'a>),
     ModifiedFile(PathBuf, Option<temp::File<'a>>),
+    CreatedDir(PathBuf),
 }
 
 impl<'a> ChangedItem<'a> {
-    fn roll_back(&self, prefix: &InstallPrefix) -> Result<()> {
+    fn journal_entry(&self) -> JournalEntry {
         use self::ChangedItem::*;
         match self {
-            AddedFile(path) => utils::remove_file("component", &prefix.abs_path(path))?,
-            AddedDir(path) => utils::remove_dir("component", &prefix.abs_path(path), &|_| ())?,
-            RemovedFile(path, tmp) | ModifiedFile(path, Some(tmp)) => {
-                utils::rename_file("component", &tmp, &prefix.abs_path(path))?
-            }
-            RemovedDir(path, tmp) => {
-                utils::rename_dir("component", &tmp.join("bk"), &prefix.abs_path(path))?
-            }
-            ModifiedFile(path, None) => {
-                let abs_path = prefix.abs_path(path);
-                if utils::is_file(&abs_path) {
-                    utils::remove_file("component", &abs_path)?;
-                }
+            AddedFile(path) => JournalEntry::AddedFile(path.clone()),
+            AddedDir(path) => JournalEntry::AddedDir(path.clone()),
+            RemovedFile(path, tmp) => JournalEntry::RemovedFile(path.clone(), tmp.to_path_buf()),
+            RemovedDir(path, tmp) => JournalEntry::RemovedDir(path.clone(), tmp.to_path_buf()),
+            ModifiedFile(path, tmp) => {
+                JournalEntry::ModifiedFile(path.clone(), tmp.as_ref().map(|t| t.to_path_buf()))
             }
+            CreatedDir(path) => JournalEntry::CreatedDir(path.clone()),
         }
-        Ok(())
     }
     fn dest_abs_path(
         prefix: &InstallPrefix,
@@ -224,43 +372,91 @@ impl<'a> ChangedItem<'a> {
             }
             .into())
         } else {
-            if let Some(p) = abs_path.parent() {
-                utils::ensure_dir_exists("component", p, &|_| ())?;
-            }
             Ok(abs_path)
         }
     }
-    fn add_file(prefix: &InstallPrefix, component: &str, relpath: PathBuf) -> Result<(Self, File)> {
+    fn create_dir(prefix: &InstallPrefix, relpath: PathBuf, journal: &mut Journal) -> Result<Self> {
+        let abs_path = prefix.abs_path(&relpath);
+        let item = ChangedItem::CreatedDir(relpath);
+        journal.record(&item)?;
+        utils::ensure_dir_exists("component", &abs_path, &|_| ())?;
+        Ok(item)
+    }
+    fn add_file(
+        prefix: &InstallPrefix,
+        component: &str,
+        relpath: PathBuf,
+        journal: &mut Journal,
+    ) -> Result<(Self, File)> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedFile(relpath);
+        journal.record(&item)?;
         let file = File::create(&abs_path)
             .chain_err(|| format!("error creating file '{}'", abs_path.display()))?;
-        Ok((ChangedItem::AddedFile(relpath), file))
+        Ok((item, file))
     }
     fn copy_file(
         prefix: &InstallPrefix,
         component: &str,
         relpath: PathBuf,
         src: &Path,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedFile(relpath);
+        journal.record(&item)?;
         utils::copy_file(src, &abs_path)?;
-        Ok(ChangedItem::AddedFile(relpath))
+        Ok(item)
     }
     fn copy_dir(
         prefix: &InstallPrefix,
         component: &str,
         relpath: PathBuf,
         src: &Path,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedDir(relpath);
+        journal.record(&item)?;
         utils::copy_dir(src, &abs_path, &|_| ())?;
-        Ok(ChangedItem::AddedDir(relpath))
+        Ok(item)
+    }
+    fn link_file(
+        prefix: &InstallPrefix,
+        store: &Store,
+        component: &str,
+        relpath: PathBuf,
+        src: &Path,
+        copy: bool,
+        journal: &mut Journal,
+    ) -> Result<Self> {
+        let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedFile(relpath);
+        journal.record(&item)?;
+        store.link_file(src, &abs_path, copy)?;
+        Ok(item)
+    }
+    fn link_dir(
+        prefix: &InstallPrefix,
+        store: &Store,
+        component: &str,
+        relpath: PathBuf,
+        src: &Path,
+        copy: bool,
+        journal: &mut Journal,
+    ) -> Result<Self> {
+        let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedDir(relpath);
+        journal.record(&item)?;
+        store.link_dir(src, &abs_path, copy)?;
+        Ok(item)
     }
     fn remove_file(
         prefix: &InstallPrefix,
         component: &str,
         relpath: PathBuf,
         temp_cfg: &'a temp::Cfg,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         let backup = temp_cfg.new_file()?;
@@ -271,8 +467,11 @@ impl<'a> ChangedItem<'a> {
             }
             .into())
         } else {
-            utils::rename_file("component", &abs_path, &backup)?;
-            Ok(ChangedItem::RemovedFile(relpath, backup))
+            let backup_path = backup.to_path_buf();
+            let item = ChangedItem::RemovedFile(relpath, backup);
+            journal.record(&item)?;
+            utils::rename_file("component", &abs_path, &backup_path)?;
+            Ok(item)
         }
     }
     fn remove_dir(
@@ -280,6 +479,7 @@ impl<'a> ChangedItem<'a> {
         component: &str,
         relpath: PathBuf,
         temp_cfg: &'a temp::Cfg,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
         let backup = temp_cfg.new_directory()?;
@@ -290,46 +490,278 @@ impl<'a> ChangedItem<'a> {
             }
             .into())
         } else {
-            utils::rename_dir("component", &abs_path, &backup.join("bk"))?;
-            Ok(ChangedItem::RemovedDir(relpath, backup))
+            let backup_path = backup.join("bk");
+            let item = ChangedItem::RemovedDir(relpath, backup);
+            journal.record(&item)?;
+            utils::rename_dir("component", &abs_path, &backup_path)?;
+            Ok(item)
         }
     }
     fn modify_file(
         prefix: &InstallPrefix,
         relpath: PathBuf,
         temp_cfg: &'a temp::Cfg,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = prefix.abs_path(&relpath);
 
-        if utils::is_file(&abs_path) {
+        // The file only changes after this returns, so the backup is
+        // complete by the time the journal mentions it
+        let item = if utils::is_file(&abs_path) {
             let backup = temp_cfg.new_file()?;
             utils::copy_file(&abs_path, &backup)?;
-            Ok(ChangedItem::ModifiedFile(relpath, Some(backup)))
+            ChangedItem::ModifiedFile(relpath, Some(backup))
         } else {
-            if let Some(p) = abs_path.parent() {
-                utils::ensure_dir_exists("component", p, &|_| {})?;
-            }
-            Ok(ChangedItem::ModifiedFile(relpath, None))
-        }
+            ChangedItem::ModifiedFile(relpath, None)
+        };
+        journal.record(&item)?;
+        Ok(item)
     }
     fn move_file(
         prefix: &InstallPrefix,
         component: &str,
         relpath: PathBuf,
         src: &Path,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedFile(relpath);
+        journal.record(&item)?;
         utils::rename_file("component", src, &abs_path)?;
-        Ok(ChangedItem::AddedFile(relpath))
+        Ok(item)
     }
     fn move_dir(
         prefix: &InstallPrefix,
         component: &str,
         relpath: PathBuf,
         src: &Path,
+        journal: &mut Journal,
     ) -> Result<Self> {
         let abs_path = ChangedItem::dest_abs_path(prefix, component, &relpath)?;
+        let item = ChangedItem::AddedDir(relpath);
+        journal.record(&item)?;
         utils::rename_dir("component", src, &abs_path)?;
-        Ok(ChangedItem::AddedDir(relpath))
+        Ok(item)
+    }
+}
+
+/// A change as it is written to the journal, with the absolute paths
+/// of its backups. The change may not have been made yet, so rolling
+/// it back only undoes what it finds was done.
+#[derive(Debug, PartialEq)]
+enum JournalEntry {
+    AddedFile(PathBuf),
+    AddedDir(PathBuf),
+    RemovedFile(PathBuf, PathBuf),
+    RemovedDir(PathBuf, PathBuf),
+    ModifiedFile(PathBuf, Option<PathBuf>),
+    CreatedDir(PathBuf),
+}
+
+impl JournalEntry {
+    fn encode(&self) -> String {
+        use self::JournalEntry::*;
+        let (kind, path, backup) = match self {
+            AddedFile(path) => ("added-file", path, None),
+            AddedDir(path) => ("added-dir", path, None),
+            RemovedFile(path, tmp) => ("removed-file", path, Some(tmp)),
+            RemovedDir(path, tmp) => ("removed-dir", path, Some(tmp)),
+            ModifiedFile(path, tmp) => ("modified-file", path, tmp.as_ref()),
+            CreatedDir(path) => ("created-dir", path, None),
+        };
+        match backup {
+            Some(tmp) => format!("{}\t{}\t{}", kind, path.display(), tmp.display()),
+            None => format!("{}\t{}", kind, path.display()),
+        }
+    }
+    fn decode(line: &str) -> Option<Self> {
+        use self::JournalEntry::*;
+        let mut fields = line.split('\t');
+        let kind = fields.next()?;
+        let path = PathBuf::from(fields.next()?);
+        let backup = fields.next().map(PathBuf::from);
+        Some(match kind {
+            "added-file" => AddedFile(path),
+            "added-dir" => AddedDir(path),
+            "removed-file" => RemovedFile(path, backup?),
+            "removed-dir" => RemovedDir(path, backup?),
+            "modified-file" => ModifiedFile(path, backup),
+            "created-dir" => CreatedDir(path),
+            _ => return None,
+        })
+    }
+    fn roll_back(&self, prefix: &InstallPrefix) -> Result<()> {
+        use self::JournalEntry::*;
+        match self {
+            AddedFile(path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(&abs_path) {
+                    utils::remove_file("component", &abs_path)?;
+                }
+            }
+            AddedDir(path) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::path_exists(&abs_path) {
+                    utils::remove_dir("component", &abs_path, &|_| ())?;
+                }
+            }
+            RemovedFile(path, tmp) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(tmp) && !utils::path_exists(&abs_path) {
+                    utils::rename_file("component", tmp, &abs_path)?;
+                }
+            }
+            RemovedDir(path, tmp) => {
+                let abs_path = prefix.abs_path(path);
+                let tmp = tmp.join("bk");
+                if utils::path_exists(&tmp) && !utils::path_exists(&abs_path) {
+                    utils::rename_dir("component", &tmp, &abs_path)?;
+                }
+            }
+            ModifiedFile(path, Some(tmp)) => {
+                if utils::is_file(tmp) {
+                    utils::rename_file("component", tmp, &prefix.abs_path(path))?;
+                }
+            }
+            ModifiedFile(path, None) => {
+                let abs_path = prefix.abs_path(path);
+                if utils::is_file(&abs_path) {
+                    utils::remove_file("component", &abs_path)?;
+                }
+            }
+            CreatedDir(path) => remove_empty_dir(&prefix.abs_path(path))?,
+        }
+        Ok(())
+    }
+    // Deletes the backups of a committed change
+    fn finish(&self) -> Result<()> {
+        use self::JournalEntry::*;
+        match self {
+            RemovedFile(_, tmp) | ModifiedFile(_, Some(tmp)) if utils::is_file(tmp) => {
+                utils::remove_file("backup", tmp)
+            }
+            RemovedDir(_, tmp) if utils::path_exists(tmp) => {
+                utils::remove_dir("backup", tmp, &|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Removes the directory at `path` if it exists and is empty
+fn remove_empty_dir(path: &Path) -> Result<()> {
+    if utils::is_directory(path) && utils::read_dir("component", path)?.next().is_none() {
+        utils::remove_dir("component", path, &|_| ())?;
+    }
+    Ok(())
+}
+
+/// The file in the prefix that changes are written to before they are
+/// made. It is created along with any directories it needs when the
+/// first change is recorded, and removed once the transaction is over.
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+    // The directories created for the journal
+    dirs: Vec<PathBuf>,
+}
+
+impl Journal {
+    fn new(prefix: &InstallPrefix) -> Self {
+        Journal {
+            path: prefix.manifest_file(JOURNAL_FILE),
+            file: None,
+            dirs: vec![],
+        }
+    }
+
+    fn record(&mut self, item: &ChangedItem<'_>) -> Result<()> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+        self.write_line(&item.journal_entry().encode())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.file.is_some() {
+            self.write_line(COMMITTED)?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<()> {
+        let mut missing = vec![];
+        let mut parent = self.path.parent();
+        while let Some(dir) = parent {
+            if utils::is_directory(dir) {
+                break;
+            }
+            missing.push(dir.to_owned());
+            parent = dir.parent();
+        }
+        for dir in missing.into_iter().rev() {
+            utils::ensure_dir_exists("journal", &dir, &|_| ())?;
+            self.dirs.push(dir);
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .chain_err(|| ErrorKind::WritingFile {
+                name: "journal",
+                path: self.path.clone(),
+            })?;
+        self.file = Some(file);
+        for dir in self.dirs.clone() {
+            self.write_line(&JournalEntry::CreatedDir(dir).encode())?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let path = &self.path;
+        let file = self.file.as_mut().expect("journal is open");
+        writeln!(file, "{}", line)
+            .and_then(|_| file.sync_data())
+            .chain_err(|| ErrorKind::WritingFile {
+                name: "journal",
+                path: path.clone(),
+            })
+    }
+
+    // The changes in the journal, and whether they were committed. A
+    // line cut short by a crash is for a change that wasn't made yet.
+    fn read(&self) -> Result<(Vec<JournalEntry>, bool)> {
+        let content = utils::read_file("journal", &self.path)?;
+        let mut entries = vec![];
+        let mut committed = false;
+        for line in content.lines() {
+            if line == COMMITTED {
+                committed = true;
+            } else if let Some(entry) = JournalEntry::decode(line) {
+                entries.push(entry);
+            }
+        }
+        Ok((entries, committed))
+    }
+
+    // Removes the journal, along with the directories it created and
+    // those of the rolled back `entries` that are left empty
+    fn remove(&mut self, entries: &[JournalEntry], prefix: &InstallPrefix) -> Result<()> {
+        self.file = None;
+        if utils::is_file(&self.path) {
+            utils::remove_file("journal", &self.path)?;
+        }
+        for dir in self.dirs.iter().rev() {
+            remove_empty_dir(dir)?;
+        }
+        for entry in entries.iter().rev() {
+            if let JournalEntry::CreatedDir(path) = entry {
+                remove_empty_dir(&prefix.abs_path(path))?;
+            }
+        }
+        Ok(())
     }
 }
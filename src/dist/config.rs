@@ -1,3 +1,4 @@
+use super::dist::Profile;
 use super::manifest::Component;
 use crate::errors::*;
 use crate::utils::toml_utils::*;
@@ -9,6 +10,7 @@ pub const DEFAULT_CONFIG_VERSION: &str = "1";
 pub struct Config {
     pub config_version: String,
     pub components: Vec<Component>,
+    pub profile: Option<Profile>,
 }
 
 impl Config {
@@ -22,9 +24,14 @@ impl Config {
         let components =
             Self::toml_to_components(components, &format!("{}{}.", path, "components"))?;
 
+        let profile = get_opt_string(&mut table, "profile", path)?
+            .map(|p| p.parse())
+            .transpose()?;
+
         Ok(Config {
             config_version: version,
             components,
+            profile,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
@@ -37,6 +44,12 @@ impl Config {
         if !components.is_empty() {
             result.insert("components".to_owned(), toml::Value::Array(components));
         }
+        if let Some(profile) = self.profile {
+            result.insert(
+                "profile".to_owned(),
+                toml::Value::String(profile.to_string()),
+            );
+        }
         result
     }
 
@@ -80,6 +93,7 @@ impl Default for Config {
         Config {
             config_version: DEFAULT_CONFIG_VERSION.to_owned(),
             components: Vec::new(),
+            profile: None,
         }
     }
 }
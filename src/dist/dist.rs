@@ -14,6 +14,7 @@ use regex::Regex;
 use std::env;
 use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 
 pub static DEFAULT_DIST_SERVER: &str = "https://static.rust-lang.org";
 
@@ -54,6 +55,14 @@ pub struct ToolchainDesc {
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct TargetTriple(String);
 
+// A named set of components to install into a fresh toolchain.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Profile {
+    Minimal,
+    Default,
+    Complete,
+}
+
 // These lists contain the targets known to rustup, and used to build
 // the PartialTargetTriple.
 
@@ -452,6 +461,64 @@ impl<'a> Manifest<'a> {
     }
 }
 
+impl Profile {
+    pub fn names() -> &'static [&'static str] {
+        &["minimal", "default", "complete"]
+    }
+
+    pub fn default_name() -> &'static str {
+        "default"
+    }
+
+    // The packages making up each profile, for manifests which don't
+    // carry their own `[profiles]` table. `None` means every component
+    // available for the target.
+    pub fn builtin_packages(self) -> Option<&'static [&'static str]> {
+        match self {
+            Profile::Minimal => Some(&["rustc", "cargo", "rust-std", "rust-mingw"]),
+            Profile::Default => Some(&[
+                "rustc",
+                "cargo",
+                "rust-std",
+                "rust-mingw",
+                "rust-docs",
+                "rustfmt",
+                "clippy",
+            ]),
+            Profile::Complete => None,
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "minimal" => Ok(Profile::Minimal),
+            "default" => Ok(Profile::Default),
+            "complete" => Ok(Profile::Complete),
+            _ => Err(ErrorKind::InvalidProfile(name.to_owned()).into()),
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Default
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Minimal => write!(f, "minimal"),
+            Profile::Default => write!(f, "default"),
+            Profile::Complete => write!(f, "complete"),
+        }
+    }
+}
+
 impl fmt::Display for TargetTriple {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
@@ -493,14 +560,16 @@ impl fmt::Display for ToolchainDesc {
 }
 
 // Installs or updates a toolchain from a dist server. If an initial
-// install then it will be installed with the default components. If
-// an upgrade then all the existing components will be upgraded.
+// install then it will be installed with the components of `profile`
+// (or the default components if there's no profile). If an upgrade
+// then all the existing components will be upgraded.
 //
 // Returns the manifest's hash if anything changed.
 pub fn update_from_dist<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
     prefix: &InstallPrefix,
     add: &[Component],
     remove: &[Component],
@@ -512,6 +581,7 @@ pub fn update_from_dist<'a>(
         download,
         update_hash,
         toolchain,
+        profile,
         prefix,
         add,
         remove,
@@ -533,6 +603,7 @@ pub fn update_from_dist_<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
     prefix: &InstallPrefix,
     add: &[Component],
     remove: &[Component],
@@ -540,12 +611,21 @@ pub fn update_from_dist_<'a>(
 ) -> Result<Option<String>> {
     let toolchain_str = toolchain.to_string();
     let manifestation = Manifestation::open(prefix.clone(), toolchain.target.clone())?;
+    // Undo or finish what an earlier rustup was doing when it was
+    // killed, before looking at the installation
+    manifestation.recover(&download.notify_handler)?;
 
     let changes = Changes {
         add_extensions: add.to_owned(),
         remove_extensions: remove.to_owned(),
+        reinstall_components: vec![],
+        profile,
     };
 
+    // Components being added need the manifest even if the toolchain is
+    // otherwise up to date
+    let update_hash = if add.is_empty() { update_hash } else { None };
+
     // TODO: Add a notification about which manifest version is going to be used
     (download.notify_handler)(Notification::DownloadingManifest(&toolchain_str));
     match dl_v2_manifest(download, update_hash, toolchain) {
@@ -554,6 +634,20 @@ pub fn update_from_dist_<'a>(
                 &m.date,
                 m.get_rust_version().ok(),
             ));
+            // Installations keep the profile they were created with
+            let profile = match manifestation.read_config()? {
+                Some(config) => config.profile,
+                None => profile,
+            };
+            let changes = Changes {
+                add_extensions: resolve_components(&m, toolchain, profile, add)?,
+                ..changes
+            };
+            // Keep the installation being replaced so it can be rolled back to
+            let snapshot = match manifestation.load_manifest()? {
+                Some(ref old) if *old != m => manifestation.snapshot()?,
+                _ => None,
+            };
             return match manifestation.update(
                 &m,
                 changes,
@@ -563,7 +657,12 @@ pub fn update_from_dist_<'a>(
                 &toolchain.manifest_name(),
             )? {
                 UpdateStatus::Unchanged => Ok(None),
-                UpdateStatus::Changed => Ok(Some(hash)),
+                UpdateStatus::Changed => {
+                    if let Some(snapshot) = snapshot {
+                        manifestation.save_snapshot(snapshot, &download.notify_handler)?;
+                    }
+                    Ok(Some(hash))
+                }
             };
         }
         Ok(None) => return Ok(None),
@@ -593,12 +692,7 @@ pub fn update_from_dist_<'a>(
             });
         }
     };
-    match manifestation.update_v1(
-        &manifest,
-        update_hash,
-        &download.temp_cfg,
-        &download.notify_handler,
-    ) {
+    match manifestation.update_v1(&manifest, update_hash, &download) {
         Ok(None) => Ok(None),
         Ok(Some(hash)) => Ok(Some(hash)),
         e @ Err(Error(crate::ErrorKind::DownloadNotExists { .. }, _)) => e.chain_err(|| {
@@ -611,13 +705,125 @@ pub fn update_from_dist_<'a>(
     }
 }
 
-fn dl_v2_manifest<'a>(
+// Resolves components requested for `toolchain` against its manifest,
+// following renames and falling back to the wildcard target, the way
+// `rustup component add` does. Those that `profile` requires, like the
+// host's rust-std, are installed anyway and left out.
+fn resolve_components(
+    manifest: &ManifestV2,
+    toolchain: &ToolchainDesc,
+    profile: Option<Profile>,
+    components: &[Component],
+) -> Result<Vec<Component>> {
+    let rust_pkg = manifest.get_package("rust")?;
+    let targ_pkg = rust_pkg.get_target(Some(&toolchain.target))?;
+    let is_known =
+        |c: &Component| targ_pkg.extensions.contains(c) || targ_pkg.components.contains(c);
+    let required = manifest.get_required_components(profile, &toolchain.target)?;
+
+    let mut result = Vec::new();
+    for component in components {
+        let component = manifest
+            .rename_component(component)
+            .unwrap_or_else(|| component.clone());
+        let component = if is_known(&component) {
+            component
+        } else if is_known(&component.wildcard()) {
+            component.wildcard()
+        } else {
+            return Err(ErrorKind::UnknownComponent(
+                toolchain.to_string(),
+                component.description(manifest),
+            )
+            .into());
+        };
+        if !required.contains(&component) {
+            result.push(component);
+        }
+    }
+
+    Ok(result)
+}
+
+/// How many days of archived nightlies `find_nightly_with_components`
+/// looks through before giving up.
+pub const NIGHTLY_SEARCH_DAYS: u32 = 30;
+
+/// Walks back from the latest nightly through the archived dated
+/// manifests until one has all of `components` available for the target
+/// of `toolchain`, returning that nightly's dated toolchain.
+pub fn find_nightly_with_components(
+    download: DownloadCfg<'_>,
+    toolchain: &ToolchainDesc,
+    components: &[String],
+) -> Result<ToolchainDesc> {
+    let mut desc = toolchain.clone();
+    for _ in 0..NIGHTLY_SEARCH_DAYS {
+        let manifest = match dl_v2_manifest(download, None, &desc) {
+            Ok(Some((m, _))) => Some(m),
+            Ok(None) => unreachable!("no update hash to match"),
+            // No nightly was published that day
+            Err(Error(ErrorKind::DownloadNotExists { .. }, _)) if desc.date.is_some() => None,
+            Err(e) => return Err(e),
+        };
+
+        let date = match manifest {
+            Some(m) => {
+                let missing: Vec<String> = components
+                    .iter()
+                    .filter(|c| !m.is_component_available(c, &desc.target))
+                    .cloned()
+                    .collect();
+                if missing.is_empty() {
+                    (download.notify_handler)(Notification::FoundNightlyWithComponents(&m.date));
+                    desc.date = Some(m.date);
+                    return Ok(desc);
+                }
+                (download.notify_handler)(Notification::SkippingNightlyMissingComponents(
+                    &m.date, &missing,
+                ));
+                m.date
+            }
+            None => {
+                let date = desc
+                    .date
+                    .take()
+                    .expect("only dated nightlies can be missing");
+                (download.notify_handler)(Notification::NightlyMissing(&date));
+                date
+            }
+        };
+        desc.date = Some(previous_date(&date)?);
+    }
+
+    Err(ErrorKind::NoNightlyWithComponents(components.to_vec(), NIGHTLY_SEARCH_DAYS).into())
+}
+
+// Returns the day before `date`, both in YYYY-MM-DD format.
+fn previous_date(date: &str) -> Result<String> {
+    let tm = time::strptime(date, "%Y-%m-%d")
+        .chain_err(|| format!("invalid manifest date: '{}'", date))?;
+    let day_before = time::at_utc(tm.to_timespec() - time::Duration::days(1));
+    Ok(time::strftime("%Y-%m-%d", &day_before).expect("valid date format"))
+}
+
+// Downloads the v2 manifest for `toolchain`, or only its hash if that
+// matches the one in `update_hash`, in which case there's nothing new
+// and `None` is returned.
+pub fn dl_v2_manifest<'a>(
     download: DownloadCfg<'a>,
     update_hash: Option<&Path>,
     toolchain: &ToolchainDesc,
 ) -> Result<Option<(ManifestV2, String)>> {
-    let manifest_url = toolchain.manifest_v2_url(download.dist_root);
-    let manifest_dl_res = download.download_and_check(&manifest_url, update_hash, ".toml");
+    let manifest_urls: Vec<_> = download
+        .dist_roots
+        .iter()
+        .map(|root| toolchain.manifest_v2_url(root))
+        .collect();
+    let name = format!("the manifest for '{}'", toolchain.manifest_name());
+    let manifest_dl_res = download.with_fallback(&name, &manifest_urls, |url| {
+        download.download_and_check(url, update_hash, ".toml")
+    });
 
     if let Ok(manifest_dl) = manifest_dl_res {
         // Downloaded ok!
@@ -640,7 +846,14 @@ fn dl_v2_manifest<'a>(
 }
 
 fn dl_v1_manifest<'a>(download: DownloadCfg<'a>, toolchain: &ToolchainDesc) -> Result<Vec<String>> {
-    let root_url = toolchain.package_dir(download.dist_root);
+    // The installers are downloaded from whichever dist server has them,
+    // starting with the first
+    let root_url = toolchain.package_dir(
+        download
+            .dist_roots
+            .first()
+            .map_or(DEFAULT_DIST_ROOT, String::as_str),
+    );
 
     if !["nightly", "beta", "stable"].contains(&&*toolchain.channel) {
         // This is an explicit version. In v1 there was no manifest,
@@ -652,8 +865,15 @@ fn dl_v1_manifest<'a>(download: DownloadCfg<'a>, toolchain: &ToolchainDesc) -> R
         return Ok(vec![installer_name]);
     }
 
-    let manifest_url = toolchain.manifest_v1_url(download.dist_root);
-    let manifest_dl = download.download_and_check(&manifest_url, None, "")?;
+    let manifest_urls: Vec<_> = download
+        .dist_roots
+        .iter()
+        .map(|root| toolchain.manifest_v1_url(root))
+        .collect();
+    let name = format!("the legacy manifest for '{}'", toolchain.manifest_name());
+    let manifest_dl = download.with_fallback(&name, &manifest_urls, |url| {
+        download.download_and_check(url, None, "")
+    })?;
     let (manifest_file, _) = manifest_dl.unwrap();
     let manifest_str = utils::read_file("manifest", &manifest_file)?;
     let urls = manifest_str
@@ -1,21 +1,72 @@
+use crate::dist::cache;
+use crate::dist::component::Store;
+use crate::dist::dist::DEFAULT_DIST_ROOT;
 use crate::dist::notifications::*;
+use crate::dist::signatures::{self, SignatureCheck};
 use crate::dist::temp;
 use crate::errors::*;
 use crate::utils::utils;
+use crate::utils::Notification as Un;
+use download::{Backend, DownloadOptions, Throttle};
 use sha2::{Digest, Sha256};
 use url::Url;
 
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
 use std::fs;
+use std::io::{self, Read};
+use std::iter;
 use std::ops;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 const UPDATE_HASH_LEN: usize = 20;
 
+/// The number of component packages downloaded at the same time.
+pub const CONCURRENT_DOWNLOADS: usize = 4;
+
+/// How many downloaded chunks may wait to be unpacked before the
+/// download waits for the unpacking to catch up.
+const UNPACK_QUEUE_CHUNKS: usize = 64;
+
+/// Unpacks a package from a stream of its contents. If a download fails
+/// part way through, it is called again with the contents from another
+/// dist server, and must replace whatever it unpacked the first time.
+pub type Unpacker = Arc<dyn Fn(&mut dyn Read) -> Result<()> + Send + Sync>;
+
+/// A component package to download, and how to unpack it.
+pub struct PackageDownload {
+    pub name: String,
+    /// Where the package is on each dist server, in the order they are
+    /// tried
+    pub urls: Vec<Url>,
+    pub hash: String,
+    pub unpack: Unpacker,
+}
+
 #[derive(Copy, Clone)]
 pub struct DownloadCfg<'a> {
-    pub dist_root: &'a str,
+    /// The `dist` directory of each dist server, in the order they are
+    /// tried
+    pub dist_roots: &'a [String],
     pub temp_cfg: &'a temp::Cfg,
     pub download_dir: &'a PathBuf,
+    pub gpg_key: &'a str,
+    pub signature_check: SignatureCheck,
+    /// What files are downloaded with
+    pub backend: &'a Arc<dyn Backend>,
+    /// How long packages are kept in `download_dir` after they are
+    /// installed. They are removed straight away when this is `None`.
+    pub retention: Option<Duration>,
+    /// Where installed files are shared between toolchains, if they are
+    pub store: Option<&'a Store>,
+    /// How to download. Its rate limit covers all the downloads running
+    /// at once.
+    pub options: &'a DownloadOptions,
     pub notify_handler: &'a dyn Fn(Notification<'_>),
 }
 
@@ -40,58 +91,174 @@ impl<'a> DownloadCfg<'a> {
         utils::ensure_dir_exists("Download Directory", &self.download_dir, &|n| {
             (self.notify_handler)(n.into())
         })?;
-        let target_file = self.download_dir.join(Path::new(hash));
-
-        if target_file.exists() {
-            let cached_result = file_hash(&target_file)?;
-            if hash == cached_result {
-                (self.notify_handler)(Notification::FileAlreadyDownloaded);
-                (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
-                return Ok(File { path: target_file });
-            } else {
-                (self.notify_handler)(Notification::CachedFileChecksumFailed);
-                fs::remove_file(&target_file).chain_err(|| "cleaning up previous download")?;
-            }
-        }
+        let _lock = cache::lock_download(self.download_dir, hash, &|n| {
+            (self.notify_handler)(n.into())
+        })?;
+        download_file(
+            self.download_dir,
+            url,
+            hash,
+            &**self.backend,
+            self.options,
+            self.notify_handler,
+        )
+    }
 
-        let partial_file_path = target_file.with_file_name(
-            target_file
-                .file_name()
-                .map(|s| s.to_str().unwrap_or("_"))
-                .unwrap_or("_")
-                .to_owned()
-                + ".partial",
-        );
+    /// Downloads the packages of several components at once, unpacking
+    /// each one as it arrives. The packages are cached in
+    /// `self.download_dir` like `download` does; those already in the
+    /// cache, or partly downloaded to it, are instead downloaded and
+    /// unpacked the same way as `download`, resuming partial downloads.
+    /// At most `CONCURRENT_DOWNLOADS` packages are downloaded at a time,
+    /// with progress reported per component. Each hash is verified
+    /// before this returns. If any download fails, no new ones are
+    /// started and the first error is returned.
+    pub fn download_components(&self, downloads: Vec<PackageDownload>) -> Result<()> {
+        utils::ensure_dir_exists("Download Directory", self.download_dir, &|n| {
+            (self.notify_handler)(n.into())
+        })?;
 
-        let mut hasher = Sha256::new();
+        let names: Vec<_> = downloads.iter().map(|d| d.name.clone()).collect();
+        let queue = Arc::new(Mutex::new(
+            downloads.into_iter().enumerate().collect::<VecDeque<_>>(),
+        ));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let worker_count = cmp::min(names.len(), CONCURRENT_DOWNLOADS);
+        // The workers share the rate limit, however many are running
+        let mut options = self.options.clone();
+        if let Some(rate) = options.max_rate.take() {
+            options.shared_throttle = Some(Arc::new(Throttle::new(rate)));
+            (self.notify_handler)(Notification::Utils(Un::DownloadRateLimited(rate)));
+        }
 
-        utils::download_file_with_resume(
-            &url,
-            &partial_file_path,
-            Some(&mut hasher),
-            true,
-            &|n| (self.notify_handler)(n.into()),
-        )?;
+        // The notification handler can't be shared with other threads, so
+        // the workers send their notifications back here.
+        let workers = (0..worker_count)
+            .map(|_| {
+                let queue = queue.clone();
+                let cancelled = cancelled.clone();
+                let tx = tx.clone();
+                let download_dir = self.download_dir.clone();
+                let options = options.clone();
+                let backend = self.backend.clone();
+                thread::spawn(move || loop {
+                    if cancelled.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let (i, download) = match queue.lock().unwrap().pop_front() {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let result =
+                        download_and_unpack(&download_dir, download, &*backend, &options, &|n| {
+                            if let Some(event) = WorkerEvent::from_notification(n) {
+                                let _ = tx.send((i, event));
+                            }
+                        });
+                    let _ = tx.send((i, WorkerEvent::Finished(Box::new(result))));
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
 
-        let actual_hash = format!("{:x}", hasher.result());
+        let mut error = None;
+        for (i, event) in rx {
+            let name = &names[i];
+            let n = match event {
+                WorkerEvent::DownloadingFile(ref url, ref path) => {
+                    Notification::Utils(Un::DownloadingFile(url, path))
+                }
+                WorkerEvent::ContentLength(len) => {
+                    Notification::ComponentDownloadContentLength(name, len)
+                }
+                WorkerEvent::DataReceived(len) => {
+                    Notification::ComponentDownloadDataReceived(name, len)
+                }
+                WorkerEvent::ResumingPartialDownload => {
+                    Notification::Utils(Un::ResumingPartialDownload)
+                }
+                WorkerEvent::RetryingDownload(ref url, attempt, delay) => {
+                    Notification::Utils(Un::RetryingDownload(url, attempt, delay))
+                }
+                WorkerEvent::DownloadRestarted(ref url) => {
+                    Notification::Utils(Un::DownloadRestarted(url))
+                }
+                WorkerEvent::UsingBackend(ref name) => Notification::Utils(Un::UsingBackend(name)),
+                WorkerEvent::FileAlreadyDownloaded => Notification::FileAlreadyDownloaded,
+                WorkerEvent::CachedFileChecksumFailed => Notification::CachedFileChecksumFailed,
+                WorkerEvent::ChecksumValid(ref url) => Notification::ChecksumValid(url),
+                WorkerEvent::TryingNextDistServer(ref url) => {
+                    Notification::TryingNextDistServer(url)
+                }
+                WorkerEvent::DownloadedFrom(ref url) => Notification::DownloadedFrom(name, url),
+                WorkerEvent::WaitingForLock(ref path, pid) => {
+                    Notification::Utils(Un::WaitingForLock(path, pid))
+                }
+                WorkerEvent::Finished(result) => {
+                    if let Err(e) = *result {
+                        cancelled.store(true, Ordering::SeqCst);
+                        if error.is_none() {
+                            error = Some(Error::with_chain(
+                                e,
+                                ErrorKind::ComponentDownloadFailed(name.clone()),
+                            ));
+                        }
+                    }
+                    Notification::ComponentDownloadFinished(name)
+                }
+            };
+            (self.notify_handler)(n);
+        }
 
-        if hash != actual_hash {
-            // Incorrect hash
-            Err(ErrorKind::ChecksumFailed {
-                url: url.to_string(),
-                expected: hash.to_string(),
-                calculated: actual_hash,
+        for worker in workers {
+            if worker.join().is_err() && error.is_none() {
+                error = Some("component download thread panicked".into());
             }
-            .into())
-        } else {
-            (self.notify_handler)(Notification::ChecksumValid(&url.to_string()));
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-            utils::rename_file("downloaded", &partial_file_path, &target_file)?;
-            Ok(File { path: target_file })
+    /// The url of the file published at `url` on each dist server, in
+    /// the order they are tried. A url that isn't on a dist server is
+    /// only tried as it is.
+    pub fn dist_urls(&self, url: &str) -> Vec<String> {
+        let path = iter::once(DEFAULT_DIST_ROOT)
+            .chain(self.dist_roots.iter().map(String::as_str))
+            .find_map(|root| url.strip_prefix(root).filter(|p| p.starts_with('/')));
+        match path {
+            Some(path) if !self.dist_roots.is_empty() => self
+                .dist_roots
+                .iter()
+                .map(|root| format!("{}{}", root, path))
+                .collect(),
+            _ => vec![url.to_owned()],
         }
     }
 
+    /// Calls `f` with each of `urls` in turn until it succeeds, moving on
+    /// to the next dist server when one doesn't have the file `name`,
+    /// can't be reached, or sends the wrong contents.
+    pub fn with_fallback<U: AsRef<str>, T>(
+        &self,
+        name: &str,
+        urls: &[U],
+        f: impl FnMut(&U) -> Result<T>,
+    ) -> Result<T> {
+        with_fallback(name, urls, self.notify_handler, f)
+    }
+
     pub fn clean(&self, hashes: &[String]) -> Result<()> {
+        if let Some(retention) = self.retention {
+            for download in cache::expire(self.download_dir, retention)? {
+                (self.notify_handler)(Notification::ExpiredCachedDownload(download.hash()));
+            }
+            return Ok(());
+        }
+
         for hash in hashes.iter() {
             let used_file = self.download_dir.join(hash);
             if self.download_dir.join(&used_file).exists() {
@@ -105,14 +272,78 @@ impl<'a> DownloadCfg<'a> {
         let hash_url = utils::parse_url(&(url.to_owned() + ".sha256"))?;
         let hash_file = self.temp_cfg.new_file()?;
 
-        utils::download_file(&hash_url, &hash_file, None, &|n| {
-            (self.notify_handler)(n.into())
-        })?;
+        utils::download_file(
+            &hash_url,
+            &hash_file,
+            None,
+            &**self.backend,
+            self.options,
+            &|n| (self.notify_handler)(n.into()),
+        )?;
 
         Ok(utils::read_file("hash", &hash_file).map(|s| s[0..64].to_owned())?)
     }
 
-    /// Downloads a file, sourcing its hash from the same url with a `.sha256` suffix.
+    fn download_signature(&self, url: &str) -> Result<Option<String>> {
+        let sig_url = utils::parse_url(&(url.to_owned() + ".asc"))?;
+        let sig_file = self.temp_cfg.new_file()?;
+
+        match utils::download_file(
+            &sig_url,
+            &sig_file,
+            None,
+            &**self.backend,
+            self.options,
+            &|n| (self.notify_handler)(n.into()),
+        ) {
+            Ok(()) => Ok(Some(utils::read_file("signature", &sig_file)?)),
+            Err(Error(ErrorKind::DownloadNotExists { .. }, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks the file downloaded from `url` against the detached signature
+    /// at the same url with a `.asc` suffix. Depending on `signature_check`
+    /// a missing or invalid signature is either an error or a warning.
+    fn check_signature(&self, url: &str, file: &Path) -> Result<()> {
+        let signature = match self.download_signature(url)? {
+            Some(signature) => signature,
+            None => {
+                return match self.signature_check {
+                    SignatureCheck::Enforce => {
+                        Err(ErrorKind::SignatureMissing(url.to_owned()).into())
+                    }
+                    SignatureCheck::Warn => {
+                        (self.notify_handler)(Notification::SignatureMissing(url));
+                        Ok(())
+                    }
+                };
+            }
+        };
+
+        let content = fs::read(file).chain_err(|| ErrorKind::ReadingFile {
+            name: "downloaded",
+            path: file.to_owned(),
+        })?;
+
+        if signatures::verify_signature(&content, &signature, self.gpg_key)? {
+            (self.notify_handler)(Notification::SignatureValid(url));
+            Ok(())
+        } else {
+            match self.signature_check {
+                SignatureCheck::Enforce => {
+                    Err(ErrorKind::SignatureVerificationFailed(url.to_owned()).into())
+                }
+                SignatureCheck::Warn => {
+                    (self.notify_handler)(Notification::SignatureInvalid(url));
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Downloads a file, sourcing its hash from the same url with a `.sha256` suffix,
+    /// and verifying its signature from the same url with a `.asc` suffix.
     /// If `update_hash` is present, then that will be compared to the downloaded hash,
     /// and if they match, the download is skipped.
     pub fn download_and_check(
@@ -143,9 +374,14 @@ impl<'a> DownloadCfg<'a> {
         let file = self.temp_cfg.new_file_with_ext("", ext)?;
 
         let mut hasher = Sha256::new();
-        utils::download_file(&url, &file, Some(&mut hasher), &|n| {
-            (self.notify_handler)(n.into())
-        })?;
+        utils::download_file(
+            &url,
+            &file,
+            Some(&mut hasher),
+            &**self.backend,
+            self.options,
+            &|n| (self.notify_handler)(n.into()),
+        )?;
         let actual_hash = format!("{:x}", hasher.result());
 
         if hash != actual_hash {
@@ -160,15 +396,296 @@ impl<'a> DownloadCfg<'a> {
             (self.notify_handler)(Notification::ChecksumValid(url_str));
         }
 
-        // TODO: Check the signature of the file
+        self.check_signature(url_str, &file)?;
 
         Ok(Some((file, partial_hash)))
     }
 }
 
-fn file_hash(path: &Path) -> Result<String> {
+/// Downloads `url` into `download_dir`, keyed by `hash`. See `DownloadCfg::download`.
+fn download_file(
+    download_dir: &Path,
+    url: &Url,
+    hash: &str,
+    backend: &dyn Backend,
+    options: &DownloadOptions,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<File> {
+    let target_file = download_dir.join(Path::new(hash));
+
+    if target_file.exists() {
+        let cached_result = file_hash(&target_file)?;
+        if hash == cached_result {
+            cache::mark_used(&target_file)?;
+            notify_handler(Notification::FileAlreadyDownloaded);
+            notify_handler(Notification::ChecksumValid(&url.to_string()));
+            return Ok(File { path: target_file });
+        } else {
+            notify_handler(Notification::CachedFileChecksumFailed);
+            fs::remove_file(&target_file).chain_err(|| "cleaning up previous download")?;
+        }
+    }
+
+    let partial_file_path = partial_file_path(&target_file);
+
+    let mut hasher = Sha256::new();
+
+    utils::download_file_with_resume(
+        &url,
+        &partial_file_path,
+        Some(&mut hasher),
+        true,
+        backend,
+        options,
+        &|n| notify_handler(n.into()),
+    )?;
+
+    let actual_hash = format!("{:x}", hasher.result());
+
+    if hash != actual_hash {
+        // Incorrect hash, so there's nothing worth resuming
+        utils::remove_file("downloaded", &partial_file_path)?;
+        Err(ErrorKind::ChecksumFailed {
+            url: url.to_string(),
+            expected: hash.to_string(),
+            calculated: actual_hash,
+        }
+        .into())
+    } else {
+        notify_handler(Notification::ChecksumValid(&url.to_string()));
+
+        utils::rename_file("downloaded", &partial_file_path, &target_file)?;
+        Ok(File { path: target_file })
+    }
+}
+
+fn partial_file_path(target_file: &Path) -> PathBuf {
+    target_file.with_file_name(
+        target_file
+            .file_name()
+            .map(|s| s.to_str().unwrap_or("_"))
+            .unwrap_or("_")
+            .to_owned()
+            + ".partial",
+    )
+}
+
+// Calls `f` with each of `urls` in turn, see `DownloadCfg::with_fallback`
+fn with_fallback<U: AsRef<str>, T>(
+    name: &str,
+    urls: &[U],
+    notify_handler: &dyn Fn(Notification<'_>),
+    mut f: impl FnMut(&U) -> Result<T>,
+) -> Result<T> {
+    let mut urls = urls.iter().peekable();
+    while let Some(url) = urls.next() {
+        match f(url) {
+            Ok(v) => {
+                notify_handler(Notification::DownloadedFrom(name, url.as_ref()));
+                return Ok(v);
+            }
+            Err(ref e) if urls.peek().is_some() && is_server_failure(e) => {
+                notify_handler(Notification::TryingNextDistServer(url.as_ref()));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(format!("no dist server to download {} from", name).into())
+}
+
+// Whether another dist server might succeed where this one failed
+fn is_server_failure(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::DownloadNotExists { .. }
+            | ErrorKind::DownloadingFile { .. }
+            | ErrorKind::ChecksumFailed { .. }
+    )
+}
+
+// Unpacks the package while it is downloaded to the cache, or from the
+// cache if it is already there
+fn download_and_unpack(
+    download_dir: &Path,
+    download: PackageDownload,
+    backend: &dyn Backend,
+    options: &DownloadOptions,
+    notify_handler: &dyn Fn(Notification<'_>),
+) -> Result<()> {
+    let PackageDownload {
+        name,
+        urls,
+        hash,
+        unpack,
+    } = download;
+    let target_file = download_dir.join(Path::new(&hash));
+    let partial_file_path = partial_file_path(&target_file);
+    let _lock = cache::lock_download(download_dir, &hash, &|n| notify_handler(n.into()))?;
+
+    with_fallback(&name, &urls, notify_handler, |url| {
+        // This is also how a download that failed part way through on
+        // the previous dist server is resumed
+        if target_file.exists() || partial_file_path.exists() {
+            let file = download_file(download_dir, url, &hash, backend, options, notify_handler)?;
+            let mut reader = fs::File::open(&*file).chain_err(|| ErrorKind::ExtractingPackage)?;
+            return unpack(&mut reader);
+        }
+
+        let (chunks, received) = mpsc::sync_channel(UNPACK_QUEUE_CHUNKS);
+        let unpack_chunks = unpack.clone();
+        let unpacker = thread::spawn(move || {
+            unpack_chunks(&mut ChunkReader {
+                chunks: received,
+                chunk: vec![],
+                pos: 0,
+            })
+        });
+
+        let chunks = RefCell::new(Some(chunks));
+        let mut hasher = Sha256::new();
+        let downloaded = utils::download_file_with_resume(
+            url,
+            &partial_file_path,
+            Some(&mut hasher),
+            false,
+            backend,
+            options,
+            &|n| {
+                match n {
+                    Un::DownloadDataReceived(data) => {
+                        // Once unpacking has failed there is no one to send
+                        // to, but the download still completes for the cache
+                        if let Some(ref chunks) = *chunks.borrow() {
+                            let _ = chunks.send(data.to_vec());
+                        }
+                    }
+                    // What was unpacked so far is from another version of
+                    // the package, so stop unpacking it
+                    Un::DownloadRestarted(_) => drop(chunks.borrow_mut().take()),
+                    _ => {}
+                }
+                notify_handler(n.into())
+            },
+        );
+        let restarted = chunks.into_inner().is_none();
+        let unpacked = unpacker
+            .join()
+            .unwrap_or_else(|_| Err("package unpacking thread panicked".into()));
+
+        // A failed download is left partial, to be resumed next time
+        downloaded?;
+        let actual_hash = format!("{:x}", hasher.result());
+        if hash != actual_hash {
+            utils::remove_file("downloaded", &partial_file_path)?;
+            return Err(ErrorKind::ChecksumFailed {
+                url: url.to_string(),
+                expected: hash.clone(),
+                calculated: actual_hash,
+            }
+            .into());
+        }
+        notify_handler(Notification::ChecksumValid(url.as_str()));
+        utils::rename_file("downloaded", &partial_file_path, &target_file)?;
+
+        if restarted {
+            let mut reader =
+                fs::File::open(&target_file).chain_err(|| ErrorKind::ExtractingPackage)?;
+            return unpack(&mut reader);
+        }
+        unpacked
+    })
+}
+
+/// Reads a package as its chunks arrive from the download.
+struct ChunkReader {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // The download is over
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A notification from a download running on a worker thread, owning its
+/// data so it can be sent back to the thread holding the notification handler.
+enum WorkerEvent {
+    DownloadingFile(Url, PathBuf),
+    ContentLength(u64),
+    DataReceived(usize),
+    ResumingPartialDownload,
+    RetryingDownload(Url, u32, Duration),
+    DownloadRestarted(Url),
+    UsingBackend(String),
+    FileAlreadyDownloaded,
+    CachedFileChecksumFailed,
+    ChecksumValid(String),
+    TryingNextDistServer(String),
+    DownloadedFrom(String),
+    WaitingForLock(PathBuf, Option<u32>),
+    // Boxed, as an error is several times the size of any other event
+    Finished(Box<Result<()>>),
+}
+
+impl WorkerEvent {
+    fn from_notification(n: Notification<'_>) -> Option<Self> {
+        Some(match n {
+            Notification::Utils(Un::DownloadingFile(url, path)) => {
+                WorkerEvent::DownloadingFile(url.clone(), path.to_owned())
+            }
+            Notification::Utils(Un::DownloadContentLengthReceived(len)) => {
+                WorkerEvent::ContentLength(len)
+            }
+            Notification::Utils(Un::DownloadDataReceived(data)) => {
+                WorkerEvent::DataReceived(data.len())
+            }
+            Notification::Utils(Un::ResumingPartialDownload) => {
+                WorkerEvent::ResumingPartialDownload
+            }
+            Notification::Utils(Un::RetryingDownload(url, attempt, delay)) => {
+                WorkerEvent::RetryingDownload(url.clone(), attempt, delay)
+            }
+            Notification::Utils(Un::DownloadRestarted(url)) => {
+                WorkerEvent::DownloadRestarted(url.clone())
+            }
+            Notification::Utils(Un::UsingBackend(name)) => {
+                WorkerEvent::UsingBackend(name.to_owned())
+            }
+            Notification::FileAlreadyDownloaded => WorkerEvent::FileAlreadyDownloaded,
+            Notification::CachedFileChecksumFailed => WorkerEvent::CachedFileChecksumFailed,
+            Notification::ChecksumValid(url) => WorkerEvent::ChecksumValid(url.to_owned()),
+            Notification::TryingNextDistServer(url) => {
+                WorkerEvent::TryingNextDistServer(url.to_owned())
+            }
+            Notification::DownloadedFrom(_, url) => WorkerEvent::DownloadedFrom(url.to_owned()),
+            Notification::Utils(Un::WaitingForLock(path, pid)) => {
+                WorkerEvent::WaitingForLock(path.to_owned(), pid)
+            }
+            // `DownloadFinished` is reported as `ComponentDownloadFinished`
+            // once the worker is done with the file.
+            _ => return None,
+        })
+    }
+}
+
+/// The SHA-256 hash of the contents of `path`, in hex.
+pub fn file_hash(path: &Path) -> Result<String> {
     let mut hasher = Sha256::new();
-    use std::io::Read;
     let mut downloaded = fs::File::open(&path).chain_err(|| "opening already downloaded file")?;
     let mut buf = vec![0; 32768];
     while let Ok(n) = downloaded.read(&mut buf) {
@@ -9,14 +9,21 @@
 //! Installers use this info to customize Rust installations.
 //!
 //! See tests/channel-rust-nightly-example.toml for an example.
+//!
+//! Version 2.1 adds an `[artifacts]` table of other files published
+//! with the release, each in as many formats as the server has. Keys
+//! this rustup doesn't understand, and profiles and artifacts it can't
+//! read, are kept and written back out unchanged.
 
 use crate::errors::*;
 use crate::utils::toml_utils::*;
 
-use crate::dist::dist::TargetTriple;
+use crate::dist::dist::{Profile, TargetTriple};
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
 
-pub const SUPPORTED_MANIFEST_VERSIONS: [&str; 1] = ["2"];
+pub const SUPPORTED_MANIFEST_VERSIONS: [&str; 2] = ["2", "2.1"];
 pub const DEFAULT_MANIFEST_VERSION: &str = "2";
 
 #[derive(Clone, Debug, PartialEq)]
@@ -26,12 +33,18 @@ pub struct Manifest {
     pub packages: HashMap<String, Package>,
     pub renames: HashMap<String, String>,
     pub reverse_renames: HashMap<String, String>,
+    pub profiles: HashMap<Profile, Vec<String>>,
+    pub artifacts: HashMap<String, Artifact>,
+    /// Keys this version of rustup doesn't know, kept so that they
+    /// survive being written back out.
+    pub unknown: toml::value::Table,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Package {
     pub version: String,
     pub targets: PackageTargets,
+    pub unknown: toml::value::Table,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -45,6 +58,7 @@ pub struct TargetedPackage {
     pub bins: Option<PackageBins>,
     pub components: Vec<Component>,
     pub extensions: Vec<Component>,
+    pub unknown: toml::value::Table,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -53,6 +67,50 @@ pub struct PackageBins {
     pub hash: String,
     pub xz_url: Option<String>,
     pub xz_hash: Option<String>,
+    pub zst_url: Option<String>,
+    pub zst_hash: Option<String>,
+}
+
+/// A file published with a release that isn't a component, like the
+/// source code or an installer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Artifact {
+    pub targets: HashMap<TargetTriple, Vec<ArtifactFile>>,
+}
+
+/// One format an artifact is available in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArtifactFile {
+    pub url: String,
+    /// Only files with a sha256 hash can be checked by this version of
+    /// rustup, so others are skipped by `Artifact::get_target`.
+    pub hash_sha256: Option<String>,
+    pub size: Option<u64>,
+    pub unknown: toml::value::Table,
+}
+
+/// The differences between two manifests, as seen from one target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestDiff {
+    pub old_date: String,
+    pub new_date: String,
+    /// Every package in either manifest, sorted by name.
+    pub packages: Vec<PackageDiff>,
+    /// Components of the rust package that only the new manifest has.
+    pub added_components: Vec<String>,
+    /// Components of the rust package that only the old manifest has.
+    pub removed_components: Vec<String>,
+}
+
+/// A package's version and availability in two manifests. The version is
+/// `None` when the package isn't in that manifest at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackageDiff {
+    pub name: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub old_available: bool,
+    pub new_available: bool,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -75,7 +133,7 @@ impl Manifest {
 
     pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
         let version = get_string(&mut table, "manifest-version", path)?;
-        if !SUPPORTED_MANIFEST_VERSIONS.contains(&&*version) {
+        if !SUPPORTED_MANIFEST_VERSIONS.contains(&version.as_str()) {
             return Err(ErrorKind::UnsupportedVersion(version).into());
         }
         let (renames, reverse_renames) = Self::table_to_renames(&mut table, path)?;
@@ -85,10 +143,13 @@ impl Manifest {
             packages: Self::table_to_packages(&mut table, path)?,
             renames,
             reverse_renames,
+            profiles: Self::table_to_profiles(&mut table, path)?,
+            artifacts: Self::table_to_artifacts(&mut table, path)?,
+            unknown: table,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
-        let mut result = toml::value::Table::new();
+        let mut result = self.unknown;
 
         result.insert("date".to_owned(), toml::Value::String(self.date));
         result.insert(
@@ -102,6 +163,24 @@ impl Manifest {
         let packages = Self::packages_to_table(self.packages);
         result.insert("pkg".to_owned(), toml::Value::Table(packages));
 
+        if !self.profiles.is_empty() {
+            let unknown = match result.remove("profiles") {
+                Some(toml::Value::Table(t)) => t,
+                _ => toml::value::Table::new(),
+            };
+            let profiles = Self::profiles_to_table(self.profiles, unknown);
+            result.insert("profiles".to_owned(), toml::Value::Table(profiles));
+        }
+
+        if !self.artifacts.is_empty() {
+            let unreadable = match result.remove("artifacts") {
+                Some(toml::Value::Table(t)) => t,
+                _ => toml::value::Table::new(),
+            };
+            let artifacts = Self::artifacts_to_table(self.artifacts, unreadable);
+            result.insert("artifacts".to_owned(), toml::Value::Table(artifacts));
+        }
+
         result
     }
 
@@ -156,12 +235,181 @@ impl Manifest {
         result
     }
 
+    fn table_to_profiles(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<HashMap<Profile, Vec<String>>> {
+        let mut result = HashMap::new();
+        let profiles_table = get_table(table, "profiles", path)?;
+
+        // Profiles this version of rustup doesn't know about are left in
+        // the unknown keys
+        let mut unknown = toml::value::Table::new();
+        for (k, v) in profiles_table {
+            let (profile, a) = match (Profile::from_str(&k), v) {
+                (Ok(profile), toml::Value::Array(a)) => (profile, a),
+                (_, v) => {
+                    unknown.insert(k, v);
+                    continue;
+                }
+            };
+            let mut pkgs = Vec::new();
+            for (i, v) in a.into_iter().enumerate() {
+                if let toml::Value::String(s) = v {
+                    pkgs.push(s);
+                } else {
+                    return Err(ErrorKind::ExpectedType(
+                        "string",
+                        format!("{}profiles.{}[{}]", path, k, i),
+                    )
+                    .into());
+                }
+            }
+            result.insert(profile, pkgs);
+        }
+        if !unknown.is_empty() {
+            table.insert("profiles".to_owned(), toml::Value::Table(unknown));
+        }
+
+        Ok(result)
+    }
+    fn profiles_to_table(
+        profiles: HashMap<Profile, Vec<String>>,
+        mut result: toml::value::Table,
+    ) -> toml::value::Table {
+        for (profile, pkgs) in profiles {
+            let pkgs = pkgs.into_iter().map(toml::Value::String).collect();
+            result.insert(profile.to_string(), toml::Value::Array(pkgs));
+        }
+        result
+    }
+
+    fn table_to_artifacts(
+        table: &mut toml::value::Table,
+        path: &str,
+    ) -> Result<HashMap<String, Artifact>> {
+        let mut result = HashMap::new();
+        let artifacts_table = match table.remove("artifacts") {
+            Some(toml::Value::Table(t)) => t,
+            Some(v) => {
+                table.insert("artifacts".to_owned(), v);
+                return Ok(result);
+            }
+            None => return Ok(result),
+        };
+
+        // Artifacts aren't needed to install anything, so those that
+        // can't be read are left in the unknown keys
+        let mut unreadable = toml::value::Table::new();
+        for (k, v) in artifacts_table {
+            let artifact = match v {
+                toml::Value::Table(ref t) => {
+                    let path = format!("{}artifacts.{}.", path, k);
+                    Artifact::from_toml(t.clone(), &path).ok()
+                }
+                _ => None,
+            };
+            match artifact {
+                Some(artifact) => {
+                    result.insert(k, artifact);
+                }
+                None => {
+                    unreadable.insert(k, v);
+                }
+            }
+        }
+        if !unreadable.is_empty() {
+            table.insert("artifacts".to_owned(), toml::Value::Table(unreadable));
+        }
+
+        Ok(result)
+    }
+    fn artifacts_to_table(
+        artifacts: HashMap<String, Artifact>,
+        mut result: toml::value::Table,
+    ) -> toml::value::Table {
+        for (k, v) in artifacts {
+            result.insert(k, toml::Value::Table(v.into_toml()));
+        }
+        result
+    }
+
     pub fn get_package(&self, name: &str) -> Result<&Package> {
         self.packages
             .get(name)
             .ok_or_else(|| format!("package not found: '{}'", name).into())
     }
 
+    /// Whether the package of the component `name`, following any rename,
+    /// is available for `target`.
+    pub fn is_component_available(&self, name: &str, target: &TargetTriple) -> bool {
+        let name = self.renames.get(name).map(String::as_str).unwrap_or(name);
+        self.get_package(name)
+            .map(|p| p.is_available_for(target))
+            .unwrap_or(false)
+    }
+
+    /// Compares this manifest with `new` for `target`, package by package
+    /// and by the components of the rust package.
+    pub fn diff(&self, new: &Manifest, target: &TargetTriple) -> ManifestDiff {
+        let mut names: Vec<&String> = self.packages.keys().chain(new.packages.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        let packages = names
+            .into_iter()
+            .map(|name| {
+                let old = self.packages.get(name);
+                let new = new.packages.get(name);
+                PackageDiff {
+                    name: name.to_owned(),
+                    old_version: old.map(|p| p.version.clone()),
+                    new_version: new.map(|p| p.version.clone()),
+                    old_available: old.map_or(false, |p| p.is_available_for(target)),
+                    new_available: new.map_or(false, |p| p.is_available_for(target)),
+                }
+            })
+            .collect();
+
+        // Components are compared by the names users know them by, so
+        // renamed ones aren't reported as both added and removed
+        let old_components = self.rust_component_names(target);
+        let new_components = new.rust_component_names(target);
+        let added_components = new_components
+            .iter()
+            .filter(|c| !old_components.contains(c))
+            .cloned()
+            .collect();
+        let removed_components = old_components
+            .iter()
+            .filter(|c| !new_components.contains(c))
+            .cloned()
+            .collect();
+
+        ManifestDiff {
+            old_date: self.date.clone(),
+            new_date: new.date.clone(),
+            packages,
+            added_components,
+            removed_components,
+        }
+    }
+
+    // The names of the components and extensions of the rust package
+    // for `target`
+    fn rust_component_names(&self, target: &TargetTriple) -> Vec<String> {
+        self.get_package("rust")
+            .and_then(|p| p.get_target(Some(target)))
+            .map(|t| {
+                t.components
+                    .iter()
+                    .chain(t.extensions.iter())
+                    .map(|c| c.name(self))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn get_rust_version(&self) -> Result<&str> {
         self.get_package("rust").map(|p| &*p.version)
     }
@@ -204,6 +452,63 @@ impl Manifest {
         Ok(())
     }
 
+    // The components of the rust package that make up `profile` for
+    // `target`. These come from the manifest's `[profiles]` table if it
+    // has one, otherwise from the built-in profile definitions.
+    pub fn get_profile_components(
+        &self,
+        profile: Profile,
+        target: &TargetTriple,
+    ) -> Result<Vec<Component>> {
+        let rust_target_package = self.get_package("rust")?.get_target(Some(target))?;
+
+        let pkgs: Option<Vec<&str>> = match self.profiles.get(&profile) {
+            Some(pkgs) => Some(pkgs.iter().map(|p| &**p).collect()),
+            None => profile.builtin_packages().map(<[_]>::to_vec),
+        };
+
+        let mut result = Vec::new();
+        let candidates = rust_target_package
+            .components
+            .iter()
+            .chain(rust_target_package.extensions.iter())
+            .filter(|c| c.target.as_ref().map_or(true, |t| t == target));
+        for component in candidates {
+            let wanted = pkgs.as_ref().map_or(true, |pkgs| {
+                pkgs.iter()
+                    .any(|p| self.renames.get(*p).map_or(*p, |r| &**r) == component.pkg)
+            });
+            if wanted && !result.contains(component) {
+                result.push(component.clone());
+            }
+        }
+
+        Ok(result)
+    }
+
+    // The components that can't be removed from an installation made with
+    // `profile`: those the rust package requires that the profile also
+    // includes. Installations without a profile need all of them.
+    pub fn get_required_components(
+        &self,
+        profile: Option<Profile>,
+        target: &TargetTriple,
+    ) -> Result<Vec<Component>> {
+        let rust_target_package = self.get_package("rust")?.get_target(Some(target))?;
+        match profile {
+            Some(profile) => {
+                let profile_components = self.get_profile_components(profile, target)?;
+                Ok(rust_target_package
+                    .components
+                    .iter()
+                    .filter(|c| profile_components.contains(c))
+                    .cloned()
+                    .collect())
+            }
+            None => Ok(rust_target_package.components.clone()),
+        }
+    }
+
     // If the component should be renamed by this manifest, then return a new
     // component with the new name. If not, return `None`.
     pub fn rename_component(&self, component: &Component) -> Option<Component> {
@@ -219,11 +524,12 @@ impl Package {
     pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
         Ok(Package {
             version: get_string(&mut table, "version", path)?,
-            targets: Self::toml_to_targets(table, path)?,
+            targets: Self::toml_to_targets(&mut table, path)?,
+            unknown: table,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
-        let mut result = toml::value::Table::new();
+        let mut result = self.unknown;
 
         result.insert("version".to_owned(), toml::Value::String(self.version));
 
@@ -233,8 +539,8 @@ impl Package {
         result
     }
 
-    fn toml_to_targets(mut table: toml::value::Table, path: &str) -> Result<PackageTargets> {
-        let mut target_table = get_table(&mut table, "target", path)?;
+    fn toml_to_targets(table: &mut toml::value::Table, path: &str) -> Result<PackageTargets> {
+        let mut target_table = get_table(table, "target", path)?;
 
         if let Some(toml::Value::Table(t)) = target_table.remove("*") {
             Ok(PackageTargets::Wildcard(TargetedPackage::from_toml(
@@ -268,6 +574,12 @@ impl Package {
         result
     }
 
+    pub fn is_available_for(&self, target: &TargetTriple) -> bool {
+        self.get_target(Some(target))
+            .map(TargetedPackage::available)
+            .unwrap_or(false)
+    }
+
     pub fn get_target(&self, target: Option<&TargetTriple>) -> Result<&TargetedPackage> {
         match self.targets {
             PackageTargets::Wildcard(ref tpkg) => Ok(tpkg),
@@ -306,13 +618,16 @@ impl TargetedPackage {
         let extensions = get_array(&mut table, "extensions", path)?;
 
         if get_bool(&mut table, "available", path)? {
+            let bins = PackageBins {
+                url: get_string(&mut table, "url", path)?,
+                hash: get_string(&mut table, "hash", path)?,
+                xz_url: get_string(&mut table, "xz_url", path).ok(),
+                xz_hash: get_string(&mut table, "xz_hash", path).ok(),
+                zst_url: get_string(&mut table, "zst_url", path).ok(),
+                zst_hash: get_string(&mut table, "zst_hash", path).ok(),
+            };
             Ok(TargetedPackage {
-                bins: Some(PackageBins {
-                    url: get_string(&mut table, "url", path)?,
-                    hash: get_string(&mut table, "hash", path)?,
-                    xz_url: get_string(&mut table, "xz_url", path).ok(),
-                    xz_hash: get_string(&mut table, "xz_hash", path).ok(),
-                }),
+                bins: Some(bins),
                 components: Self::toml_to_components(
                     components,
                     &format!("{}{}.", path, "components"),
@@ -321,19 +636,21 @@ impl TargetedPackage {
                     extensions,
                     &format!("{}{}.", path, "extensions"),
                 )?,
+                unknown: table,
             })
         } else {
             Ok(TargetedPackage {
                 bins: None,
                 components: vec![],
                 extensions: vec![],
+                unknown: table,
             })
         }
     }
     pub fn into_toml(self) -> toml::value::Table {
         let extensions = Self::components_to_toml(self.extensions);
         let components = Self::components_to_toml(self.components);
-        let mut result = toml::value::Table::new();
+        let mut result = self.unknown;
         if !extensions.is_empty() {
             result.insert("extensions".to_owned(), toml::Value::Array(extensions));
         }
@@ -347,6 +664,10 @@ impl TargetedPackage {
                 result.insert("xz_hash".to_owned(), toml::Value::String(xz_hash));
                 result.insert("xz_url".to_owned(), toml::Value::String(xz_url));
             }
+            if let (Some(zst_hash), Some(zst_url)) = (bins.zst_hash, bins.zst_url) {
+                result.insert("zst_hash".to_owned(), toml::Value::String(zst_hash));
+                result.insert("zst_url".to_owned(), toml::Value::String(zst_url));
+            }
             result.insert("available".to_owned(), toml::Value::Boolean(true));
         } else {
             result.insert("available".to_owned(), toml::Value::Boolean(false));
@@ -379,6 +700,81 @@ impl TargetedPackage {
     }
 }
 
+impl Artifact {
+    pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let mut targets = HashMap::new();
+        for (k, v) in get_table(&mut table, "target", path)? {
+            let path = format!("{}target.{}", path, k);
+            let files = if let toml::Value::Array(a) = v {
+                a
+            } else {
+                return Err(ErrorKind::ExpectedType("array", path).into());
+            };
+            let mut result = Vec::new();
+            for (i, v) in files.into_iter().enumerate() {
+                if let toml::Value::Table(t) = v {
+                    result.push(ArtifactFile::from_toml(t, &format!("{}[{}].", path, i))?);
+                } else {
+                    return Err(ErrorKind::ExpectedType("table", format!("{}[{}]", path, i)).into());
+                }
+            }
+            targets.insert(TargetTriple::from_str(&k), result);
+        }
+        Ok(Artifact { targets })
+    }
+    pub fn into_toml(self) -> toml::value::Table {
+        let mut targets = toml::value::Table::new();
+        for (k, v) in self.targets {
+            let files = v
+                .into_iter()
+                .map(|f| toml::Value::Table(f.into_toml()))
+                .collect();
+            targets.insert(k.to_string(), toml::Value::Array(files));
+        }
+        let mut result = toml::value::Table::new();
+        result.insert("target".to_owned(), toml::Value::Table(targets));
+        result
+    }
+
+    /// The formats the artifact is available in for `target`, or for
+    /// every target, that this version of rustup can verify.
+    pub fn get_target(&self, target: &TargetTriple) -> Vec<&ArtifactFile> {
+        self.targets
+            .get(target)
+            .or_else(|| self.targets.get(&TargetTriple::from_str("*")))
+            .map(|files| files.iter().filter(|f| f.hash_sha256.is_some()).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl ArtifactFile {
+    pub fn from_toml(mut table: toml::value::Table, path: &str) -> Result<Self> {
+        let size = match get_opt_integer(&mut table, "size", path)? {
+            Some(size) => Some(u64::try_from(size).map_err(|_| {
+                ErrorKind::ExpectedType("unsigned integer", path.to_owned() + "size")
+            })?),
+            None => None,
+        };
+        Ok(ArtifactFile {
+            url: get_string(&mut table, "url", path)?,
+            hash_sha256: get_opt_string(&mut table, "hash-sha256", path)?,
+            size,
+            unknown: table,
+        })
+    }
+    pub fn into_toml(self) -> toml::value::Table {
+        let mut result = self.unknown;
+        result.insert("url".to_owned(), toml::Value::String(self.url));
+        if let Some(hash) = self.hash_sha256 {
+            result.insert("hash-sha256".to_owned(), toml::Value::String(hash));
+        }
+        if let Some(size) = self.size {
+            result.insert("size".to_owned(), toml::Value::Integer(size as i64));
+        }
+        result
+    }
+}
+
 impl Component {
     pub fn new(pkg: String, target: Option<TargetTriple>) -> Component {
         Component { pkg, target }
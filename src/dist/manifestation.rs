@@ -1,24 +1,37 @@
 //! Maintains a Rust installation by installing individual Rust
 //! platform components from a distribution server.
 
-use crate::dist::component::{Components, Package, TarGzPackage, TarXzPackage, Transaction};
+#[cfg(feature = "zstd")]
+use crate::dist::component::TarZstdPackage;
+use crate::dist::component::{
+    self, Components, Package, TarGzPackage, TarPackage, TarXzPackage, Transaction, Verification,
+};
 use crate::dist::config::Config;
-use crate::dist::dist::{TargetTriple, DEFAULT_DIST_SERVER};
-use crate::dist::download::{DownloadCfg, File};
+use crate::dist::dist::{Profile, TargetTriple, DEFAULT_DIST_SERVER};
+use crate::dist::download::{DownloadCfg, PackageDownload, Unpacker};
 use crate::dist::manifest::{Component, Manifest, TargetedPackage};
 use crate::dist::notifications::*;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 use crate::errors::*;
 use crate::utils::utils;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub const DIST_MANIFEST: &str = "multirust-channel-manifest.toml";
 pub const CONFIG_FILE: &str = "multirust-config.toml";
+pub const SNAPSHOTS_DIR: &str = "snapshots";
 
+/// How many installations replaced by updates are kept to roll back to.
+pub const MAX_SNAPSHOTS: usize = 3;
+
+#[derive(Clone, Copy)]
 enum Format {
     Gz,
     Xz,
+    #[cfg(feature = "zstd")]
+    Zst,
 }
 
 #[derive(Debug)]
@@ -31,6 +44,11 @@ pub struct Manifestation {
 pub struct Changes {
     pub add_extensions: Vec<Component>,
     pub remove_extensions: Vec<Component>,
+    /// Installed components to uninstall and install again, as they are
+    pub reinstall_components: Vec<Component>,
+    /// The profile to install on a fresh install. Existing installs
+    /// keep the profile they were installed with.
+    pub profile: Option<Profile>,
 }
 
 impl Changes {
@@ -38,13 +56,26 @@ impl Changes {
         Changes {
             add_extensions: Vec::new(),
             remove_extensions: Vec::new(),
+            reinstall_components: Vec::new(),
+            profile: None,
         }
     }
 
-    fn check_invariants(&self, rust_target_package: &TargetedPackage, config: &Option<Config>) {
+    fn check_invariants(
+        &self,
+        rust_target_package: &TargetedPackage,
+        required_components: &[Component],
+        config: &Option<Config>,
+    ) {
+        // Components left out by the installation's profile can be added
+        // and removed just like extensions
+        let is_optional = |c: &Component| {
+            rust_target_package.extensions.contains(c)
+                || (rust_target_package.components.contains(c) && !required_components.contains(c))
+        };
         for component_to_add in &self.add_extensions {
             assert!(
-                rust_target_package.extensions.contains(component_to_add),
+                is_optional(component_to_add),
                 "package must contain extension to add"
             );
             assert!(
@@ -54,7 +85,7 @@ impl Changes {
         }
         for component_to_remove in &self.remove_extensions {
             assert!(
-                rust_target_package.extensions.contains(component_to_remove),
+                is_optional(component_to_remove),
                 "package must contain extension to remove"
             );
             let config = config
@@ -68,6 +99,14 @@ impl Changes {
     }
 }
 
+/// The manifest and configuration of an installation, kept when an update
+/// replaces them so that the update can be rolled back.
+#[derive(Debug)]
+pub struct Snapshot {
+    manifest: String,
+    config: String,
+}
+
 #[derive(PartialEq, Debug)]
 pub enum UpdateStatus {
     Changed,
@@ -89,6 +128,16 @@ impl Manifestation {
         })
     }
 
+    /// Rolls back an update that was interrupted, or finishes it if
+    /// it was being committed.
+    ///
+    /// The update may be another process's that is still running, so
+    /// this is only for callers holding the toolchain's lock, before
+    /// they change the installation themselves.
+    pub fn recover(&self, notify_handler: &dyn Fn(Notification<'_>)) -> Result<()> {
+        Transaction::recover(&self.installation.prefix(), notify_handler)
+    }
+
     /// Install or update from a given channel manifest, while
     /// selecting extension components to add or remove.
     ///
@@ -138,8 +187,9 @@ impl Manifestation {
 
         let altered = temp_cfg.dist_server != DEFAULT_DIST_SERVER;
 
-        // Download component packages and validate hashes
-        let mut things_to_install: Vec<(Component, Format, File)> = Vec::new();
+        // Download and unpack component packages, and validate hashes
+        let mut components: Vec<(Component, temp::Dir<'_>)> = Vec::new();
+        let mut downloads: Vec<PackageDownload> = Vec::new();
         let mut things_downloaded: Vec<String> = Vec::new();
         for (component, format, url, hash) in update.components_urls_and_hashes(new_manifest)? {
             notify_handler(Notification::DownloadingComponent(
@@ -147,25 +197,37 @@ impl Manifestation {
                 &self.target_triple,
                 component.target.as_ref(),
             ));
-            let url = if altered {
-                url.replace(DEFAULT_DIST_SERVER, temp_cfg.dist_server.as_str())
-            } else {
-                url
+            let urls = download_cfg
+                .dist_urls(&url)
+                .iter()
+                .map(|url| utils::parse_url(url))
+                .collect::<Result<Vec<_>>>()?;
+
+            let unpacked_dir = temp_cfg.new_directory()?;
+            let path = unpacked_dir.to_owned();
+            let unpack: Unpacker = match format {
+                Format::Gz => Arc::new(move |stream| TarGzPackage::unpack(stream, &path)),
+                Format::Xz => Arc::new(move |stream| TarXzPackage::unpack(stream, &path)),
+                #[cfg(feature = "zstd")]
+                Format::Zst => Arc::new(move |stream| TarZstdPackage::unpack(stream, &path)),
             };
-
-            let url_url = utils::parse_url(&url)?;
-
-            let downloaded_file = download_cfg
-                .download(&url_url, &hash)
-                .chain_err(|| ErrorKind::ComponentDownloadFailed(component.name(new_manifest)))?;
-
+            downloads.push(PackageDownload {
+                name: component.name(new_manifest),
+                urls,
+                hash: hash.clone(),
+                unpack,
+            });
             things_downloaded.push(hash);
-
-            things_to_install.push((component, format, downloaded_file));
+            components.push((component, unpacked_dir));
         }
 
+        download_cfg.download_components(downloads)?;
+
         // Begin transaction
         let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
+        if let Some(store) = download_cfg.store {
+            tx.use_store(store);
+        }
 
         // If the previous installation was from a v1 manifest we need
         // to uninstall it first.
@@ -189,7 +251,7 @@ impl Manifestation {
         }
 
         // Install components
-        for (component, format, installer_file) in things_to_install {
+        for (component, unpacked_dir) in components {
             // For historical reasons, the rust-installer component
             // names are not the same as the dist manifest component
             // names. Some are just the component name some are the
@@ -204,23 +266,7 @@ impl Manifestation {
                 component.target.as_ref(),
             ));
 
-            let gz;
-            let xz;
-            let notification_converter = |notification: crate::utils::Notification<'_>| {
-                notify_handler(Notification::Utils(notification));
-            };
-            let reader =
-                utils::FileReaderWithProgress::new_file(&installer_file, &notification_converter)?;
-            let package: &dyn Package = match format {
-                Format::Gz => {
-                    gz = TarGzPackage::new(reader, temp_cfg)?;
-                    &gz
-                }
-                Format::Xz => {
-                    xz = TarXzPackage::new(reader, temp_cfg)?;
-                    &xz
-                }
-            };
+            let package = TarPackage::unpacked(unpacked_dir)?;
 
             // If the package doesn't contain the component that the
             // manifest says it does then somebody must be playing a joke on us.
@@ -244,6 +290,7 @@ impl Manifestation {
         // name/target. Needs to be fixed in rust-installer.
         let mut config = Config::new();
         config.components = update.final_component_list;
+        config.profile = update.profile;
         let config_str = config.stringify();
         let rel_config_path = prefix.rel_manifest_file(CONFIG_FILE);
         let config_path = prefix.path().join(&rel_config_path);
@@ -293,11 +340,7 @@ impl Manifestation {
         // names are not the same as the dist manifest component
         // names. Some are just the component name some are the
         // component name plus the target triple.
-        let name = component.name_in_manifest();
-        let short_name = component.short_name_in_manifest();
-        if let Some(c) = self.installation.find(&name)? {
-            tx = c.uninstall(tx)?;
-        } else if let Some(c) = self.installation.find(&short_name)? {
+        if let Some(c) = self.find_installed(component)? {
             tx = c.uninstall(tx)?;
         } else {
             notify_handler(Notification::MissingInstalledComponent(
@@ -308,6 +351,253 @@ impl Manifestation {
         Ok(tx)
     }
 
+    // The installed component for `component`, by whichever of its
+    // names rust-installer knows it
+    fn find_installed(&self, component: &Component) -> Result<Option<component::Component>> {
+        match self.installation.find(&component.name_in_manifest())? {
+            Some(c) => Ok(Some(c)),
+            None => self.installation.find(component.short_name_in_manifest()),
+        }
+    }
+
+    /// Checks the files of each installed component against the hashes
+    /// recorded when it was installed.
+    pub fn verify(&self) -> Result<Vec<(Component, Verification)>> {
+        let config = match self.read_config()? {
+            Some(config) => config,
+            None => return Ok(vec![]),
+        };
+
+        let mut result = vec![];
+        for component in config.components {
+            let verification = match self.find_installed(&component)? {
+                Some(c) => c.verify()?,
+                None => Verification {
+                    missing: vec![self
+                        .installation
+                        .prefix()
+                        .rel_manifest_file(&format!("manifest-{}", component.name_in_manifest()))],
+                    ..Verification::default()
+                },
+            };
+            result.push((component, verification));
+        }
+        Ok(result)
+    }
+
+    /// Reinstalls the components that `verify` finds damaged from
+    /// `manifest`, returning them.
+    pub fn repair(
+        &self,
+        manifest: &Manifest,
+        download_cfg: &DownloadCfg<'_>,
+        notify_handler: &dyn Fn(Notification<'_>),
+        toolchain_str: &str,
+    ) -> Result<Vec<Component>> {
+        let mut damaged = vec![];
+        for (component, verification) in self.verify()? {
+            if verification.is_intact() {
+                continue;
+            }
+            // Files modified in place are modified in the store they are
+            // linked from as well, which must not be linked to again
+            if let (Some(store), Some(installed)) =
+                (download_cfg.store, self.find_installed(&component)?)
+            {
+                let hashes = installed.hashes()?.unwrap_or_default();
+                for path in &verification.modified {
+                    if let Some(hash) = hashes.get(path) {
+                        store.remove_damaged(hash)?;
+                    }
+                }
+            }
+            damaged.push(component);
+        }
+        if damaged.is_empty() {
+            return Ok(damaged);
+        }
+
+        let changes = Changes {
+            reinstall_components: damaged.clone(),
+            ..Changes::none()
+        };
+        self.update(
+            manifest,
+            changes,
+            false,
+            download_cfg,
+            notify_handler,
+            toolchain_str,
+        )?;
+
+        Ok(damaged)
+    }
+
+    /// The installed manifest and configuration, for v2 installations.
+    pub fn snapshot(&self) -> Result<Option<Snapshot>> {
+        let prefix = self.installation.prefix();
+        let manifest_path = prefix.manifest_file(DIST_MANIFEST);
+        let config_path = prefix.manifest_file(CONFIG_FILE);
+        if !utils::path_exists(&manifest_path) || !utils::path_exists(&config_path) {
+            return Ok(None);
+        }
+
+        Ok(Some(Snapshot {
+            manifest: utils::read_file("installed manifest", &manifest_path)?,
+            config: utils::read_file("dist config", &config_path)?,
+        }))
+    }
+
+    /// Keeps `snapshot` for `rollback`, dropping the oldest snapshots
+    /// beyond `MAX_SNAPSHOTS`.
+    pub fn save_snapshot(
+        &self,
+        snapshot: Snapshot,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<()> {
+        let mut snapshots = self.list_snapshots()?;
+        let next = snapshots.last().map_or(0, |&(n, _)| n + 1);
+        let dir = self.snapshots_dir().join(next.to_string());
+        utils::ensure_dir_exists("snapshot", &dir, &|n| notify_handler(n.into()))?;
+        utils::write_file(
+            "snapshot manifest",
+            &dir.join(DIST_MANIFEST),
+            &snapshot.manifest,
+        )?;
+        utils::write_file("snapshot config", &dir.join(CONFIG_FILE), &snapshot.config)?;
+
+        snapshots.push((next, dir));
+        let excess = snapshots.len().saturating_sub(MAX_SNAPSHOTS);
+        for (_, dir) in snapshots.drain(..excess) {
+            utils::remove_dir("snapshot", &dir, &|n| notify_handler(n.into()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reinstalls the manifest and exact component set of the most recent
+    /// snapshot, which is then discarded. Packages still in the download
+    /// directory are reused. Returns the date of the restored manifest,
+    /// or `None` if there is nothing to roll back to.
+    pub fn rollback(
+        &self,
+        download_cfg: &DownloadCfg<'_>,
+        notify_handler: &dyn Fn(Notification<'_>),
+        toolchain_str: &str,
+    ) -> Result<Option<String>> {
+        let dir = match self.list_snapshots()?.pop() {
+            Some((_, dir)) => dir,
+            None => return Ok(None),
+        };
+        let snapshot = Self::read_snapshot(&dir)?;
+        let manifest = Manifest::parse(&snapshot.manifest)?;
+        let snapshot_config = Config::parse(&snapshot.config)?;
+        notify_handler(Notification::RollingBackToSnapshot(&manifest.date));
+
+        // Add the components of the snapshot and remove the rest, as far
+        // as the profile of the installation allows
+        let config = self.read_config()?;
+        let installed = config
+            .as_ref()
+            .map(|c| c.components.clone())
+            .unwrap_or_default();
+        let profile = config.and_then(|c| c.profile);
+        let rust_target_package = manifest
+            .get_package("rust")?
+            .get_target(Some(&self.target_triple))?;
+        let required = manifest.get_required_components(profile, &self.target_triple)?;
+        let is_optional = |c: &Component| {
+            rust_target_package.extensions.contains(c)
+                || (rust_target_package.components.contains(c) && !required.contains(c))
+        };
+        let changes = Changes {
+            add_extensions: snapshot_config
+                .components
+                .iter()
+                .filter(|c| is_optional(c) && !installed.contains(c))
+                .cloned()
+                .collect(),
+            remove_extensions: installed
+                .iter()
+                .filter(|c| is_optional(c) && !snapshot_config.components.contains(c))
+                .cloned()
+                .collect(),
+            reinstall_components: vec![],
+            profile: None,
+        };
+
+        self.update(
+            &manifest,
+            changes,
+            false,
+            download_cfg,
+            notify_handler,
+            toolchain_str,
+        )?;
+        utils::remove_dir("snapshot", &dir, &|n| notify_handler(n.into()))?;
+
+        Ok(Some(manifest.date))
+    }
+
+    /// The hashes of the packages of the installation and of its
+    /// snapshots, which reinstalls and rollbacks can take from the
+    /// download cache.
+    pub fn package_hashes(&self) -> Result<Vec<String>> {
+        let mut installations: Vec<_> = self.snapshot()?.into_iter().collect();
+        for (_, dir) in self.list_snapshots()? {
+            installations.push(Self::read_snapshot(&dir)?);
+        }
+
+        let mut hashes = vec![];
+        for installation in installations {
+            let manifest = Manifest::parse(&installation.manifest)?;
+            let config = Config::parse(&installation.config)?;
+            for component in &config.components {
+                let bins = manifest
+                    .get_package(component.short_name_in_manifest())
+                    .and_then(|p| p.get_target(component.target.as_ref()))
+                    .ok()
+                    .and_then(|t| t.bins.as_ref());
+                if let Some(bins) = bins {
+                    hashes.push(bins.hash.clone());
+                    hashes.extend(bins.xz_hash.clone());
+                    hashes.extend(bins.zst_hash.clone());
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    fn read_snapshot(dir: &Path) -> Result<Snapshot> {
+        Ok(Snapshot {
+            manifest: utils::read_file("snapshot manifest", &dir.join(DIST_MANIFEST))?,
+            config: utils::read_file("snapshot config", &dir.join(CONFIG_FILE))?,
+        })
+    }
+
+    fn snapshots_dir(&self) -> PathBuf {
+        self.installation.prefix().manifest_file(SNAPSHOTS_DIR)
+    }
+
+    // The saved snapshots, oldest first
+    fn list_snapshots(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let dir = self.snapshots_dir();
+        if !utils::is_directory(&dir) {
+            return Ok(vec![]);
+        }
+
+        let mut snapshots: Vec<_> = utils::read_dir("snapshots", &dir)?
+            .filter_map(io::Result::ok)
+            .filter_map(|e| {
+                let n = e.file_name().to_str()?.parse().ok()?;
+                Some((n, e.path()))
+            })
+            .collect();
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
     // Read the config file. Config files are presently only created
     // for v2 installations.
     pub fn read_config(&self) -> Result<Option<Config>> {
@@ -338,9 +628,11 @@ impl Manifestation {
         &self,
         new_manifest: &[String],
         update_hash: Option<&Path>,
-        temp_cfg: &temp::Cfg,
-        notify_handler: &dyn Fn(Notification<'_>),
+        download_cfg: &DownloadCfg<'_>,
     ) -> Result<Option<String>> {
+        let temp_cfg = download_cfg.temp_cfg;
+        let notify_handler = download_cfg.notify_handler;
+
         // If there's already a v2 installation then something has gone wrong
         if self.read_config()?.is_some() {
             return Err(
@@ -359,10 +651,7 @@ impl Manifestation {
             )
             .into());
         }
-        // Only replace once. The cost is inexpensive.
-        let url = url
-            .unwrap()
-            .replace(DEFAULT_DIST_SERVER, temp_cfg.dist_server.as_str());
+        let urls = download_cfg.dist_urls(url.unwrap());
 
         notify_handler(Notification::DownloadingComponent(
             "rust",
@@ -373,13 +662,14 @@ impl Manifestation {
         use std::path::PathBuf;
         let dld_dir = PathBuf::from("bogus");
         let dlcfg = DownloadCfg {
-            dist_root: "bogus",
+            dist_roots: &[],
             download_dir: &dld_dir,
-            temp_cfg,
-            notify_handler,
+            ..*download_cfg
         };
 
-        let dl = dlcfg.download_and_check(&url, update_hash, ".tar.gz")?;
+        let dl = dlcfg.with_fallback("rust", &urls, |url| {
+            dlcfg.download_and_check(url, update_hash, ".tar.gz")
+        })?;
         if dl.is_none() {
             return Ok(None);
         };
@@ -395,6 +685,9 @@ impl Manifestation {
 
         // Begin transaction
         let mut tx = Transaction::new(prefix.clone(), temp_cfg, notify_handler);
+        if let Some(store) = download_cfg.store {
+            tx.use_store(store);
+        }
 
         // Uninstall components
         for component in self.installation.list()? {
@@ -443,6 +736,7 @@ struct Update {
     components_to_install: Vec<Component>,
     final_component_list: Vec<Component>,
     missing_components: Vec<Component>,
+    profile: Option<Profile>,
 }
 
 impl Update {
@@ -461,7 +755,16 @@ impl Update {
         let rust_package = new_manifest.get_package("rust")?;
         let rust_target_package = rust_package.get_target(Some(&manifestation.target_triple))?;
 
-        changes.check_invariants(rust_target_package, &config);
+        // Installations keep the profile they were created with. Ones
+        // that predate profiles don't have one.
+        let profile = match config {
+            Some(ref config) => config.profile,
+            None => changes.profile,
+        };
+
+        let required_components =
+            new_manifest.get_required_components(profile, &manifestation.target_triple)?;
+        changes.check_invariants(rust_target_package, &required_components, &config);
 
         // The list of components already installed, empty if a new install
         let starting_list = config
@@ -474,6 +777,7 @@ impl Update {
             components_to_install: vec![],
             final_component_list: vec![],
             missing_components: vec![],
+            profile,
         };
 
         // Find the final list of components we want to be left with when
@@ -482,10 +786,11 @@ impl Update {
         result.build_final_component_list(
             &starting_list,
             rust_target_package,
+            &manifestation.target_triple,
             new_manifest,
             &changes,
             notify_handler,
-        );
+        )?;
 
         // If this is a full upgrade then the list of components to
         // uninstall is all that are currently installed, and those
@@ -509,6 +814,9 @@ impl Update {
             for component in &result.final_component_list {
                 if !starting_list.contains(component) {
                     result.components_to_install.push(component.clone());
+                } else if changes.reinstall_components.contains(component) {
+                    result.components_to_uninstall.push(component.clone());
+                    result.components_to_install.push(component.clone());
                 } else if changes.add_extensions.contains(&component) {
                     notify_handler(Notification::ComponentAlreadyInstalled(
                         &component.description(new_manifest),
@@ -528,19 +836,28 @@ impl Update {
         &mut self,
         starting_list: &[Component],
         rust_target_package: &TargetedPackage,
+        target_triple: &TargetTriple,
         new_manifest: &Manifest,
         changes: &Changes,
         notify_handler: &dyn Fn(Notification<'_>),
-    ) {
+    ) -> Result<()> {
         // Add components required by the package, according to the
-        // manifest
-        for required_component in &rust_target_package.components {
-            self.final_component_list.push(required_component.clone());
+        // manifest. A fresh install gets everything in its profile.
+        let required_components = match self.profile {
+            Some(profile) if starting_list.is_empty() => {
+                new_manifest.get_profile_components(profile, target_triple)?
+            }
+            profile => new_manifest.get_required_components(profile, target_triple)?,
+        };
+        for required_component in required_components {
+            self.final_component_list.push(required_component);
         }
 
         // Add requested extension components
         for extension in &changes.add_extensions {
-            self.final_component_list.push(extension.clone());
+            if !self.final_component_list.contains(extension) {
+                self.final_component_list.push(extension.clone());
+            }
         }
 
         // Add extensions that are already installed
@@ -582,6 +899,8 @@ impl Update {
                 }
             }
         }
+
+        Ok(())
     }
 
     fn nothing_changes(&self) -> bool {
@@ -664,18 +983,21 @@ impl Update {
                 None => continue,
                 Some(ref bins) => bins,
             };
-            let c_u_h = if let (Some(url), Some(hash)) = (bins.xz_url.clone(), bins.xz_hash.clone())
-            {
-                (component.clone(), Format::Xz, url, hash)
-            } else {
-                (
-                    component.clone(),
-                    Format::Gz,
-                    bins.url.clone(),
-                    bins.hash.clone(),
-                )
-            };
-            components_urls_and_hashes.push(c_u_h);
+            // The formats this build can unpack, best first, falling back
+            // to the gzip tarball every package has
+            let formats = [
+                #[cfg(feature = "zstd")]
+                (Format::Zst, &bins.zst_url, &bins.zst_hash),
+                (Format::Xz, &bins.xz_url, &bins.xz_hash),
+            ];
+            let (format, url, hash) = formats
+                .iter()
+                .find_map(|(format, url, hash)| match (url, hash) {
+                    (Some(url), Some(hash)) => Some((*format, url.clone(), hash.clone())),
+                    _ => None,
+                })
+                .unwrap_or_else(|| (Format::Gz, bins.url.clone(), bins.hash.clone()));
+            components_urls_and_hashes.push((component.clone(), format, url, hash));
         }
 
         Ok(components_urls_and_hashes)
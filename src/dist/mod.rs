@@ -4,6 +4,7 @@ pub use crate::dist::notifications::Notification;
 
 pub mod temp;
 
+pub mod cache;
 pub mod component;
 pub mod config;
 pub mod dist;
@@ -12,3 +13,4 @@ pub mod manifest;
 pub mod manifestation;
 pub mod notifications;
 pub mod prefix;
+pub mod signatures;
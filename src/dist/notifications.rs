@@ -16,9 +16,13 @@ pub enum Notification<'a> {
     NoUpdateHash(&'a Path),
     ChecksumValid(&'a str),
     SignatureValid(&'a str),
+    SignatureMissing(&'a str),
+    SignatureInvalid(&'a str),
     FileAlreadyDownloaded,
     CachedFileChecksumFailed,
     RollingBack,
+    RollingBackInterruptedTransaction(&'a Path),
+    FinishingInterruptedTransaction(&'a Path),
     ExtensionNotInstalled(&'a str),
     NonFatalError(&'a Error),
     MissingInstalledComponent(&'a str),
@@ -31,6 +35,20 @@ pub enum Notification<'a> {
     DownloadingLegacyManifest,
     ManifestChecksumFailedHack,
     ComponentUnavailable(&'a str, Option<&'a TargetTriple>),
+    /// Component downloads run concurrently, so their progress is
+    /// reported tagged with the component name.
+    ComponentDownloadContentLength(&'a str, u64),
+    ComponentDownloadDataReceived(&'a str, usize),
+    ComponentDownloadFinished(&'a str),
+    NightlyMissing(&'a str),
+    SkippingNightlyMissingComponents(&'a str, &'a [String]),
+    FoundNightlyWithComponents(&'a str),
+    RollingBackToSnapshot(&'a str),
+    ExpiredCachedDownload(&'a str),
+    /// A download from this url failed in a way another dist server
+    /// might not.
+    TryingNextDistServer(&'a str),
+    DownloadedFrom(&'a str, &'a str),
 }
 
 impl<'a> From<crate::utils::Notification<'a>> for Notification<'a> {
@@ -52,11 +70,17 @@ impl<'a> Notification<'a> {
             Temp(n) => n.level(),
             Utils(n) => n.level(),
             ChecksumValid(_)
+            | SignatureValid(_)
             | NoUpdateHash(_)
             | FileAlreadyDownloaded
-            | DownloadingLegacyManifest => NotificationLevel::Verbose,
+            | DownloadingLegacyManifest
+            | ComponentDownloadContentLength(_, _)
+            | ComponentDownloadDataReceived(_, _)
+            | ComponentDownloadFinished(_)
+            | NightlyMissing(_)
+            | ExpiredCachedDownload(_)
+            | DownloadedFrom(_, _) => NotificationLevel::Verbose,
             Extracting(_, _)
-            | SignatureValid(_)
             | DownloadingComponent(_, _, _)
             | InstallingComponent(_, _, _)
             | RemovingComponent(_, _, _)
@@ -64,12 +88,20 @@ impl<'a> Notification<'a> {
             | ComponentAlreadyInstalled(_)
             | ManifestChecksumFailedHack
             | RollingBack
+            | FinishingInterruptedTransaction(_)
             | DownloadingManifest(_)
-            | DownloadedManifest(_, _) => NotificationLevel::Info,
+            | DownloadedManifest(_, _)
+            | SkippingNightlyMissingComponents(_, _)
+            | FoundNightlyWithComponents(_)
+            | RollingBackToSnapshot(_)
+            | TryingNextDistServer(_) => NotificationLevel::Info,
             CantReadUpdateHash(_)
             | ExtensionNotInstalled(_)
             | MissingInstalledComponent(_)
             | CachedFileChecksumFailed
+            | RollingBackInterruptedTransaction(_)
+            | SignatureMissing(_)
+            | SignatureInvalid(_)
             | ComponentUnavailable(_, _) => NotificationLevel::Warn,
             NonFatalError(_) => NotificationLevel::Error,
         }
@@ -92,9 +124,21 @@ impl<'a> Display for Notification<'a> {
             NoUpdateHash(path) => write!(f, "no update hash at: '{}'", path.display()),
             ChecksumValid(_) => write!(f, "checksum passed"),
             SignatureValid(_) => write!(f, "signature valid"),
+            SignatureMissing(url) => write!(f, "no signature found for '{}'", url),
+            SignatureInvalid(url) => write!(f, "signature verification failed for '{}'", url),
             FileAlreadyDownloaded => write!(f, "reusing previously downloaded file"),
             CachedFileChecksumFailed => write!(f, "bad checksum for cached download"),
             RollingBack => write!(f, "rolling back changes"),
+            RollingBackInterruptedTransaction(path) => write!(
+                f,
+                "rolling back the changes to '{}' that were interrupted",
+                path.display()
+            ),
+            FinishingInterruptedTransaction(path) => write!(
+                f,
+                "finishing the changes to '{}' that were interrupted",
+                path.display()
+            ),
             ExtensionNotInstalled(c) => write!(f, "extension '{}' was not installed", c),
             NonFatalError(e) => write!(f, "{}", e),
             MissingInstalledComponent(c) => {
@@ -155,6 +199,43 @@ impl<'a> Display for Notification<'a> {
                     write!(f, "component '{}' is not available anymore", pkg)
                 }
             }
+            ComponentDownloadContentLength(c, len) => {
+                write!(f, "download size of component '{}' is: '{}'", c, len)
+            }
+            ComponentDownloadDataReceived(c, len) => {
+                write!(
+                    f,
+                    "received some data of size {} for component '{}'",
+                    len, c
+                )
+            }
+            ComponentDownloadFinished(c) => write!(f, "download of component '{}' finished", c),
+            NightlyMissing(date) => write!(f, "no nightly was published on {}", date),
+            SkippingNightlyMissingComponents(date, components) => write!(
+                f,
+                "skipping nightly from {}, which is missing: {}",
+                date,
+                components.join(", ")
+            ),
+            ExpiredCachedDownload(hash) => {
+                write!(f, "removing expired download '{}' from the cache", hash)
+            }
+            RollingBackToSnapshot(date) => {
+                write!(f, "rolling back to the installation from {}", date)
+            }
+            FoundNightlyWithComponents(date) => write!(
+                f,
+                "using nightly from {}, the latest with all requested components",
+                date
+            ),
+            TryingNextDistServer(url) => {
+                write!(
+                    f,
+                    "could not download '{}', trying the next dist server",
+                    url
+                )
+            }
+            DownloadedFrom(name, url) => write!(f, "downloaded {} from '{}'", name, url),
         }
     }
 }
@@ -0,0 +1,82 @@
+//! Verification of the detached GPG signatures published alongside
+//! artifacts on the distribution server.
+
+use crate::errors::*;
+
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do when a downloaded artifact has a missing or invalid signature.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignatureCheck {
+    /// Refuse to use the artifact
+    Enforce,
+    /// Warn and carry on using the artifact
+    Warn,
+}
+
+impl SignatureCheck {
+    pub fn names() -> &'static [&'static str] {
+        &["enforce", "warn"]
+    }
+}
+
+impl Default for SignatureCheck {
+    fn default() -> Self {
+        SignatureCheck::Enforce
+    }
+}
+
+impl FromStr for SignatureCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "enforce" => Ok(SignatureCheck::Enforce),
+            "warn" => Ok(SignatureCheck::Warn),
+            _ => Err(ErrorKind::InvalidSignatureCheck(s.to_owned()).into()),
+        }
+    }
+}
+
+impl fmt::Display for SignatureCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureCheck::Enforce => write!(f, "enforce"),
+            SignatureCheck::Warn => write!(f, "warn"),
+        }
+    }
+}
+
+/// Checks whether `signature`, an ASCII-armored detached signature,
+/// was made over `content` by any of the keys (or their subkeys) in the
+/// ASCII-armored public key block `key`.
+///
+/// A signature that can't be parsed is treated as invalid; a key that
+/// can't be parsed is an error.
+pub fn verify_signature(content: &[u8], signature: &str, key: &str) -> Result<bool> {
+    let keys = SignedPublicKey::from_string_many(key)
+        .and_then(|(keys, _)| keys.collect::<::std::result::Result<Vec<_>, _>>())
+        .map_err(|e| ErrorKind::InvalidPgpKey(e.to_string()))?;
+
+    let signatures = match StandaloneSignature::from_string_many(signature)
+        .and_then(|(sigs, _)| sigs.collect::<::std::result::Result<Vec<_>, _>>())
+    {
+        Ok(signatures) => signatures,
+        Err(_) => return Ok(false),
+    };
+
+    let valid = signatures.iter().any(|signature| {
+        keys.iter().any(|key| {
+            signature.verify(key, content).is_ok()
+                || key
+                    .public_subkeys
+                    .iter()
+                    .any(|subkey| signature.verify(subkey, content).is_ok())
+        })
+    });
+
+    Ok(valid)
+}
@@ -1,4 +1,5 @@
 use crate::component_for_bin;
+use crate::dist::dist::Profile;
 use crate::dist::manifest::{Component, Manifest};
 use crate::dist::temp;
 use error_chain::error_chain;
@@ -59,6 +60,13 @@ error_chain! {
             description("could not create directory")
             display("could not create {} directory: '{}'", name, path.display())
         }
+        LockingFile {
+            name: &'static str,
+            path: PathBuf,
+        } {
+            description("could not lock file")
+            display("could not lock {} file: '{}'", name, path.display())
+        }
         ExpectedType(t: &'static str, n: String) {
             description("expected type")
             display("expected type: '{}' for '{}'", t, n)
@@ -201,6 +209,26 @@ error_chain! {
                     expected,
                     calculated)
         }
+        SignatureMissing(url: String) {
+            description("signature missing")
+            display("no signature found for '{}'", url)
+        }
+        SignatureVerificationFailed(url: String) {
+            description("signature verification failed")
+            display("signature verification failed for '{}'", url)
+        }
+        InvalidPgpKey(e: String) {
+            description("invalid PGP key")
+            display("could not parse PGP key: {}", e)
+        }
+        InvalidProfile(p: String) {
+            description("invalid profile name")
+            display("invalid profile name: '{}'; valid names are: {}", p, valid_profile_names())
+        }
+        InvalidSignatureCheck(s: String) {
+            description("invalid signature check mode")
+            display("invalid signature check mode: '{}'", s)
+        }
         ComponentConflict {
             name: String,
             path: PathBuf,
@@ -249,6 +277,14 @@ error_chain! {
         ComponentFilePermissionsFailed {
             description("error setting file permissions during install")
         }
+        NoNightlyWithComponents(c: Vec<String>, days: u32) {
+            description("no recent nightly has all the requested components")
+            display("no nightly in the last {} days has all of the components: {}", days, c.join(", "))
+        }
+        ComponentSearchRequiresNightly(t: String) {
+            description("only nightly toolchains can be searched for components")
+            display("'--with-components' requires an undated nightly toolchain, not '{}'", t)
+        }
         ComponentDownloadFailed(c: String) {
             description("component download failed")
             display("component download failed for {}", c)
@@ -280,6 +316,10 @@ error_chain! {
             description("toolchain is not installed")
             display("toolchain '{}' is not installed", t)
         }
+        NoToolchainSnapshot(t: String) {
+            description("toolchain has no earlier installation to roll back to")
+            display("toolchain '{}' has no earlier installation to roll back to", t)
+        }
         OverrideToolchainNotInstalled(t: String) {
             description("override toolchain is not installed")
             display("override toolchain '{}' is not installed", t)
@@ -314,6 +354,10 @@ error_chain! {
         ParsingSettings(e: toml::de::Error) {
             description("error parsing settings")
         }
+        ParsingToolchainFile(path: PathBuf, e: toml::de::Error) {
+            description("error parsing toolchain file")
+            display("could not parse toolchain file '{}': {}", path.display(), e)
+        }
         RemovingRequiredComponent(t: String, c: String) {
             description("required component cannot be removed")
             display("component {} is required for toolchain '{}' and cannot be removed",
@@ -360,6 +404,14 @@ fn component_unavailable_msg(cs: &[Component], manifest: &Manifest) -> String {
     String::from_utf8(buf).expect("")
 }
 
+fn valid_profile_names() -> String {
+    Profile::names()
+        .iter()
+        .map(|s| format!("'{}'", s))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn install_msg(bin: &str, toolchain: &str, is_default: bool) -> String {
     match component_for_bin(bin) {
         Some(c) => format!("\nTo install, run `rustup component add {}{}`", c, {
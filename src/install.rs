@@ -4,6 +4,7 @@
 use crate::dist::component::{Components, Package, TarGzPackage, Transaction};
 use crate::dist::dist;
 use crate::dist::download::DownloadCfg;
+use crate::dist::manifest::Component;
 use crate::dist::prefix::InstallPrefix;
 use crate::dist::temp;
 use crate::dist::Notification;
@@ -16,9 +17,12 @@ pub enum InstallMethod<'a> {
     Copy(&'a Path),
     Link(&'a Path),
     Installer(&'a Path, &'a temp::Cfg),
-    // bool is whether to force an update
+    // The components are added to the installation, and the bool is
+    // whether to force an update
     Dist(
         &'a dist::ToolchainDesc,
+        Option<dist::Profile>,
+        &'a [Component],
         Option<&'a Path>,
         DownloadCfg<'a>,
         bool,
@@ -50,14 +54,22 @@ impl<'a> InstallMethod<'a> {
                 InstallMethod::tar_gz(src, path, &temp_cfg, notify_handler)?;
                 Ok(true)
             }
-            InstallMethod::Dist(toolchain, update_hash, dl_cfg, force_update) => {
+            InstallMethod::Dist(
+                toolchain,
+                profile,
+                components,
+                update_hash,
+                dl_cfg,
+                force_update,
+            ) => {
                 let prefix = &InstallPrefix::from(path.to_owned());
                 let maybe_new_hash = dist::update_from_dist(
                     dl_cfg,
                     update_hash,
                     toolchain,
+                    profile,
                     prefix,
-                    &[],
+                    components,
                     &[],
                     force_update,
                 )?;
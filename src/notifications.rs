@@ -3,8 +3,11 @@ use std::path::{Path, PathBuf};
 
 use crate::errors::*;
 
+use crate::dist::dist::Profile;
+use crate::dist::signatures::SignatureCheck;
 use crate::dist::temp;
 use crate::utils::notify::NotificationLevel;
+use download::RetryPolicy;
 
 #[derive(Debug)]
 pub enum Notification<'a> {
@@ -14,6 +17,15 @@ pub enum Notification<'a> {
 
     SetDefaultToolchain(&'a str),
     SetOverrideToolchain(&'a Path, &'a str),
+    SetSignatureCheck(SignatureCheck),
+    SetProfile(Profile),
+    SetDownloadRetention(Option<u32>),
+    SetSharedStore(bool),
+    SetDistServers(&'a [String]),
+    SetDownloadRetries(Option<u32>),
+    SetMaxDownloadRate(Option<u64>),
+    PinnedToolchain(&'a str),
+    UnpinnedToolchain(&'a str),
     LookingForToolchain(&'a str),
     ToolchainDirectory(&'a Path, &'a str),
     UpdatingToolchain(&'a str),
@@ -66,6 +78,15 @@ impl<'a> Notification<'a> {
             | UpdateHashMatches => NotificationLevel::Verbose,
             SetDefaultToolchain(_)
             | SetOverrideToolchain(_, _)
+            | SetSignatureCheck(_)
+            | SetProfile(_)
+            | SetDownloadRetention(_)
+            | SetSharedStore(_)
+            | SetDistServers(_)
+            | SetDownloadRetries(_)
+            | SetMaxDownloadRate(_)
+            | PinnedToolchain(_)
+            | UnpinnedToolchain(_)
             | UsingExistingToolchain(_)
             | UninstallingToolchain(_)
             | UninstalledToolchain(_)
@@ -86,6 +107,51 @@ impl<'a> Display for Notification<'a> {
             Utils(n) => n.fmt(f),
             Temp(n) => n.fmt(f),
             SetDefaultToolchain(name) => write!(f, "default toolchain set to '{}'", name),
+            SetSignatureCheck(check) => write!(f, "signature check set to '{}'", check),
+            SetProfile(profile) => write!(f, "profile set to '{}'", profile),
+            SetDownloadRetention(Some(days)) => {
+                write!(
+                    f,
+                    "downloads will be kept for {} days after installing",
+                    days
+                )
+            }
+            SetDownloadRetention(None) => {
+                write!(f, "downloads will be removed after installing")
+            }
+            PinnedToolchain(name) => write!(
+                f,
+                "toolchain '{}' pinned, `rustup update` will only update it when named",
+                name
+            ),
+            UnpinnedToolchain(name) => write!(f, "toolchain '{}' unpinned", name),
+            SetSharedStore(true) => write!(f, "toolchains will share identical files"),
+            SetSharedStore(false) => write!(f, "toolchains will have their own copy of every file"),
+            SetDistServers([]) => {
+                write!(f, "toolchains will be downloaded from the default server")
+            }
+            SetDistServers(servers) => write!(
+                f,
+                "toolchains will be downloaded from the first of: {}",
+                servers.join(", ")
+            ),
+            SetDownloadRetries(Some(0)) => write!(f, "failed downloads will not be retried"),
+            SetDownloadRetries(Some(retries)) => {
+                write!(
+                    f,
+                    "failed downloads will be retried up to {} times",
+                    retries
+                )
+            }
+            SetDownloadRetries(None) => write!(
+                f,
+                "failed downloads will be retried up to {} times",
+                RetryPolicy::default().max_attempts - 1
+            ),
+            SetMaxDownloadRate(Some(rate)) => {
+                write!(f, "downloads will be limited to {} bytes per second", rate)
+            }
+            SetMaxDownloadRate(None) => write!(f, "downloads will not be limited"),
             SetOverrideToolchain(path, name) => write!(
                 f,
                 "override toolchain for '{}' set to '{}'",
@@ -1,30 +1,48 @@
+use crate::dist::dist::Profile;
+use crate::dist::signatures::SignatureCheck;
 use crate::errors::*;
 use crate::notifications::*;
 use crate::toml_utils::*;
+use crate::utils::lock::FileLock;
 use crate::utils::utils;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 pub const SUPPORTED_METADATA_VERSIONS: [&str; 2] = ["2", "12"];
 pub const DEFAULT_METADATA_VERSION: &str = "12";
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone)]
 pub struct SettingsFile {
     path: PathBuf,
     cache: RefCell<Option<Settings>>,
+    notify_handler: Arc<dyn Fn(Notification<'_>)>,
 }
 
 impl SettingsFile {
-    pub fn new(path: PathBuf) -> Self {
+    pub fn new(path: PathBuf, notify_handler: Arc<dyn Fn(Notification<'_>)>) -> Self {
         SettingsFile {
             path,
             cache: RefCell::new(None),
+            notify_handler,
+        }
+    }
+    // Other rustup processes read and write the settings too, so each
+    // does so holding the lock next to the file
+    fn lock(&self, exclusive: bool) -> Result<FileLock> {
+        let lock_path = self.path.with_extension("lock");
+        let notify_handler = |n: crate::utils::Notification<'_>| (self.notify_handler)(n.into());
+        if exclusive {
+            FileLock::exclusive(&lock_path, &notify_handler)
+        } else {
+            FileLock::shared(&lock_path, &notify_handler)
         }
     }
     fn write_settings(&self) -> Result<()> {
         let s = self.cache.borrow().as_ref().unwrap().clone();
-        utils::write_file("settings", &self.path, &s.stringify())?;
+        utils::write_file_atomically("settings", &self.path, &s.stringify())?;
         Ok(())
     }
     fn read_settings(&self) -> Result<()> {
@@ -47,12 +65,20 @@ impl SettingsFile {
         Ok(())
     }
     pub fn with<T, F: FnOnce(&Settings) -> Result<T>>(&self, f: F) -> Result<T> {
-        self.read_settings()?;
+        if self.cache.borrow().is_none() {
+            // Reading a missing file writes the default settings
+            let _lock = self.lock(!utils::is_file(&self.path))?;
+            self.read_settings()?;
+        }
 
         // Settings can no longer be None so it's OK to unwrap
         f(self.cache.borrow().as_ref().unwrap())
     }
     pub fn with_mut<T, F: FnOnce(&mut Settings) -> Result<T>>(&self, f: F) -> Result<T> {
+        let _lock = self.lock(true)?;
+        // Another process may have changed the settings since they
+        // were read, and those changes must not be lost
+        *self.cache.borrow_mut() = None;
         self.read_settings()?;
 
         // Settings can no longer be None so it's OK to unwrap
@@ -67,7 +93,41 @@ pub struct Settings {
     pub version: String,
     pub default_host_triple: Option<String>,
     pub default_toolchain: Option<String>,
+    pub signature_check: Option<SignatureCheck>,
+    pub profile: Option<Profile>,
+    /// Days to keep packages in the download cache after installing them
+    pub download_retention: Option<u32>,
+    /// Whether to share identical files between toolchains
+    pub shared_store: Option<bool>,
+    /// The servers to download toolchains from, in the order they are
+    /// tried. The default server is used when this is empty.
+    pub dist_servers: Vec<String>,
+    /// How many times to retry a failed download
+    pub download_retries: Option<u32>,
+    /// The most bytes per second to download
+    pub max_download_rate: Option<u64>,
+    /// The proxy to download through instead of the one in the
+    /// environment
+    pub proxy: Option<String>,
+    /// Hosts to download from without the proxy
+    pub no_proxy: Vec<String>,
+    /// Certificate authorities to trust as well as the system's
+    pub ca_certs: Vec<String>,
+    /// A PKCS #12 archive of the certificate to show servers that ask
+    pub client_cert: Option<String>,
+    /// Seconds to wait for a connection
+    pub connect_timeout: Option<u64>,
+    /// Downloads fail when they stay below `low_speed_limit` bytes per
+    /// second for `low_speed_time` seconds
+    pub low_speed_limit: Option<u32>,
+    pub low_speed_time: Option<u64>,
     pub overrides: BTreeMap<String, String>,
+    /// Toolchains that `rustup update` leaves as they are, unless they
+    /// are named
+    pub pinned: Vec<String>,
+    /// Keys this version doesn't know about, kept for the newer version
+    /// that wrote them
+    pub unknown: toml::value::Table,
 }
 
 impl Default for Settings {
@@ -76,7 +136,23 @@ impl Default for Settings {
             version: DEFAULT_METADATA_VERSION.to_owned(),
             default_host_triple: None,
             default_toolchain: None,
+            signature_check: None,
+            profile: None,
+            download_retention: None,
+            shared_store: None,
+            dist_servers: vec![],
+            download_retries: None,
+            max_download_rate: None,
+            proxy: None,
+            no_proxy: vec![],
+            ca_certs: vec![],
+            client_cert: None,
+            connect_timeout: None,
+            low_speed_limit: None,
+            low_speed_time: None,
             overrides: BTreeMap::new(),
+            pinned: vec![],
+            unknown: toml::value::Table::new(),
         }
     }
 }
@@ -134,15 +210,68 @@ impl Settings {
         if !SUPPORTED_METADATA_VERSIONS.contains(&&*version) {
             return Err(ErrorKind::UnknownMetadataVersion(version).into());
         }
+        let signature_check = get_opt_string(&mut table, "signature_check", path)?
+            .map(|s| s.parse())
+            .transpose()?;
+        let profile = get_opt_string(&mut table, "profile", path)?
+            .map(|s| s.parse())
+            .transpose()?;
+        let shared_store = get_opt_bool(&mut table, "shared_store", path)?;
         Ok(Settings {
             version,
             default_host_triple: get_opt_string(&mut table, "default_host_triple", path)?,
             default_toolchain: get_opt_string(&mut table, "default_toolchain", path)?,
+            signature_check,
+            profile,
+            download_retention: get_opt_number(
+                &mut table,
+                "download_retention",
+                path,
+                "number of days",
+            )?,
+            shared_store,
+            dist_servers: get_string_array(&mut table, "dist_servers", path)?,
+            download_retries: get_opt_number(
+                &mut table,
+                "download_retries",
+                path,
+                "number of retries",
+            )?,
+            max_download_rate: get_opt_number(
+                &mut table,
+                "max_download_rate",
+                path,
+                "number of bytes per second",
+            )?,
+            proxy: get_opt_string(&mut table, "proxy", path)?,
+            no_proxy: get_string_array(&mut table, "no_proxy", path)?,
+            ca_certs: get_string_array(&mut table, "ca_certs", path)?,
+            client_cert: get_opt_string(&mut table, "client_cert", path)?,
+            connect_timeout: get_opt_number(
+                &mut table,
+                "connect_timeout",
+                path,
+                "number of seconds",
+            )?,
+            low_speed_limit: get_opt_number(
+                &mut table,
+                "low_speed_limit",
+                path,
+                "number of bytes per second",
+            )?,
+            low_speed_time: get_opt_number(
+                &mut table,
+                "low_speed_time",
+                path,
+                "number of seconds",
+            )?,
             overrides: Self::table_to_overrides(&mut table, path)?,
+            pinned: get_string_array(&mut table, "pinned", path)?,
+            unknown: table,
         })
     }
     pub fn into_toml(self) -> toml::value::Table {
-        let mut result = toml::value::Table::new();
+        let mut result = self.unknown;
 
         result.insert("version".to_owned(), toml::Value::String(self.version));
 
@@ -154,6 +283,95 @@ impl Settings {
             result.insert("default_toolchain".to_owned(), toml::Value::String(v));
         }
 
+        if let Some(v) = self.signature_check {
+            result.insert(
+                "signature_check".to_owned(),
+                toml::Value::String(v.to_string()),
+            );
+        }
+
+        if let Some(v) = self.profile {
+            result.insert("profile".to_owned(), toml::Value::String(v.to_string()));
+        }
+
+        if let Some(v) = self.download_retention {
+            result.insert(
+                "download_retention".to_owned(),
+                toml::Value::Integer(i64::from(v)),
+            );
+        }
+
+        if let Some(v) = self.shared_store {
+            result.insert("shared_store".to_owned(), toml::Value::Boolean(v));
+        }
+
+        if !self.dist_servers.is_empty() {
+            let servers = self
+                .dist_servers
+                .into_iter()
+                .map(toml::Value::String)
+                .collect();
+            result.insert("dist_servers".to_owned(), toml::Value::Array(servers));
+        }
+
+        if let Some(v) = self.download_retries {
+            result.insert(
+                "download_retries".to_owned(),
+                toml::Value::Integer(i64::from(v)),
+            );
+        }
+
+        if let Some(v) = self.max_download_rate {
+            result.insert(
+                "max_download_rate".to_owned(),
+                toml::Value::Integer(i64::try_from(v).unwrap_or(i64::MAX)),
+            );
+        }
+
+        if let Some(v) = self.proxy {
+            result.insert("proxy".to_owned(), toml::Value::String(v));
+        }
+
+        if !self.no_proxy.is_empty() {
+            let hosts = self.no_proxy.into_iter().map(toml::Value::String).collect();
+            result.insert("no_proxy".to_owned(), toml::Value::Array(hosts));
+        }
+
+        if !self.ca_certs.is_empty() {
+            let certs = self.ca_certs.into_iter().map(toml::Value::String).collect();
+            result.insert("ca_certs".to_owned(), toml::Value::Array(certs));
+        }
+
+        if let Some(v) = self.client_cert {
+            result.insert("client_cert".to_owned(), toml::Value::String(v));
+        }
+
+        if let Some(v) = self.connect_timeout {
+            result.insert(
+                "connect_timeout".to_owned(),
+                toml::Value::Integer(i64::try_from(v).unwrap_or(i64::MAX)),
+            );
+        }
+
+        if let Some(v) = self.low_speed_limit {
+            result.insert(
+                "low_speed_limit".to_owned(),
+                toml::Value::Integer(i64::from(v)),
+            );
+        }
+
+        if let Some(v) = self.low_speed_time {
+            result.insert(
+                "low_speed_time".to_owned(),
+                toml::Value::Integer(i64::try_from(v).unwrap_or(i64::MAX)),
+            );
+        }
+
+        if !self.pinned.is_empty() {
+            let pinned = self.pinned.into_iter().map(toml::Value::String).collect();
+            result.insert("pinned".to_owned(), toml::Value::Array(pinned));
+        }
+
         let overrides = Self::overrides_to_table(self.overrides);
         result.insert("overrides".to_owned(), toml::Value::Table(overrides));
 
@@ -184,3 +402,59 @@ impl Settings {
         result
     }
 }
+
+fn get_opt_number<T: TryFrom<i64>>(
+    table: &mut toml::value::Table,
+    key: &str,
+    path: &str,
+    what: &'static str,
+) -> Result<Option<T>> {
+    get_opt_integer(table, key, path)?
+        .map(|n| T::try_from(n).map_err(|_| ErrorKind::ExpectedType(what, path.to_owned() + key)))
+        .transpose()
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn changes_keep_what_other_processes_wrote() {
+        let tempdir = TempDir::new("rustup").unwrap();
+        let path = tempdir.path().join("settings.toml");
+        let settings_file = SettingsFile::new(path.clone(), Arc::new(|_| ()));
+        settings_file
+            .with_mut(|s| {
+                s.default_toolchain = Some("stable".to_owned());
+                Ok(())
+            })
+            .unwrap();
+
+        // Another process adds an override, and a key only it knows about
+        let mut settings = Settings::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        settings
+            .overrides
+            .insert("/other".to_owned(), "beta".to_owned());
+        settings
+            .unknown
+            .insert("future_key".to_owned(), toml::Value::Boolean(true));
+        fs::write(&path, settings.stringify()).unwrap();
+
+        settings_file
+            .with_mut(|s| {
+                s.profile = Some("minimal".parse().unwrap());
+                Ok(())
+            })
+            .unwrap();
+
+        let settings = Settings::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(settings.default_toolchain.as_deref(), Some("stable"));
+        assert_eq!(settings.overrides["/other"], "beta");
+        assert_eq!(settings.unknown["future_key"], toml::Value::Boolean(true));
+        assert!(settings.profile.is_some());
+        assert!(!tempdir.path().join("settings.toml.tmp").exists());
+    }
+}
@@ -1,13 +1,15 @@
 use crate::config::Cfg;
-use crate::dist::dist::ToolchainDesc;
+use crate::dist::component::Verification;
+use crate::dist::dist::{self, Profile, ToolchainDesc};
 use crate::dist::download::DownloadCfg;
-use crate::dist::manifest::Component;
+use crate::dist::manifest::{Component, Manifest, ManifestDiff};
 use crate::dist::manifestation::{Changes, Manifestation};
 use crate::dist::prefix::InstallPrefix;
 use crate::env_var;
 use crate::errors::*;
 use crate::install::{self, InstallMethod};
 use crate::notifications::*;
+use crate::utils::lock::FileLock;
 use crate::utils::utils;
 
 use std::env;
@@ -40,6 +42,8 @@ pub enum UpdateStatus {
     Installed,
     Updated,
     Unchanged,
+    /// Left as it is because it is pinned
+    Pinned,
 }
 
 impl<'a> Toolchain<'a> {
@@ -83,6 +87,7 @@ impl<'a> Toolchain<'a> {
         utils::assert_is_directory(&self.path)
     }
     pub fn remove(&self) -> Result<()> {
+        let _lock = self.lock()?;
         if self.exists() || self.is_symlink() {
             (self.cfg.notify_handler)(Notification::UninstallingToolchain(&self.name));
         } else {
@@ -96,9 +101,27 @@ impl<'a> Toolchain<'a> {
         if !self.exists() {
             (self.cfg.notify_handler)(Notification::UninstalledToolchain(&self.name));
         }
-        result
+        result?;
+
+        // Files the toolchain shared may not be used by any other
+        self.cfg.store.collect_garbage()?;
+        Ok(())
+    }
+    // Other rustup processes may be changing the toolchain too, so it is
+    // only changed holding its lock
+    fn lock(&self) -> Result<FileLock> {
+        let lock_path = self
+            .cfg
+            .rustup_dir
+            .join("locks")
+            .join(format!("{}.lock", self.name));
+        FileLock::exclusive(&lock_path, &|n| (self.cfg.notify_handler)(n.into()))
     }
     fn install(&self, install_method: InstallMethod<'_>) -> Result<UpdateStatus> {
+        let _lock = self.lock()?;
+        self.install_locked(install_method)
+    }
+    fn install_locked(&self, install_method: InstallMethod<'_>) -> Result<UpdateStatus> {
         assert!(self.is_valid_install_method(install_method));
         let exists = self.exists();
         if exists {
@@ -127,8 +150,10 @@ impl<'a> Toolchain<'a> {
     fn install_if_not_installed(&self, install_method: InstallMethod<'_>) -> Result<UpdateStatus> {
         assert!(self.is_valid_install_method(install_method));
         (self.cfg.notify_handler)(Notification::LookingForToolchain(&self.name));
+        // Another process may have installed it while this one waited
+        let _lock = self.lock()?;
         if !self.exists() {
-            Ok(self.install(install_method)?)
+            Ok(self.install_locked(install_method)?)
         } else {
             (self.cfg.notify_handler)(Notification::UsingExistingToolchain(&self.name));
             Ok(UpdateStatus::Unchanged)
@@ -150,21 +175,44 @@ impl<'a> Toolchain<'a> {
         }
     }
 
-    fn download_cfg(&self) -> DownloadCfg<'_> {
-        DownloadCfg {
-            dist_root: &self.cfg.dist_root_url,
+    fn download_cfg(&self) -> Result<DownloadCfg<'_>> {
+        Ok(DownloadCfg {
+            dist_roots: &self.cfg.dist_root_urls,
             temp_cfg: &self.cfg.temp_cfg,
             download_dir: &self.cfg.download_dir,
+            gpg_key: &self.cfg.gpg_key,
+            signature_check: self.cfg.signature_check()?,
+            retention: self.cfg.download_retention()?,
+            store: if self.cfg.shared_store()? {
+                Some(&self.cfg.store)
+            } else {
+                None
+            },
+            backend: &self.cfg.download_backend,
+            options: self.cfg.download_options()?,
             notify_handler: &*self.dist_handler,
-        }
+        })
     }
 
     pub fn install_from_dist(&self, force_update: bool) -> Result<UpdateStatus> {
+        self.install_from_dist_with_components(force_update, self.cfg.get_profile()?, &[])
+    }
+
+    /// Installs or updates the toolchain, adding `components` to it in
+    /// the same transaction.
+    pub fn install_from_dist_with_components(
+        &self,
+        force_update: bool,
+        profile: Profile,
+        components: &[Component],
+    ) -> Result<UpdateStatus> {
         let update_hash = self.update_hash()?;
         self.install(InstallMethod::Dist(
             &self.desc()?,
+            Some(profile),
+            components,
             update_hash.as_ref().map(|p| &**p),
-            self.download_cfg(),
+            self.download_cfg()?,
             force_update,
         ))
     }
@@ -173,11 +221,44 @@ impl<'a> Toolchain<'a> {
         let update_hash = self.update_hash()?;
         self.install_if_not_installed(InstallMethod::Dist(
             &self.desc()?,
+            Some(self.cfg.get_profile()?),
+            &[],
             update_hash.as_ref().map(|p| &**p),
-            self.download_cfg(),
+            self.download_cfg()?,
             false,
         ))
     }
+    /// Installs the most recent nightly that has all of `components`
+    /// available for this toolchain's target, together with them and
+    /// `extra_components`.
+    pub fn install_nightly_with_components(
+        &self,
+        force_update: bool,
+        components: &[String],
+        extra_components: &[Component],
+    ) -> Result<UpdateStatus> {
+        let desc = self.desc()?;
+        if desc.channel != "nightly" || desc.date.is_some() {
+            return Err(ErrorKind::ComponentSearchRequiresNightly(self.name.clone()).into());
+        }
+
+        let download_cfg = self.download_cfg()?;
+        let nightly = dist::find_nightly_with_components(download_cfg, &desc, components)?;
+        let components: Vec<_> = components
+            .iter()
+            .map(|c| Component::new(c.to_string(), Some(desc.target.clone())))
+            .chain(extra_components.iter().cloned())
+            .collect();
+        let update_hash = self.update_hash()?;
+        self.install(InstallMethod::Dist(
+            &nightly,
+            Some(self.cfg.get_profile()?),
+            &components,
+            update_hash.as_ref().map(|p| &**p),
+            download_cfg,
+            force_update,
+        ))
+    }
     pub fn is_custom(&self) -> bool {
         ToolchainDesc::from_str(&self.name).is_err()
     }
@@ -224,9 +305,14 @@ impl<'a> Toolchain<'a> {
             if let Some(url) = url {
                 // Download to a local file
                 let local_installer = self.cfg.temp_cfg.new_file_with_ext("", ".tar.gz")?;
-                utils::download_file(&url, &local_installer, None, &|n| {
-                    (self.cfg.notify_handler)(n.into())
-                })?;
+                utils::download_file(
+                    &url,
+                    &local_installer,
+                    None,
+                    &*self.cfg.download_backend,
+                    self.cfg.download_options()?,
+                    &|n| (self.cfg.notify_handler)(n.into()),
+                )?;
                 self.install(InstallMethod::Installer(
                     &local_installer,
                     &self.cfg.temp_cfg,
@@ -462,6 +548,168 @@ impl<'a> Toolchain<'a> {
         })
     }
 
+    /// The version of the rust package in the installed manifest
+    pub fn show_version(&self) -> Result<Option<String>> {
+        if !self.exists() {
+            return Ok(None);
+        }
+
+        let toolchain = ToolchainDesc::from_str(&self.name)
+            .chain_err(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let manifestation = Manifestation::open(prefix, toolchain.target)?;
+
+        match manifestation.load_manifest()? {
+            Some(manifest) => Ok(Some(manifest.get_rust_version()?.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Compares the installed manifest with the latest one for this
+    /// toolchain, or with the one archived on `date`.
+    pub fn diff_manifest(&self, date: Option<&str>) -> Result<ManifestDiff> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+
+        let mut desc = self
+            .desc()
+            .chain_err(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let manifestation = Manifestation::open(prefix, desc.target.clone())?;
+        let installed = manifestation
+            .load_manifest()?
+            .ok_or_else(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+
+        if let Some(date) = date {
+            desc.date = Some(date.to_owned());
+        }
+        let new = match dist::dl_v2_manifest(self.download_cfg()?, None, &desc) {
+            Ok(Some((manifest, _))) => manifest,
+            Ok(None) => unreachable!("no update hash to match"),
+            Err(Error(ErrorKind::DownloadNotExists { .. }, _)) => {
+                return Err(format!("no release found for '{}'", desc.manifest_name()).into());
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(installed.diff(&new, &desc.target))
+    }
+
+    /// Reinstalls the installation this toolchain had before its last
+    /// update, returning the date of the restored manifest.
+    pub fn rollback(&self) -> Result<String> {
+        let _lock = self.lock()?;
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+
+        let desc = self
+            .desc()
+            .chain_err(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let manifestation = Manifestation::open(prefix, desc.target.clone())?;
+        manifestation.recover(&*self.dist_handler)?;
+
+        let download_cfg = self.download_cfg()?;
+        let date = manifestation
+            .rollback(
+                &download_cfg,
+                &download_cfg.notify_handler,
+                &desc.manifest_name(),
+            )?
+            .ok_or_else(|| ErrorKind::NoToolchainSnapshot(self.name.to_owned()))?;
+
+        // Forget the hash of the newer manifest so the next update
+        // installs it again
+        if let Some(update_hash) = self.update_hash()? {
+            if utils::is_file(&update_hash) {
+                utils::remove_file("update hash", &update_hash)?;
+            }
+        }
+
+        Ok(date)
+    }
+
+    /// Checks the files of each installed component, returning the
+    /// components by name.
+    pub fn verify_components(&self) -> Result<Vec<(String, Verification)>> {
+        let (manifestation, manifest, _) = self.installed_manifestation(false)?;
+        Ok(manifestation
+            .verify()?
+            .into_iter()
+            .map(|(component, verification)| (component.name(&manifest), verification))
+            .collect())
+    }
+
+    /// Reinstalls the components whose files are missing or damaged,
+    /// returning their names.
+    pub fn repair_components(&self) -> Result<Vec<String>> {
+        let _lock = self.lock()?;
+        let (manifestation, manifest, desc) = self.installed_manifestation(true)?;
+        let download_cfg = self.download_cfg()?;
+        let repaired = manifestation.repair(
+            &manifest,
+            &download_cfg,
+            &download_cfg.notify_handler,
+            &desc.manifest_name(),
+        )?;
+        Ok(repaired.iter().map(|c| c.name(&manifest)).collect())
+    }
+
+    // Only callers holding the toolchain's lock can `recover` an
+    // interrupted update, which may be another process's
+    fn installed_manifestation(
+        &self,
+        recover: bool,
+    ) -> Result<(Manifestation, Manifest, ToolchainDesc)> {
+        if !self.exists() {
+            return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
+        }
+
+        let desc = self
+            .desc()
+            .chain_err(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        let manifestation = Manifestation::open(prefix, desc.target.clone())?;
+        if recover {
+            manifestation.recover(&*self.dist_handler)?;
+        }
+        let manifest = manifestation
+            .load_manifest()?
+            .ok_or_else(|| ErrorKind::ComponentsUnsupported(self.name.to_string()))?;
+        Ok((manifestation, manifest, desc))
+    }
+
+    /// The hashes of the cached packages this toolchain could reuse.
+    pub fn cached_package_hashes(&self) -> Result<Vec<String>> {
+        if !self.exists() || self.is_custom() {
+            return Ok(vec![]);
+        }
+        let desc = match self.desc() {
+            Ok(desc) => desc,
+            Err(_) => return Ok(vec![]),
+        };
+        let prefix = InstallPrefix::from(self.path.to_owned());
+        Manifestation::open(prefix, desc.target)?.package_hashes()
+    }
+
+    /// The version of the rust package on the dist server, if it differs
+    /// from what's installed. Only downloads the manifest's hash when
+    /// there's no update.
+    pub fn show_dist_version(&self) -> Result<Option<String>> {
+        let update_hash = self.update_hash()?;
+
+        match dist::dl_v2_manifest(
+            self.download_cfg()?,
+            update_hash.as_ref().map(|p| &**p),
+            &self.desc()?,
+        )? {
+            Some((manifest, _)) => Ok(Some(manifest.get_rust_version()?.to_string())),
+            None => Ok(None),
+        }
+    }
+
     pub fn list_components(&self) -> Result<Vec<ComponentStatus>> {
         if !self.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
@@ -490,6 +738,10 @@ impl<'a> Toolchain<'a> {
                 .get(&toolchain.target)
                 .expect("installed manifest should have a known target");
 
+            let profile = config.as_ref().and_then(|c| c.profile);
+            let required_components =
+                manifest.get_required_components(profile, &toolchain.target)?;
+
             for component in &targ_pkg.components {
                 let installed = config
                     .as_ref()
@@ -513,7 +765,7 @@ impl<'a> Toolchain<'a> {
                 res.push(ComponentStatus {
                     component: component.clone(),
                     name: component.name(&manifest),
-                    required: true,
+                    required: required_components.contains(component),
                     installed,
                     available: component_target_pkg.available(),
                 });
@@ -557,6 +809,7 @@ impl<'a> Toolchain<'a> {
     }
 
     pub fn add_component(&self, mut component: Component) -> Result<()> {
+        let _lock = self.lock()?;
         if !self.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
         }
@@ -567,6 +820,7 @@ impl<'a> Toolchain<'a> {
 
         let prefix = InstallPrefix::from(self.path.to_owned());
         let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+        manifestation.recover(&*self.dist_handler)?;
 
         if let Some(manifest) = manifestation.load_manifest()? {
             // Rename the component if necessary.
@@ -584,7 +838,10 @@ impl<'a> Toolchain<'a> {
                 .get(&toolchain.target)
                 .expect("installed manifest should have a known target");
 
-            if targ_pkg.components.contains(&component) {
+            let dist_config = manifestation.read_config()?.unwrap();
+            if targ_pkg.components.contains(&component)
+                && dist_config.components.contains(&component)
+            {
                 // Treat it as a warning, see https://github.com/rust-lang/rustup.rs/issues/441
                 eprintln!(
                     "{}",
@@ -596,9 +853,11 @@ impl<'a> Toolchain<'a> {
                 return Ok(());
             }
 
-            if !targ_pkg.extensions.contains(&component) {
+            let is_known =
+                |c: &Component| targ_pkg.extensions.contains(c) || targ_pkg.components.contains(c);
+            if !is_known(&component) {
                 let wildcard_component = component.wildcard();
-                if targ_pkg.extensions.contains(&wildcard_component) {
+                if is_known(&wildcard_component) {
                     component = wildcard_component;
                 } else {
                     return Err(ErrorKind::UnknownComponent(
@@ -612,14 +871,17 @@ impl<'a> Toolchain<'a> {
             let changes = Changes {
                 add_extensions: vec![component],
                 remove_extensions: vec![],
+                reinstall_components: vec![],
+                profile: None,
             };
 
+            let download_cfg = self.download_cfg()?;
             manifestation.update(
                 &manifest,
                 changes,
                 false,
-                &self.download_cfg(),
-                &self.download_cfg().notify_handler,
+                &download_cfg,
+                &download_cfg.notify_handler,
                 &toolchain.manifest_name(),
             )?;
 
@@ -630,6 +892,7 @@ impl<'a> Toolchain<'a> {
     }
 
     pub fn remove_component(&self, mut component: Component) -> Result<()> {
+        let _lock = self.lock()?;
         if !self.exists() {
             return Err(ErrorKind::ToolchainNotInstalled(self.name.to_owned()).into());
         }
@@ -640,6 +903,7 @@ impl<'a> Toolchain<'a> {
 
         let prefix = InstallPrefix::from(self.path.to_owned());
         let manifestation = Manifestation::open(prefix, toolchain.target.clone())?;
+        manifestation.recover(&*self.dist_handler)?;
 
         if let Some(manifest) = manifestation.load_manifest()? {
             // Rename the component if necessary.
@@ -648,16 +912,10 @@ impl<'a> Toolchain<'a> {
             }
 
             // Validate the component name
-            let rust_pkg = manifest
-                .packages
-                .get("rust")
-                .expect("manifest should cantain a rust package");
-            let targ_pkg = rust_pkg
-                .targets
-                .get(&toolchain.target)
-                .expect("installed manifest should have a known target");
-
-            if targ_pkg.components.contains(&component) {
+            let dist_config = manifestation.read_config()?.unwrap();
+            let required_components =
+                manifest.get_required_components(dist_config.profile, &toolchain.target)?;
+            if required_components.contains(&component) {
                 return Err(ErrorKind::RemovingRequiredComponent(
                     self.name.to_string(),
                     component.description(&manifest),
@@ -665,7 +923,6 @@ impl<'a> Toolchain<'a> {
                 .into());
             }
 
-            let dist_config = manifestation.read_config()?.unwrap();
             if !dist_config.components.contains(&component) {
                 let wildcard_component = component.wildcard();
                 if dist_config.components.contains(&wildcard_component) {
@@ -682,14 +939,17 @@ impl<'a> Toolchain<'a> {
             let changes = Changes {
                 add_extensions: vec![],
                 remove_extensions: vec![component],
+                reinstall_components: vec![],
+                profile: None,
             };
 
+            let download_cfg = self.download_cfg()?;
             manifestation.update(
                 &manifest,
                 changes,
                 false,
-                &self.download_cfg(),
-                &self.download_cfg().notify_handler,
+                &download_cfg,
+                &download_cfg.notify_handler,
                 &toolchain.manifest_name(),
             )?;
 
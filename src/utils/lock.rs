@@ -0,0 +1,164 @@
+//! Advisory locks, which keep rustup processes sharing `RUSTUP_HOME`
+//! from changing the same files at the same time.
+//!
+//! A lock lives as long as its `FileLock`. The process holding an
+//! exclusive lock writes its id into the lock file, so that the
+//! processes waiting for it can say whom they are waiting for.
+
+use crate::errors::*;
+use crate::utils::notifications::Notification;
+use crate::utils::utils;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+#[derive(Debug)]
+pub struct FileLock {
+    // Closing the file releases the lock
+    _file: File,
+}
+
+impl FileLock {
+    /// Locks `path` so that other processes can read what it guards
+    /// but not change it, waiting while another process holds it
+    /// exclusively.
+    pub fn shared(path: &Path, notify_handler: &dyn Fn(Notification<'_>)) -> Result<Self> {
+        let lock = FileLock::acquire(path, false, true, notify_handler)?;
+        Ok(lock.expect("waited for the lock"))
+    }
+
+    /// Locks `path` for this process alone, waiting while any other
+    /// process holds it.
+    pub fn exclusive(path: &Path, notify_handler: &dyn Fn(Notification<'_>)) -> Result<Self> {
+        let lock = FileLock::acquire(path, true, true, notify_handler)?;
+        Ok(lock.expect("waited for the lock"))
+    }
+
+    /// Locks `path` for this process alone if no other process holds
+    /// it, without waiting.
+    pub fn try_exclusive(
+        path: &Path,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Option<Self>> {
+        FileLock::acquire(path, true, false, notify_handler)
+    }
+
+    fn acquire(
+        path: &Path,
+        exclusive: bool,
+        wait: bool,
+        notify_handler: &dyn Fn(Notification<'_>),
+    ) -> Result<Option<Self>> {
+        if let Some(parent) = path.parent() {
+            utils::ensure_dir_exists("locks", parent, notify_handler)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .chain_err(|| ErrorKind::LockingFile {
+                name: "lock",
+                path: path.to_owned(),
+            })?;
+
+        match lock_file(&file, exclusive, false) {
+            Ok(()) => {}
+            Err(ref e) if is_contended(e) && !wait => return Ok(None),
+            Err(ref e) if is_contended(e) => {
+                notify_handler(Notification::WaitingForLock(path, holder(path)));
+                lock_file(&file, exclusive, true).chain_err(|| ErrorKind::LockingFile {
+                    name: "lock",
+                    path: path.to_owned(),
+                })?;
+            }
+            Err(e) => {
+                return Err(e).chain_err(|| ErrorKind::LockingFile {
+                    name: "lock",
+                    path: path.to_owned(),
+                })
+            }
+        }
+
+        if exclusive {
+            file.set_len(0)
+                .and_then(|()| write!(file, "{}", process::id()))
+                .chain_err(|| ErrorKind::WritingFile {
+                    name: "lock",
+                    path: path.to_owned(),
+                })?;
+        }
+
+        Ok(Some(FileLock { _file: file }))
+    }
+}
+
+// The id of the process that last held `path` exclusively
+fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn lock_file(file: &File, exclusive: bool, wait: bool) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    if !wait {
+        operation |= libc::LOCK_NB;
+    }
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_contended(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+#[cfg(windows)]
+fn lock_file(file: &File, exclusive: bool, wait: bool) -> io::Result<()> {
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+
+    let mut flags = 0;
+    if exclusive {
+        flags |= LOCKFILE_EXCLUSIVE_LOCK;
+    }
+    if !wait {
+        flags |= LOCKFILE_FAIL_IMMEDIATELY;
+    }
+    // Windows locks keep other processes from reading the locked
+    // bytes, so lock one far past the process id
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    unsafe {
+        overlapped.u.s_mut().OffsetHigh = u32::max_value();
+    }
+    let ok = unsafe { LockFileEx(file.as_raw_handle() as _, flags, 0, 1, 0, &mut overlapped) };
+    if ok == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn is_contended(err: &io::Error) -> bool {
+    use winapi::shared::winerror::ERROR_LOCK_VIOLATION;
+
+    err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32)
+}
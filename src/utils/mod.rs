@@ -1,4 +1,5 @@
 ///!  Utility functions for rustup
+pub mod lock;
 pub mod notifications;
 pub mod raw;
 pub mod toml_utils;
@@ -1,5 +1,6 @@
 use std::fmt::{self, Display};
 use std::path::Path;
+use std::time::Duration;
 
 use url::Url;
 
@@ -20,8 +21,19 @@ pub enum Notification<'a> {
     DownloadFinished,
     NoCanonicalPath(&'a Path),
     ResumingPartialDownload,
-    UsingCurl,
-    UsingReqwest,
+    /// A download failed, and will be attempted again: the attempt
+    /// number, and how long until it starts.
+    RetryingDownload(&'a Url, u32, Duration),
+    /// The file changed on the server since it was partly downloaded,
+    /// so all of it is being downloaded again.
+    DownloadRestarted(&'a Url),
+    /// The download is limited to this many bytes per second.
+    DownloadRateLimited(u64),
+    /// The name of the backend downloading the file
+    UsingBackend(&'a str),
+    /// Another process holds the lock: the lock file, and the process
+    /// id it recorded there.
+    WaitingForLock(&'a Path, Option<u32>),
 }
 
 impl<'a> Notification<'a> {
@@ -36,8 +48,11 @@ impl<'a> Notification<'a> {
             | DownloadDataReceived(_)
             | DownloadFinished
             | ResumingPartialDownload
-            | UsingCurl
-            | UsingReqwest => NotificationLevel::Verbose,
+            | DownloadRateLimited(_)
+            | UsingBackend(_) => NotificationLevel::Verbose,
+            RetryingDownload(_, _, _) | DownloadRestarted(_) | WaitingForLock(_, _) => {
+                NotificationLevel::Info
+            }
             NoCanonicalPath(_) => NotificationLevel::Warn,
         }
     }
@@ -61,8 +76,29 @@ impl<'a> Display for Notification<'a> {
             DownloadFinished => write!(f, "download finished"),
             NoCanonicalPath(path) => write!(f, "could not canonicalize path: '{}'", path.display()),
             ResumingPartialDownload => write!(f, "resuming partial download"),
-            UsingCurl => write!(f, "downloading with curl"),
-            UsingReqwest => write!(f, "downloading with reqwest"),
+            RetryingDownload(url, attempt, delay) => write!(
+                f,
+                "download of '{}' failed, retrying in {:.1}s (attempt {})",
+                url,
+                delay.as_secs_f64(),
+                attempt
+            ),
+            DownloadRestarted(url) => write!(
+                f,
+                "'{}' changed since it was partly downloaded, downloading all of it again",
+                url
+            ),
+            DownloadRateLimited(rate) => {
+                write!(f, "download limited to {} bytes per second", rate)
+            }
+            UsingBackend(name) => write!(f, "downloading with {}", name),
+            WaitingForLock(path, Some(pid)) => write!(
+                f,
+                "waiting for lock held by pid {}: '{}'",
+                pid,
+                path.display()
+            ),
+            WaitingForLock(path, None) => write!(f, "waiting for lock: '{}'", path.display()),
         }
     }
 }
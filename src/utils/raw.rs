@@ -64,6 +64,17 @@ pub fn write_file(path: &Path, contents: &str) -> io::Result<()> {
     Ok(())
 }
 
+// Writes a file next to `path` and renames it over `path`, so that
+// nothing ever sees `path` only partly written
+pub fn write_file_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    write_file(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
 pub fn read_file(path: &Path) -> io::Result<String> {
     let mut file = fs::OpenOptions::new().read(true).open(path)?;
 
@@ -1,6 +1,7 @@
 use crate::errors::*;
 use crate::utils::notifications::Notification;
 use crate::utils::raw;
+use download::{Backend, CurlBackend, DownloadOptions, ReqwestBackend};
 use sha2::Sha256;
 use std::cmp::Ord;
 use std::env;
@@ -9,6 +10,7 @@ use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::Arc;
 use url::Url;
 
 #[cfg(windows)]
@@ -48,6 +50,13 @@ pub fn write_file(name: &'static str, path: &Path, contents: &str) -> Result<()>
     })
 }
 
+pub fn write_file_atomically(name: &'static str, path: &Path, contents: &str) -> Result<()> {
+    raw::write_file_atomically(path, contents).chain_err(|| ErrorKind::WritingFile {
+        name,
+        path: PathBuf::from(path),
+    })
+}
+
 pub fn append_file(name: &'static str, path: &Path, line: &str) -> Result<()> {
     raw::append_file(path, line).chain_err(|| ErrorKind::WritingFile {
         name,
@@ -119,9 +128,19 @@ pub fn download_file(
     url: &Url,
     path: &Path,
     hasher: Option<&mut Sha256>,
+    backend: &dyn Backend,
+    options: &DownloadOptions,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
-    download_file_with_resume(&url, &path, hasher, false, &notify_handler)
+    download_file_with_resume(
+        &url,
+        &path,
+        hasher,
+        false,
+        backend,
+        options,
+        &notify_handler,
+    )
 }
 
 pub fn download_file_with_resume(
@@ -129,10 +148,20 @@ pub fn download_file_with_resume(
     path: &Path,
     hasher: Option<&mut Sha256>,
     resume_from_partial: bool,
+    backend: &dyn Backend,
+    options: &DownloadOptions,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
     use download::ErrorKind as DEK;
-    match download_file_(url, path, hasher, resume_from_partial, notify_handler) {
+    match download_file_(
+        url,
+        path,
+        hasher,
+        resume_from_partial,
+        backend,
+        options,
+        notify_handler,
+    ) {
         Ok(_) => Ok(()),
         Err(e) => {
             let is_client_error = match e.kind() {
@@ -162,10 +191,12 @@ fn download_file_(
     path: &Path,
     hasher: Option<&mut Sha256>,
     resume_from_partial: bool,
+    backend: &dyn Backend,
+    options: &DownloadOptions,
     notify_handler: &dyn Fn(Notification<'_>),
 ) -> Result<()> {
-    use download::download_to_path_with_backend;
-    use download::{Backend, Event};
+    use download::download_to_path_with_options;
+    use download::Event;
     use sha2::Digest;
     use std::cell::RefCell;
 
@@ -192,6 +223,16 @@ fn download_file_(
             Event::ResumingPartialDownload => {
                 notify_handler(Notification::ResumingPartialDownload);
             }
+            Event::RetryingDownload(attempt, delay) => {
+                notify_handler(Notification::RetryingDownload(url, attempt, delay));
+            }
+            Event::DownloadRestarted => {
+                if let Some(h) = hasher.borrow_mut().as_mut() {
+                    **h = Sha256::new();
+                }
+                notify_handler(Notification::DownloadRestarted(url));
+            }
+            Event::ETagReceived(_) | Event::LastModifiedReceived(_) => {}
         }
 
         Ok(())
@@ -199,21 +240,35 @@ fn download_file_(
 
     // Download the file
 
-    // Keep the hyper env var around for a bit
-    let use_curl_backend = env::var_os("RUSTUP_USE_CURL").is_some();
-    let (backend, notification) = if use_curl_backend {
-        (Backend::Curl, Notification::UsingCurl)
-    } else {
-        (Backend::Reqwest, Notification::UsingReqwest)
-    };
-    notify_handler(notification);
-    download_to_path_with_backend(backend, url, path, resume_from_partial, Some(callback))?;
+    notify_handler(Notification::UsingBackend(backend.name()));
+    if let Some(rate) = options.max_rate {
+        notify_handler(Notification::DownloadRateLimited(rate));
+    }
+    download_to_path_with_options(
+        backend,
+        url,
+        path,
+        resume_from_partial,
+        options,
+        Some(callback),
+    )?;
 
     notify_handler(Notification::DownloadFinished);
 
     Ok(())
 }
 
+/// The backend to download with unless told otherwise: curl when
+/// `RUSTUP_USE_CURL` is set, and reqwest otherwise.
+pub fn download_backend() -> Arc<dyn Backend> {
+    // Keep the hyper env var around for a bit
+    if env::var_os("RUSTUP_USE_CURL").is_some() {
+        Arc::new(CurlBackend)
+    } else {
+        Arc::new(ReqwestBackend)
+    }
+}
+
 pub fn parse_url(url: &str) -> Result<Url> {
     Url::parse(url).chain_err(|| format!("failed to parse url: {}", url))
 }
@@ -161,6 +161,21 @@ fn with_non_default_toolchain() {
     });
 }
 
+#[test]
+fn with_profile() {
+    setup(&|config| {
+        let out = run_input(config, &["rustup-init", "--profile=minimal"], "\n\n");
+        assert!(out.ok);
+        assert!(out.stdout.contains("profile: minimal"));
+
+        let docs = format!(
+            "toolchains/stable-{}/share/doc/rust/html/index.html",
+            clitools::this_host_triple()
+        );
+        assert!(!config.rustupdir.join(docs).exists());
+    });
+}
+
 #[test]
 fn with_non_release_channel_non_default_toolchain() {
     setup(&|config| {
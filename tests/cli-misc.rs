@@ -721,6 +721,70 @@ fn update_unavailable_rustc() {
     });
 }
 
+#[test]
+fn install_nightly_with_components_walks_back() {
+    clitools::setup(Scenario::Unavailable, &|config| {
+        set_current_dist_date(config, "2015-01-02");
+        expect_stderr_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--with-components",
+                "rls,rust-analysis",
+                "--no-self-update",
+            ],
+            "skipping nightly from 2015-01-02, which is missing: rls, rust-analysis",
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            for_host!("rls-{} (installed)"),
+        );
+    });
+}
+
+#[test]
+fn install_with_components_reports_picked_date() {
+    clitools::setup(Scenario::Unavailable, &|config| {
+        set_current_dist_date(config, "2015-01-02");
+        expect_stderr_ok(
+            config,
+            &[
+                "rustup",
+                "install",
+                "nightly",
+                "--with-components",
+                "rls",
+                "--no-self-update",
+            ],
+            "using nightly from 2015-01-01, the latest with all requested components",
+        );
+    });
+}
+
+#[test]
+fn install_with_components_requires_nightly() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "stable",
+                "--with-components",
+                "rls",
+            ],
+            for_host!("'--with-components' requires an undated nightly toolchain, not 'stable-{}'"),
+        );
+    });
+}
+
 #[test]
 fn completion_rustup() {
     setup(&|config| {
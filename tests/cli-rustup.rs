@@ -81,6 +81,34 @@ fn rustup_stable_no_change() {
     });
 }
 
+#[test]
+fn rustup_update_skips_pinned_toolchains() {
+    setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "update", "stable", "--no-self-update"]);
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        expect_ok(config, &["rustup", "toolchain", "pin", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "--no-self-update"],
+            for_host!(
+                r"
+  stable-{0} updated - 1.1.0 (hash-s-2)
+  nightly-{0} pinned - 1.2.0 (hash-n-1)
+"
+            ),
+        );
+
+        // Naming it updates it all the same
+        expect_stdout_ok(
+            config,
+            &["rustup", "update", "nightly", "--no-self-update"],
+            for_host!("nightly-{0} updated - 1.3.0 (hash-n-2)"),
+        );
+    });
+}
+
 #[test]
 fn rustup_all_channels() {
     setup(&|config| {
@@ -250,6 +278,98 @@ info: default toolchain set to 'nightly-{0}'
     });
 }
 
+#[test]
+fn check_no_updates() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "update", "stable", "--no-self-update"]);
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+
+        expect_ok_ex(
+            config,
+            &["rustup", "check", "--no-self-update"],
+            for_host!(
+                r"stable-{0} - Up to date : 1.1.0
+nightly-{0} - Up to date : 1.3.0
+"
+            ),
+            r"",
+        );
+    });
+}
+
+#[test]
+fn check_updates_available() {
+    setup(&|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "update", "stable", "--no-self-update"]);
+        expect_ok(config, &["rustup", "update", "beta", "--no-self-update"]);
+        set_current_dist_date(config, "2015-01-02");
+
+        let mut cmd = clitools::cmd(config, "rustup", &["check", "--no-self-update"]);
+        clitools::env(config, &mut cmd);
+        let out = cmd.output().unwrap();
+        assert_eq!(out.status.code(), Some(100));
+        assert_eq!(
+            &String::from_utf8(out.stdout).unwrap(),
+            for_host!(
+                r"stable-{0} - Update available : 1.0.0 -> 1.1.0
+beta-{0} - Update available : 1.1.0 -> 1.2.0
+"
+            )
+        );
+
+        // Nothing was installed
+        expect_stdout_ok(config, &["rustc", "+stable", "--version"], "hash-s-1");
+    });
+}
+
+#[test]
+fn check_new_build_of_same_version() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "update", "stable", "--no-self-update"]);
+        // The channel was published again since
+        let update_hash = config
+            .rustupdir
+            .join("update-hashes")
+            .join(for_host!("stable-{}"));
+        fs::write(&update_hash, "0000").unwrap();
+
+        let out = clitools::run(config, "rustup", &["check", "--no-self-update"], &[]);
+        assert_eq!(
+            &out.stdout,
+            for_host!(
+                r"stable-{0} - Update available : 1.1.0 (new build)
+"
+            )
+        );
+    });
+}
+
+#[test]
+fn check_reports_toolchains_it_cannot_check() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "update", "stable", "--no-self-update"]);
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        let manifest = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("stable-{}"))
+            .join("lib/rustlib/multirust-channel-manifest.toml");
+        fs::write(&manifest, "not a manifest").unwrap();
+
+        let out = clitools::run(config, "rustup", &["check", "--no-self-update"], &[]);
+        assert_eq!(
+            &out.stdout,
+            for_host!(
+                r"stable-{0} - Cannot check for updates
+nightly-{0} - Up to date : 1.3.0
+"
+            )
+        );
+        assert!(out.stderr.contains("error: error parsing manifest"));
+    });
+}
+
 #[test]
 fn default_override() {
     setup(&|config| {
@@ -695,6 +815,44 @@ fn list_default_toolchain() {
     });
 }
 
+#[test]
+fn list_pinned_toolchain() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "toolchain", "pin", "nightly"]);
+        expect_ok_ex(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!(
+                r"nightly-{0} (default) (pinned)
+"
+            ),
+            r"",
+        );
+        expect_ok(config, &["rustup", "toolchain", "unpin", "nightly"]);
+        expect_ok_ex(
+            config,
+            &["rustup", "toolchain", "list"],
+            for_host!(
+                r"nightly-{0} (default)
+"
+            ),
+            r"",
+        );
+    });
+}
+
+#[test]
+fn pin_toolchain_not_installed() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "toolchain", "pin", "nightly"],
+            for_host!("toolchain 'nightly-{0}' is not installed"),
+        );
+    });
+}
+
 #[test]
 #[ignore = "FIXME: Windows shows UNC paths"]
 fn show_toolchain_override() {
@@ -1400,6 +1558,93 @@ fn bad_file_override() {
     });
 }
 
+#[test]
+fn toml_file_override() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(
+            &toolchain_file,
+            &format!(
+                r#"
+[toolchain]
+channel = "nightly"
+components = ["rust-src"]
+targets = ["{}"]
+"#,
+                clitools::CROSS_ARCH1
+            ),
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--installed"],
+            "rust-src",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "target", "list", "--installed"],
+            clitools::CROSS_ARCH1,
+        );
+    });
+}
+
+#[test]
+fn toml_file_override_with_profile() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "stable"]);
+
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain.toml");
+        raw::write_file(
+            &toolchain_file,
+            "[toolchain]\nchannel = \"nightly\"\nprofile = \"minimal\"\n",
+        )
+        .unwrap();
+
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        let docs = format!(
+            "toolchains/nightly-{}/share/doc/rust/html/index.html",
+            this_host_triple()
+        );
+        assert!(!config.rustupdir.join(docs).exists());
+    });
+}
+
+#[test]
+fn bad_toml_file_override() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "[toolchain]\nchannel = nightly\n").unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "could not parse toolchain file",
+        );
+    });
+}
+
+#[test]
+fn toml_file_override_without_channel() {
+    setup(&|config| {
+        let cwd = config.current_dir();
+        let toolchain_file = cwd.join("rust-toolchain");
+        raw::write_file(&toolchain_file, "[toolchain]\ncomponents = []\n").unwrap();
+
+        expect_err(
+            config,
+            &["rustc", "--version"],
+            "missing key: 'toolchain.channel'",
+        );
+    });
+}
+
 #[test]
 fn file_override_with_target_info() {
     setup(&|config| {
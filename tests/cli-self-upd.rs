@@ -4,7 +4,7 @@ pub mod mock;
 
 use crate::mock::clitools::{
     self, expect_err, expect_err_ex, expect_ok, expect_ok_contains, expect_ok_ex, expect_stderr_ok,
-    expect_stdout_ok, this_host_triple, Config, Scenario,
+    expect_stdout_ok, run, this_host_triple, Config, Scenario,
 };
 use crate::mock::dist::calc_hash;
 use crate::mock::{get_path, restore_path};
@@ -559,6 +559,22 @@ info: rustup updated successfully to "
     });
 }
 
+#[test]
+fn check_finds_rustup_update() {
+    update_setup(&|config, _| {
+        expect_ok(config, &["rustup-init", "-y"]);
+
+        let out = run(config, "rustup", &["check"], &[]);
+        assert!(!out.ok);
+        assert!(out.stdout.contains(for_host!("stable-{0} - Up to date")));
+        assert!(out.stdout.contains(&format!(
+            "rustup - Update available : {} -> {}",
+            env!("CARGO_PKG_VERSION"),
+            TEST_VERSION
+        )));
+    });
+}
+
 #[test]
 fn update_but_not_installed() {
     update_setup(&|config, _| {
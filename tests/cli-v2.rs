@@ -7,10 +7,16 @@ use crate::mock::clitools::{
     self, expect_err, expect_not_stdout_ok, expect_ok, expect_stderr_ok, expect_stdout_ok,
     set_current_dist_date, this_host_triple, Config, Scenario,
 };
+use filetime::FileTime;
 use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::time::{Duration, SystemTime};
 use tempdir::TempDir;
 
 use rustup::dist::dist::TargetTriple;
+use rustup::utils::lock::FileLock;
 
 macro_rules! for_host {
     ($s: expr) => {
@@ -243,6 +249,66 @@ fn bad_sha_on_manifest() {
     });
 }
 
+#[test]
+fn missing_signature_on_manifest() {
+    setup(&|config| {
+        fs::remove_file(config.distdir.join("dist/channel-rust-nightly.toml.asc")).unwrap();
+        expect_err(
+            config,
+            &["rustup", "default", "nightly"],
+            "no signature found for",
+        );
+    });
+}
+
+#[test]
+fn missing_signature_on_manifest_warn() {
+    setup(&|config| {
+        fs::remove_file(config.distdir.join("dist/channel-rust-nightly.toml.asc")).unwrap();
+        expect_ok(config, &["rustup", "set", "signature-check", "warn"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "default", "nightly"],
+            "warning: no signature found for",
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
+#[test]
+fn bad_signature_on_manifest() {
+    setup(&|config| {
+        // A valid signature, but for a different file
+        fs::copy(
+            config.distdir.join("dist/channel-rust-beta.toml.asc"),
+            config.distdir.join("dist/channel-rust-nightly.toml.asc"),
+        )
+        .unwrap();
+        expect_err(
+            config,
+            &["rustup", "default", "nightly"],
+            "signature verification failed for",
+        );
+    });
+}
+
+#[test]
+fn bad_signature_on_manifest_warn() {
+    setup(&|config| {
+        fs::copy(
+            config.distdir.join("dist/channel-rust-beta.toml.asc"),
+            config.distdir.join("dist/channel-rust-nightly.toml.asc"),
+        )
+        .unwrap();
+        expect_ok(config, &["rustup", "set", "signature-check", "warn"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "default", "nightly"],
+            "warning: signature verification failed for",
+        );
+    });
+}
+
 #[test]
 fn bad_sha_on_installer() {
     setup(&|config| {
@@ -819,7 +885,7 @@ fn remove_target_missing_update_hash() {
 }
 
 fn make_component_unavailable(config: &Config, name: &str, target: &TargetTriple) {
-    use crate::mock::dist::create_hash;
+    use crate::mock::dist::{create_hash, create_signature};
     use rustup::dist::manifest::Manifest;
 
     let manifest_path = config.distdir.join("dist/channel-rust-nightly.toml");
@@ -833,10 +899,12 @@ fn make_component_unavailable(config: &Config, name: &str, target: &TargetTriple
     let manifest_str = manifest.stringify();
     rustup::utils::raw::write_file(&manifest_path, &manifest_str).unwrap();
 
-    // Have to update the hash too
+    // Have to update the hash and signature too
     let hash_path = manifest_path.with_extension("toml.sha256");
     println!("{}", hash_path.display());
     create_hash(&manifest_path, &hash_path);
+    let sig_path = manifest_path.with_extension("toml.asc");
+    create_signature(&manifest_path, &sig_path);
 }
 
 #[test]
@@ -886,3 +954,1022 @@ fn update_unavailable_force() {
         );
     });
 }
+
+#[test]
+fn install_with_profile_minimal() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--profile",
+                "minimal",
+                "--no-self-update",
+            ],
+        );
+        let docs = format!(
+            "toolchains/nightly-{}/share/doc/rust/html/index.html",
+            this_host_triple()
+        );
+        assert!(!config.rustupdir.join(&docs).exists());
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "nightly"],
+            &format!("rustc-{}", this_host_triple()),
+        );
+
+        // Components left out by the profile can be added and removed
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "component",
+                "add",
+                "rust-docs",
+                "--toolchain",
+                "nightly",
+            ],
+        );
+        assert!(config.rustupdir.join(&docs).exists());
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "component",
+                "remove",
+                "rust-docs",
+                "--toolchain",
+                "nightly",
+            ],
+        );
+        assert!(!config.rustupdir.join(&docs).exists());
+
+        // Required components still can't be removed
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "component",
+                "remove",
+                "rustc",
+                "--toolchain",
+                "nightly",
+            ],
+            "is required for toolchain",
+        );
+    });
+}
+
+#[test]
+fn update_keeps_profile() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
+        expect_ok(config, &["rustup", "default", "nightly"]);
+
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "set", "profile", "complete"]);
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+
+        let docs = format!(
+            "toolchains/nightly-{}/share/doc/rust/html/index.html",
+            this_host_triple()
+        );
+        assert!(!config.rustupdir.join(&docs).exists());
+    });
+}
+
+#[test]
+fn install_with_invalid_profile() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--profile",
+                "bogus",
+            ],
+            "'bogus' isn't a valid value",
+        );
+    });
+}
+
+#[test]
+fn install_with_components_and_targets() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "-c",
+                "rust-src,rust-analysis",
+                "-t",
+                clitools::CROSS_ARCH1,
+                "--no-self-update",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "nightly"],
+            "rust-src (installed)",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "nightly"],
+            for_host!("rust-analysis-{} (installed)"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "target", "list", "--toolchain", "nightly"],
+            &format!("{} (installed)", clitools::CROSS_ARCH1),
+        );
+    });
+}
+
+#[test]
+fn install_with_the_host_target() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "-t",
+                &this_host_triple(),
+                "--no-self-update",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "target", "list", "--toolchain", "nightly"],
+            for_host!("{} (default)"),
+        );
+    });
+}
+
+#[test]
+fn install_with_a_required_component() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "-c",
+                "rustc",
+                "--no-self-update",
+            ],
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
+#[test]
+fn update_adds_components_to_up_to_date_toolchain() {
+    setup(&|config| {
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+            ],
+        );
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "update",
+                "nightly",
+                "--component",
+                "rust-src",
+                "--no-self-update",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list", "--toolchain", "nightly"],
+            "rust-src (installed)",
+        );
+    });
+}
+
+#[test]
+fn install_with_unknown_component_installs_nothing() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "-c",
+                "rust-src,bogus",
+                "--no-self-update",
+            ],
+            for_host!(
+                "toolchain 'nightly-{0}' does not contain component 'bogus' for target '{0}'"
+            ),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "list"],
+            "no installed toolchains",
+        );
+    });
+}
+
+#[test]
+fn toolchain_diff_with_latest() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            for_host!("nightly-{}: 2015-01-01 -> 2015-01-02"),
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            "cargo: 1.2.0 -> 1.3.0",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            "rls: 1.2.0 -> (none)",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            "rls-preview: (none) -> 1.3.0",
+        );
+        // rls was only renamed, so no component came or went
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            "components:",
+        );
+    });
+}
+
+#[test]
+fn toolchain_diff_with_date() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "diff",
+                "nightly",
+                "--date",
+                "2015-01-01",
+            ],
+            "cargo: 1.3.0 -> 1.2.0",
+        );
+        expect_err(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "diff",
+                "nightly",
+                "--date",
+                "2014-12-31",
+            ],
+            "no release found for 'nightly-2014-12-31'",
+        );
+    });
+}
+
+#[test]
+fn toolchain_diff_json() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly", "--json"],
+            r#""new_date": "2015-01-02""#,
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly", "--json"],
+            r#""new_version": "1.3.0""#,
+        );
+    });
+}
+
+#[test]
+fn toolchain_diff_not_installed() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            for_host!("toolchain 'nightly-{}' is not installed"),
+        );
+    });
+}
+
+#[test]
+fn toolchain_diff_shows_unavailable_packages() {
+    clitools::setup(Scenario::Unavailable, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "diff", "nightly"],
+            "rustc: 1.2.0 -> 1.3.0 (unavailable)",
+        );
+    });
+}
+
+#[test]
+fn toolchain_rollback_restores_previous_installation() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+        expect_ok(config, &["rustup", "toolchain", "rollback", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+        expect_err(
+            config,
+            &["rustup", "toolchain", "rollback", "nightly"],
+            for_host!("toolchain 'nightly-{}' has no earlier installation to roll back to"),
+        );
+    });
+}
+
+#[test]
+fn toolchain_rollback_restores_component_set() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_ok(config, &["rustup", "component", "add", "rust-src"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        expect_ok(config, &["rustup", "component", "remove", "rust-src"]);
+        expect_ok(config, &["rustup", "component", "add", "rust-analysis"]);
+        expect_ok(config, &["rustup", "toolchain", "rollback", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            "rust-src (installed)",
+        );
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "component", "list"],
+            for_host!("rust-analysis-{} (installed)"),
+        );
+    });
+}
+
+#[test]
+fn toolchain_rollback_without_update() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_err(
+            config,
+            &["rustup", "toolchain", "rollback", "nightly"],
+            for_host!("toolchain 'nightly-{}' has no earlier installation to roll back to"),
+        );
+    });
+}
+
+#[test]
+fn toolchain_verify_finds_missing_and_modified_files() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(
+            config,
+            &["rustup", "toolchain", "verify", "nightly"],
+            for_host!("rust-std-{}: ok"),
+        );
+
+        let path = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("nightly-{}"));
+        let libstd = format!("lib/rustlib/{}/libstd.rlib", this_host_triple());
+        fs::remove_file(path.join(&libstd)).unwrap();
+        rustup::utils::raw::write_file(&path.join("share/doc/rust/html/index.html"), "xxx")
+            .unwrap();
+
+        let out = clitools::run(config, "rustup", &["toolchain", "verify", "nightly"], &[]);
+        assert!(!out.ok);
+        assert!(out.stdout.contains(for_host!("rustc-{}: ok")));
+        assert!(out.stdout.contains(&format!("    missing: {}", libstd)));
+        assert!(out
+            .stdout
+            .contains("    modified: share/doc/rust/html/index.html"));
+        assert!(out.stderr.contains(for_host!(
+            "toolchain 'nightly-{}' has missing or modified files"
+        )));
+    });
+}
+
+#[test]
+fn toolchain_repair_reinstalls_damaged_components() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let path = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("nightly-{}"));
+        let libstd = format!("lib/rustlib/{}/libstd.rlib", this_host_triple());
+        fs::remove_file(path.join(&libstd)).unwrap();
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "toolchain", "repair", "nightly"],
+            for_host!("reinstalled rust-std-{}"),
+        );
+        assert!(path.join(&libstd).exists());
+        expect_ok(config, &["rustup", "toolchain", "verify", "nightly"]);
+        expect_stderr_ok(
+            config,
+            &["rustup", "toolchain", "repair", "nightly"],
+            for_host!("toolchain 'nightly-{}' has no missing or modified files"),
+        );
+    });
+}
+
+#[test]
+fn interrupted_changes_are_rolled_back() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let path = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("nightly-{}"));
+        rustup::utils::raw::write_file(&path.join("stray"), "").unwrap();
+        rustup::utils::raw::write_file(
+            &path.join("lib/rustlib/rustup-journal"),
+            "added-file\tstray",
+        )
+        .unwrap();
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "component", "add", "rust-src"],
+            "that were interrupted",
+        );
+        assert!(!path.join("stray").exists());
+        assert!(!path.join("lib/rustlib/rustup-journal").exists());
+        expect_ok(config, &["rustup", "toolchain", "verify", "nightly"]);
+    });
+}
+
+#[test]
+fn changes_of_running_processes_are_not_rolled_back() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let path = config
+            .rustupdir
+            .join("toolchains")
+            .join(for_host!("nightly-{}"));
+        rustup::utils::raw::write_file(&path.join("stray"), "").unwrap();
+        rustup::utils::raw::write_file(
+            &path.join("lib/rustlib/rustup-journal"),
+            "added-file\tstray",
+        )
+        .unwrap();
+
+        // The process installing the file still holds the lock
+        let lock_path = config
+            .rustupdir
+            .join("locks")
+            .join(for_host!("nightly-{}.lock"));
+        let _lock = FileLock::exclusive(&lock_path, &|_| ()).unwrap();
+
+        expect_ok(config, &["rustup", "component", "list"]);
+        expect_ok(config, &["rustup", "show"]);
+        expect_ok(config, &["rustup", "toolchain", "verify", "nightly"]);
+        assert!(path.join("stray").exists());
+        assert!(path.join("lib/rustlib/rustup-journal").exists());
+    });
+}
+
+// Runs a command that has to wait for `lock`, which is released once the
+// command says it is waiting for this process
+fn expect_ok_once_unlocked(config: &Config, args: &[&str], lock: FileLock) {
+    let mut cmd = clitools::cmd(config, args[0], &args[1..]);
+    cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+    let mut child = cmd.spawn().unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+
+    let waiting = format!("waiting for lock held by pid {}", process::id());
+    let mut line = String::new();
+    while !line.contains(&waiting) {
+        line.clear();
+        let read = stderr.read_line(&mut line).unwrap();
+        assert!(read > 0, "{:?} did not wait for the lock", args);
+    }
+    drop(lock);
+
+    io::copy(&mut stderr, &mut io::sink()).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn installs_wait_for_the_toolchain_lock() {
+    setup(&|config| {
+        let lock_path = config
+            .rustupdir
+            .join("locks")
+            .join(for_host!("nightly-{}.lock"));
+        let lock = FileLock::exclusive(&lock_path, &|_| ()).unwrap();
+        expect_ok_once_unlocked(
+            config,
+            &["rustup", "update", "nightly", "--no-self-update"],
+            lock,
+        );
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
+#[test]
+fn settings_changes_wait_for_the_settings_lock() {
+    setup(&|config| {
+        let lock_path = config.rustupdir.join("settings.lock");
+        let lock = FileLock::exclusive(&lock_path, &|_| ()).unwrap();
+        expect_ok_once_unlocked(config, &["rustup", "set", "profile", "minimal"], lock);
+        let settings = fs::read_to_string(config.rustupdir.join("settings.toml")).unwrap();
+        assert!(settings.contains("profile = \"minimal\""));
+    });
+}
+
+#[test]
+fn settings_keep_keys_from_newer_versions() {
+    setup(&|config| {
+        let path = config.rustupdir.join("settings.toml");
+        rustup::utils::raw::write_file(
+            &path,
+            "version = \"12\"\nfuture_key = \"kept\"\n\n[future_table]\nkey = 1\n",
+        )
+        .unwrap();
+
+        expect_ok(config, &["rustup", "set", "profile", "minimal"]);
+
+        let settings = fs::read_to_string(&path).unwrap();
+        assert!(settings.contains("future_key = \"kept\""));
+        assert!(settings.contains("[future_table]"));
+        assert!(settings.contains("profile = \"minimal\""));
+    });
+}
+
+#[test]
+fn cache_prune_removes_partial_and_unused_downloads() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        let downloads = config.rustupdir.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        rustup::utils::raw::write_file(&downloads.join("1234.partial"), "xxx").unwrap();
+        rustup::utils::raw::write_file(&downloads.join("1234.partial.validator"), "\"v1\"")
+            .unwrap();
+        rustup::utils::raw::write_file(&downloads.join("5678"), "xxx").unwrap();
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "list"],
+            "1234.partial 3 B (partial)",
+        );
+        expect_stdout_ok(config, &["rustup", "cache", "size"], "6 B in 2 files");
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "prune", "--older-than", "1"],
+            "removed 0 files",
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "prune"],
+            "removed 2 files, freeing 6 B",
+        );
+        assert!(!downloads.join("1234.partial").exists());
+        assert!(!downloads.join("1234.partial.validator").exists());
+        assert!(!downloads.join("5678").exists());
+    });
+}
+
+#[test]
+fn cache_keeps_downloads_with_retention() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        expect_ok(config, &["rustup", "set", "download-retention", "30"]);
+        set_current_dist_date(config, "2015-01-01");
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        set_current_dist_date(config, "2015-01-02");
+        expect_ok(config, &["rustup", "update", "nightly", "--no-self-update"]);
+        expect_stdout_ok(config, &["rustup", "cache", "list"], "(in use)");
+        expect_not_stdout_ok(config, &["rustup", "cache", "size"], "in 0 files");
+        // Packages of the installation replaced by the update are kept
+        // for rolling back to
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "prune", "--older-than", "0"],
+            "removed 0 files",
+        );
+        expect_ok(config, &["rustup", "cache", "clear"]);
+        expect_stdout_ok(config, &["rustup", "cache", "size"], "0 B in 0 files");
+    });
+}
+
+#[test]
+fn cache_keeps_downloads_in_progress() {
+    setup(&|config| {
+        let downloads = config.rustupdir.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        rustup::utils::raw::write_file(&downloads.join("1234.partial"), "xxx").unwrap();
+        rustup::utils::raw::write_file(&downloads.join("5678"), "xxx").unwrap();
+
+        // Another rustup is still downloading 1234
+        let lock_path = downloads.join("locks").join("1234.lock");
+        let lock = FileLock::exclusive(&lock_path, &|_| ()).unwrap();
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "prune"],
+            "removed 1 files, freeing 3 B",
+        );
+        expect_stdout_ok(config, &["rustup", "cache", "clear"], "removed 0 files");
+        assert!(downloads.join("1234.partial").exists());
+        assert!(!downloads.join("5678").exists());
+
+        drop(lock);
+        expect_stdout_ok(config, &["rustup", "cache", "clear"], "removed 1 files");
+        assert!(!downloads.join("1234.partial").exists());
+    });
+}
+
+#[test]
+fn cache_keeps_reused_downloads_with_retention() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "set", "download-retention", "1"]);
+        expect_ok(config, &["rustup", "default", "nightly"]);
+
+        // The packages were downloaded long ago, but reinstalling reuses
+        // them
+        let downloads = config.rustupdir.join("downloads");
+        let long_ago =
+            FileTime::from_system_time(SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60));
+        let mut packages = 0;
+        for entry in fs::read_dir(&downloads).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_file() {
+                filetime::set_file_times(&path, long_ago, long_ago).unwrap();
+                packages += 1;
+            }
+        }
+        assert!(packages > 0);
+
+        expect_ok(config, &["rustup", "toolchain", "remove", "nightly"]);
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+            ],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "size"],
+            &format!("in {} files", packages),
+        );
+    });
+}
+
+#[test]
+fn set_download_retention_requires_days() {
+    setup(&|config| {
+        expect_err(
+            config,
+            &["rustup", "set", "download-retention", "forever"],
+            "'forever' is not a number of days",
+        );
+        expect_ok(config, &["rustup", "set", "download-retention", "none"]);
+    });
+}
+
+#[test]
+fn shared_store_is_cleaned_up_with_toolchains() {
+    clitools::setup(Scenario::ArchivesV2, &|config| {
+        expect_ok(config, &["rustup", "set", "shared-store", "on"]);
+        expect_ok(config, &["rustup", "default", "nightly-2015-01-01"]);
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly-2015-01-02",
+                "--no-self-update",
+            ],
+        );
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-1");
+        expect_not_stdout_ok(
+            config,
+            &["rustup", "cache", "stats"],
+            "shared store: 0 files",
+        );
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "uninstall", "nightly-2015-01-01"],
+        );
+        expect_ok(
+            config,
+            &["rustup", "toolchain", "uninstall", "nightly-2015-01-02"],
+        );
+        expect_stdout_ok(
+            config,
+            &["rustup", "cache", "stats"],
+            "shared store: 0 files, 0 B",
+        );
+    });
+}
+
+// Copies the manifests of the dist server in `config` to `mirror`, and
+// the packages too if `packages` is given, with those contents
+fn mirror_dist_server(config: &Config, mirror: &Path, packages: Option<&[u8]>) {
+    for entry in walkdir::WalkDir::new(&config.distdir) {
+        let entry = entry.unwrap();
+        let dest = mirror.join(entry.path().strip_prefix(&config.distdir).unwrap());
+        let name = entry.file_name().to_string_lossy();
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest).unwrap();
+        } else if name.starts_with("channel-rust-") {
+            fs::copy(entry.path(), &dest).unwrap();
+        } else if let Some(contents) = packages {
+            if name.contains(".tar.") && !name.ends_with(".sha256") {
+                fs::write(&dest, contents).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn dist_servers_fall_back_on_missing_packages() {
+    setup(&|config| {
+        let mirror = TempDir::new("rustup-mirror").unwrap();
+        mirror_dist_server(config, mirror.path(), None);
+        let servers = format!(
+            "file://{},file://{}",
+            mirror.path().display(),
+            config.distdir.display()
+        );
+
+        let out = clitools::run(
+            config,
+            "rustup",
+            &[
+                "--verbose",
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+            ],
+            &[("RUSTUP_DIST_SERVER", &servers)],
+        );
+        assert!(out.ok);
+        assert!(out.stderr.contains(&format!(
+            "downloaded the manifest for 'nightly' from 'file://{}",
+            mirror.path().display()
+        )));
+        assert!(out.stderr.contains("trying the next dist server"));
+        assert!(out.stderr.contains(&format!(
+            "downloaded rustc-{} from 'file://{}",
+            this_host_triple(),
+            config.distdir.display()
+        )));
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
+#[test]
+fn dist_servers_fall_back_on_checksum_mismatch() {
+    setup(&|config| {
+        let mirror = TempDir::new("rustup-mirror").unwrap();
+        mirror_dist_server(config, mirror.path(), Some(b"not a package"));
+        let servers = format!(
+            "file://{},file://{}",
+            mirror.path().display(),
+            config.distdir.display()
+        );
+
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["toolchain", "install", "nightly", "--no-self-update"],
+            &[("RUSTUP_DIST_SERVER", &servers)],
+        );
+        assert!(out.ok);
+        assert!(out.stderr.contains("trying the next dist server"));
+        expect_ok(config, &["rustup", "default", "nightly"]);
+        expect_stdout_ok(config, &["rustc", "--version"], "hash-n-2");
+    });
+}
+
+#[test]
+fn dist_servers_setting() {
+    setup(&|config| {
+        let mirror = TempDir::new("rustup-mirror").unwrap();
+        let mirror_url = format!("file://{}", mirror.path().display());
+        let dist_url = format!("file://{}", config.distdir.display());
+        expect_ok(
+            config,
+            &["rustup", "set", "dist-servers", &mirror_url, &dist_url],
+        );
+
+        // An empty `RUSTUP_DIST_SERVER` leaves the choice to the setting
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["toolchain", "install", "nightly", "--no-self-update"],
+            &[("RUSTUP_DIST_SERVER", "")],
+        );
+        assert!(out.ok);
+        assert!(out.stderr.contains(&format!(
+            "could not download '{}/dist/channel-rust-nightly.toml'",
+            mirror_url
+        )));
+
+        expect_ok(config, &["rustup", "set", "dist-servers", "default"]);
+    });
+}
+
+#[test]
+fn download_retries_setting() {
+    setup(&|config| {
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "download-retries", "0"],
+            "failed downloads will not be retried",
+        );
+        let settings = fs::read_to_string(config.rustupdir.join("settings.toml")).unwrap();
+        assert!(settings.contains("download_retries = 0"));
+
+        expect_err(
+            config,
+            &["rustup", "set", "download-retries", "lots"],
+            "'lots' is not a number of retries",
+        );
+
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["toolchain", "install", "nightly", "--no-self-update"],
+            &[("RUSTUP_DOWNLOAD_RETRIES", "lots")],
+        );
+        assert!(!out.ok);
+        assert!(out
+            .stderr
+            .contains("RUSTUP_DOWNLOAD_RETRIES must be a number of retries, not 'lots'"));
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "download-retries", "default"],
+            "failed downloads will be retried up to 3 times",
+        );
+    });
+}
+
+#[test]
+fn download_options_from_settings_and_env() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "set", "download-retries", "1"]);
+        let settings_path = config.rustupdir.join("settings.toml");
+        let settings = fs::read_to_string(&settings_path).unwrap();
+        fs::write(
+            &settings_path,
+            settings + "no_proxy = [\"example.com\"]\nlow_speed_time = 60\n",
+        )
+        .unwrap();
+        expect_ok(
+            config,
+            &[
+                "rustup",
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+            ],
+        );
+
+        // The environment takes precedence over the settings
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["update", "nightly", "--no-self-update"],
+            &[("RUSTUP_LOW_SPEED_TIME", "soon")],
+        );
+        assert!(!out.ok);
+        assert!(out
+            .stderr
+            .contains("RUSTUP_LOW_SPEED_TIME must be a number, not 'soon'"));
+
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["update", "nightly", "--no-self-update"],
+            &[("RUSTUP_PROXY", "not a proxy")],
+        );
+        assert!(!out.ok);
+        assert!(out.stderr.contains("invalid proxy 'not a proxy'"));
+    });
+}
+
+#[test]
+fn bad_download_options_only_fail_downloads() {
+    setup(&|config| {
+        expect_ok(config, &["rustup", "default", "nightly"]);
+
+        let env = [
+            ("RUSTUP_CONNECT_TIMEOUT", "soon"),
+            ("RUSTUP_PROXY", "not a proxy"),
+        ];
+        let out = clitools::run(config, "rustc", &["--version"], &env);
+        assert!(out.ok);
+        assert!(out.stdout.contains("hash-n-2"));
+        let out = clitools::run(config, "rustup", &["toolchain", "list"], &env);
+        assert!(out.ok);
+
+        let out = clitools::run(
+            config,
+            "rustup",
+            &["toolchain", "install", "stable", "--no-self-update"],
+            &env,
+        );
+        assert!(!out.ok);
+    });
+}
+
+#[test]
+fn max_download_rate_setting() {
+    setup(&|config| {
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "max-download-rate", "2M"],
+            "downloads will be limited to 2097152 bytes per second",
+        );
+        let settings = fs::read_to_string(config.rustupdir.join("settings.toml")).unwrap();
+        assert!(settings.contains("max_download_rate = 2097152"));
+
+        expect_err(
+            config,
+            &["rustup", "set", "max-download-rate", "fast"],
+            "'fast' is not a download rate",
+        );
+
+        // The environment takes precedence over the setting
+        let out = clitools::run(
+            config,
+            "rustup",
+            &[
+                "--verbose",
+                "toolchain",
+                "install",
+                "nightly",
+                "--no-self-update",
+            ],
+            &[("RUSTUP_MAX_DOWNLOAD_RATE", "100M")],
+        );
+        assert!(out.ok);
+        assert!(out
+            .stderr
+            .contains("download limited to 104857600 bytes per second"));
+
+        expect_stderr_ok(
+            config,
+            &["rustup", "set", "max-download-rate", "none"],
+            "downloads will not be limited",
+        );
+    });
+}
@@ -5,18 +5,21 @@ pub mod mock;
 
 use crate::mock::dist::*;
 use crate::mock::{MockComponentBuilder, MockFile, MockInstallerBuilder};
+use download::DownloadOptions;
 use rustup::dist::dist::{TargetTriple, ToolchainDesc, DEFAULT_DIST_SERVER};
 use rustup::dist::download::DownloadCfg;
 use rustup::dist::manifest::{Component, Manifest};
 use rustup::dist::manifestation::{Changes, Manifestation, UpdateStatus};
 use rustup::dist::prefix::InstallPrefix;
+use rustup::dist::signatures::SignatureCheck;
 use rustup::dist::temp;
 use rustup::dist::Notification;
 use rustup::errors::Result;
 use rustup::utils::raw as utils_raw;
 use rustup::utils::utils;
+use rustup::utils::Notification as Un;
 use rustup::ErrorKind;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
@@ -223,7 +226,7 @@ fn mock_dist_server_smoke_test() {
     let tempdir = TempDir::new("rustup").unwrap();
     let path = tempdir.path();
 
-    create_mock_dist_server(&path, None).write(&[ManifestVersion::V2], false);
+    create_mock_dist_server(&path, None).write(&[ManifestVersion::V2], false, false);
 
     assert!(utils::path_exists(path.join(
         "dist/2016-02-01/rustc-nightly-x86_64-apple-darwin.tar.gz"
@@ -305,6 +308,7 @@ fn rename_component() {
         mock_dist_server,
         &url,
         false,
+        false,
         &|url, toolchain, prefix, download_cfg, temp_cfg| {
             change_channel_date(url, "nightly", "2016-02-01");
             update_from_dist(url, toolchain, prefix, &[], &[], download_cfg, temp_cfg).unwrap();
@@ -354,6 +358,7 @@ fn rename_component_new() {
         mock_dist_server,
         &url,
         false,
+        false,
         &|url, toolchain, prefix, download_cfg, temp_cfg| {
             change_channel_date(url, "nightly", "2016-02-01");
             update_from_dist(url, toolchain, prefix, &[], &[], download_cfg, temp_cfg).unwrap();
@@ -383,7 +388,14 @@ fn update_from_dist(
     // Download the dist manifest and place it into the installation prefix
     let manifest_url = make_manifest_url(dist_server, toolchain)?;
     let manifest_file = temp_cfg.new_file()?;
-    utils::download_file(&manifest_url, &manifest_file, None, &|_| {})?;
+    utils::download_file(
+        &manifest_url,
+        &manifest_file,
+        None,
+        &*utils::download_backend(),
+        &DownloadOptions::default(),
+        &|_| {},
+    )?;
     let manifest_str = utils::read_file("manifest", &manifest_file)?;
     let manifest = Manifest::parse(&manifest_str)?;
 
@@ -394,6 +406,8 @@ fn update_from_dist(
     let changes = Changes {
         add_extensions: add.to_owned(),
         remove_extensions: remove.to_owned(),
+        reinstall_components: vec![],
+        profile: None,
     };
 
     manifestation.update(
@@ -438,16 +452,17 @@ fn setup(
     let dist_tempdir = TempDir::new("rustup").unwrap();
     let mock_dist_server = create_mock_dist_server(dist_tempdir.path(), edit);
     let url = Url::parse(&format!("file://{}", dist_tempdir.path().to_string_lossy())).unwrap();
-    setup_from_dist_server(mock_dist_server, &url, enable_xz, f);
+    setup_from_dist_server(mock_dist_server, &url, enable_xz, false, f);
 }
 
 fn setup_from_dist_server(
     server: MockDistServer,
     url: &Url,
     enable_xz: bool,
+    enable_zst: bool,
     f: &dyn Fn(&Url, &ToolchainDesc, &InstallPrefix, &DownloadCfg<'_>, &temp::Cfg),
 ) {
-    server.write(&[ManifestVersion::V2], enable_xz);
+    server.write(&[ManifestVersion::V2], enable_xz, enable_zst);
 
     let prefix_tempdir = TempDir::new("rustup").unwrap();
 
@@ -461,9 +476,15 @@ fn setup_from_dist_server(
     let toolchain = ToolchainDesc::from_str("nightly-x86_64-apple-darwin").unwrap();
     let prefix = InstallPrefix::from(prefix_tempdir.path().to_owned());
     let download_cfg = DownloadCfg {
-        dist_root: "phony",
+        dist_roots: &[],
         temp_cfg: &temp_cfg,
         download_dir: &prefix.path().to_owned().join("downloads"),
+        gpg_key: include_str!("mock/signing-key.pub.asc"),
+        signature_check: SignatureCheck::Enforce,
+        backend: &utils::download_backend(),
+        retention: None,
+        store: None,
+        options: &DownloadOptions::default(),
         notify_handler: &|_| {},
     };
 
@@ -498,6 +519,49 @@ fn initial_install_xz() {
     });
 }
 
+#[test]
+#[cfg(feature = "zstd")]
+fn initial_install_zst() {
+    let dist_tempdir = TempDir::new("rustup").unwrap();
+    let mock_dist_server = create_mock_dist_server(dist_tempdir.path(), None);
+    let url = Url::parse(&format!("file://{}", dist_tempdir.path().to_string_lossy())).unwrap();
+
+    setup_from_dist_server(
+        mock_dist_server,
+        &url,
+        true,
+        true,
+        &|url, toolchain, prefix, download_cfg, temp_cfg| {
+            let downloaded = RefCell::new(vec![]);
+            let download_cfg = DownloadCfg {
+                dist_roots: download_cfg.dist_roots,
+                temp_cfg: download_cfg.temp_cfg,
+                download_dir: download_cfg.download_dir,
+                gpg_key: download_cfg.gpg_key,
+                signature_check: download_cfg.signature_check,
+                backend: download_cfg.backend,
+                retention: download_cfg.retention,
+                store: download_cfg.store,
+                options: download_cfg.options,
+                notify_handler: &|n| {
+                    if let Notification::Utils(Un::DownloadingFile(url, _)) = n {
+                        downloaded.borrow_mut().push(url.to_string());
+                    }
+                },
+            };
+
+            update_from_dist(url, toolchain, prefix, &[], &[], &download_cfg, temp_cfg).unwrap();
+
+            let downloaded = downloaded.borrow();
+            let packages: Vec<_> = downloaded.iter().filter(|u| u.contains(".tar.")).collect();
+            assert!(!packages.is_empty());
+            assert!(packages.iter().all(|u| u.ends_with(".tar.zst")));
+            assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+            assert!(utils::path_exists(&prefix.path().join("lib/libstd.rlib")));
+        },
+    );
+}
+
 #[test]
 fn test_uninstall() {
     setup(None, false, &|url,
@@ -625,9 +689,15 @@ fn removed_component() {
             let received_notification = Arc::new(Cell::new(false));
 
             let download_cfg = DownloadCfg {
-                dist_root: download_cfg.dist_root,
+                dist_roots: download_cfg.dist_roots,
                 temp_cfg: download_cfg.temp_cfg,
                 download_dir: download_cfg.download_dir,
+                gpg_key: download_cfg.gpg_key,
+                signature_check: download_cfg.signature_check,
+                backend: download_cfg.backend,
+                retention: download_cfg.retention,
+                store: download_cfg.store,
+                options: download_cfg.options,
                 notify_handler: &|n| {
                     if let Notification::ComponentUnavailable("bonus", Some(_)) = n {
                         received_notification.set(true);
@@ -1438,9 +1508,15 @@ fn reuse_downloaded_file() {
         let reuse_notification_fired = Arc::new(Cell::new(false));
 
         let download_cfg = DownloadCfg {
-            dist_root: download_cfg.dist_root,
+            dist_roots: download_cfg.dist_roots,
             temp_cfg: download_cfg.temp_cfg,
             download_dir: download_cfg.download_dir,
+            gpg_key: download_cfg.gpg_key,
+            signature_check: download_cfg.signature_check,
+            backend: download_cfg.backend,
+            retention: download_cfg.retention,
+            store: download_cfg.store,
+            options: download_cfg.options,
             notify_handler: &|n| {
                 if let Notification::FileAlreadyDownloaded = n {
                     reuse_notification_fired.set(true);
@@ -1480,9 +1556,15 @@ fn checks_files_hashes_before_reuse() {
 
         let noticed_bad_checksum = Arc::new(Cell::new(false));
         let download_cfg = DownloadCfg {
-            dist_root: download_cfg.dist_root,
+            dist_roots: download_cfg.dist_roots,
             temp_cfg: download_cfg.temp_cfg,
             download_dir: download_cfg.download_dir,
+            gpg_key: download_cfg.gpg_key,
+            signature_check: download_cfg.signature_check,
+            backend: download_cfg.backend,
+            retention: download_cfg.retention,
+            store: download_cfg.store,
+            options: download_cfg.options,
             notify_handler: &|n| {
                 if let Notification::CachedFileChecksumFailed = n {
                     noticed_bad_checksum.set(true);
@@ -1495,3 +1577,114 @@ fn checks_files_hashes_before_reuse() {
         assert!(noticed_bad_checksum.get());
     })
 }
+
+#[test]
+fn reports_download_progress_per_component() {
+    setup(None, false, &|url,
+                         toolchain,
+                         prefix,
+                         download_cfg,
+                         temp_cfg| {
+        let downloading = RefCell::new(Vec::new());
+        let finished = RefCell::new(Vec::new());
+        let download_cfg = DownloadCfg {
+            dist_roots: download_cfg.dist_roots,
+            temp_cfg: download_cfg.temp_cfg,
+            download_dir: download_cfg.download_dir,
+            gpg_key: download_cfg.gpg_key,
+            signature_check: download_cfg.signature_check,
+            backend: download_cfg.backend,
+            retention: download_cfg.retention,
+            store: download_cfg.store,
+            options: download_cfg.options,
+            notify_handler: &|n| match n {
+                Notification::DownloadingComponent(c, _, _) => {
+                    downloading.borrow_mut().push(c.to_owned())
+                }
+                Notification::ComponentDownloadFinished(c) => {
+                    finished.borrow_mut().push(c.to_owned())
+                }
+                _ => {}
+            },
+        };
+
+        update_from_dist(url, toolchain, prefix, &[], &[], &download_cfg, temp_cfg).unwrap();
+
+        // Every component that started downloading reports finishing
+        assert!(downloading.borrow().len() > 1);
+        assert_eq!(finished.borrow().len(), downloading.borrow().len());
+        assert!(finished.borrow().iter().any(|c| c.starts_with("rustc")));
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+    })
+}
+
+#[test]
+fn streamed_package_with_bad_hash_is_not_installed() {
+    setup(None, false, &|url,
+                         toolchain,
+                         prefix,
+                         download_cfg,
+                         temp_cfg| {
+        // A well-formed package, just not the one the manifest describes
+        let path = url.to_file_path().unwrap().join("dist/2016-02-02");
+        fs::copy(
+            path.join("cargo-nightly-x86_64-apple-darwin.tar.gz"),
+            path.join("rustc-nightly-x86_64-apple-darwin.tar.gz"),
+        )
+        .unwrap();
+
+        let err =
+            update_from_dist(url, toolchain, prefix, &[], &[], download_cfg, temp_cfg).unwrap_err();
+
+        match err.iter().nth(1).map(|e| e.to_string()) {
+            Some(ref e) if e.starts_with("checksum failed") => (),
+            _ => panic!("{}", err),
+        }
+        assert!(!utils::path_exists(&prefix.path().join("bin/rustc")));
+        assert!(!utils::path_exists(&prefix.path().join("bin/cargo")));
+    })
+}
+
+#[test]
+fn resumes_partial_download_before_unpacking() {
+    setup(None, false, &|url,
+                         toolchain,
+                         prefix,
+                         download_cfg,
+                         temp_cfg| {
+        let path = url.to_file_path().unwrap();
+        let path = path.join("dist/2016-02-02/rustc-nightly-x86_64-apple-darwin.tar.gz");
+        let target_hash = utils::read_file("target hash", &path.with_extension("gz.sha256"))
+            .unwrap()[..64]
+            .to_owned();
+        let package = fs::read(&path).unwrap();
+        let partial = download_cfg
+            .download_dir
+            .join(format!("{}.partial", target_hash));
+        utils::ensure_dir_exists("download dir", download_cfg.download_dir, &|_| {}).unwrap();
+        fs::write(&partial, &package[..package.len() / 2]).unwrap();
+
+        let resumed = Cell::new(false);
+        let download_cfg = DownloadCfg {
+            dist_roots: download_cfg.dist_roots,
+            temp_cfg: download_cfg.temp_cfg,
+            download_dir: download_cfg.download_dir,
+            gpg_key: download_cfg.gpg_key,
+            signature_check: download_cfg.signature_check,
+            backend: download_cfg.backend,
+            retention: download_cfg.retention,
+            store: download_cfg.store,
+            options: download_cfg.options,
+            notify_handler: &|n| {
+                if let Notification::Utils(Un::ResumingPartialDownload) = n {
+                    resumed.set(true);
+                }
+            },
+        };
+
+        update_from_dist(url, toolchain, prefix, &[], &[], &download_cfg, temp_cfg).unwrap();
+
+        assert!(resumed.get());
+        assert!(utils::path_exists(&prefix.path().join("bin/rustc")));
+    })
+}
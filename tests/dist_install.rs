@@ -12,6 +12,7 @@ use rustup::utils::utils;
 use rustup::ErrorKind;
 use std::fs::File;
 use std::io::Write;
+use std::path::PathBuf;
 use tempdir::TempDir;
 
 // Just testing that the mocks work
@@ -241,6 +242,68 @@ fn uninstall() {
     assert!(components.find("mycomponent2").unwrap().is_none());
 }
 
+#[test]
+fn verify_finds_missing_modified_and_extra_files() {
+    let pkgdir = TempDir::new("rustup").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![MockComponentBuilder {
+            name: "mycomponent".to_string(),
+            files: vec![
+                MockFile::new("bin/foo", b"foo"),
+                MockFile::new("lib/bar", b"bar"),
+                MockFile::new_dir("doc/stuff", &[("doc1", b"", false), ("doc2", b"", false)]),
+            ],
+        }],
+    };
+
+    mock.build(pkgdir.path());
+
+    let instdir = TempDir::new("rustup").unwrap();
+    let prefix = InstallPrefix::from(instdir.path().to_owned());
+
+    let tmpdir = TempDir::new("rustup").unwrap();
+    let tmpcfg = temp::Cfg::new(
+        tmpdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let notify = |_: Notification<'_>| ();
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+
+    let components = Components::open(prefix.clone()).unwrap();
+
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned(), true).unwrap();
+
+    let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+    tx.commit();
+
+    let component = components.find("mycomponent").unwrap().unwrap();
+    let verification = component.verify().unwrap();
+    assert!(verification.hashed);
+    assert!(verification.is_intact());
+
+    utils::remove_file("", &instdir.path().join("bin/foo")).unwrap();
+    utils::write_file("", &instdir.path().join("doc/stuff/doc2"), "changed").unwrap();
+    utils::write_file("", &instdir.path().join("doc/stuff/doc3"), "").unwrap();
+
+    let verification = component.verify().unwrap();
+    assert_eq!(verification.missing, vec![PathBuf::from("bin/foo")]);
+    assert_eq!(verification.modified, vec![PathBuf::from("doc/stuff/doc2")]);
+    assert_eq!(verification.extra, vec![PathBuf::from("doc/stuff/doc3")]);
+
+    // Damaged components can still be uninstalled
+    let tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+    let tx = component.uninstall(tx).unwrap();
+    tx.commit();
+
+    assert!(!utils::path_exists(instdir.path().join("lib/bar")));
+    assert!(!utils::path_exists(instdir.path().join("doc/stuff")));
+    assert!(!utils::path_exists(
+        prefix.manifest_file("hashes-mycomponent")
+    ));
+}
+
 // If any single file can't be uninstalled, it is not a fatal error
 // and the subsequent files will still be removed.
 #[test]
@@ -428,3 +491,110 @@ fn install_to_prefix_that_does_not_exist() {
 
     assert!(utils::path_exists(does_not_exist.join("bin/foo")));
 }
+
+#[test]
+#[cfg(unix)]
+fn install_through_store_shares_files() {
+    use rustup::dist::component::Store;
+    use std::os::unix::fs::MetadataExt;
+
+    let pkgdir = TempDir::new("rustup").unwrap();
+
+    let mock = MockInstallerBuilder {
+        components: vec![MockComponentBuilder {
+            name: "mycomponent".to_string(),
+            files: vec![
+                MockFile::new("bin/foo", b"foo"),
+                MockFile::new_dir(
+                    "doc/stuff",
+                    &[("doc1", b"doc", false), ("doc2", b"", false)],
+                ),
+            ],
+        }],
+    };
+
+    mock.build(pkgdir.path());
+
+    let storedir = TempDir::new("rustup").unwrap();
+    let store = Store::new(storedir.path().to_owned());
+
+    let tmpdir = TempDir::new("rustup").unwrap();
+    let tmpcfg = temp::Cfg::new(
+        tmpdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+    let notify = |_: Notification<'_>| ();
+    let pkg = DirectoryPackage::new(pkgdir.path().to_owned(), true).unwrap();
+
+    let instdirs = [
+        TempDir::new("rustup").unwrap(),
+        TempDir::new("rustup").unwrap(),
+    ];
+    for instdir in &instdirs {
+        let prefix = InstallPrefix::from(instdir.path().to_owned());
+        let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+        tx.use_store(&store);
+        let components = Components::open(prefix).unwrap();
+        let tx = pkg.install(&components, "mycomponent", None, tx).unwrap();
+        tx.commit();
+    }
+
+    let foo = |i: usize| {
+        std::fs::metadata(instdirs[i].path().join("bin/foo"))
+            .unwrap()
+            .ino()
+    };
+    assert_eq!(foo(0), foo(1));
+    assert!(utils::path_exists(
+        instdirs[1].path().join("doc/stuff/doc1")
+    ));
+    let stats = store.stats().unwrap();
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.saved_size, 6);
+
+    // Uninstalling drops the links, and the files once nothing uses them
+    for (i, instdir) in instdirs.iter().enumerate() {
+        let prefix = InstallPrefix::from(instdir.path().to_owned());
+        let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+        tx.use_store(&store);
+        let components = Components::open(prefix).unwrap();
+        let component = components.find("mycomponent").unwrap().unwrap();
+        let tx = component.uninstall(tx).unwrap();
+        tx.commit();
+
+        assert!(!utils::path_exists(instdir.path().join("bin/foo")));
+        assert_eq!(store.stats().unwrap().files, if i == 0 { 3 } else { 0 });
+    }
+}
+
+#[test]
+fn store_collects_only_files_nothing_is_adding() {
+    use rustup::dist::component::Store;
+
+    let srcdir = TempDir::new("rustup").unwrap();
+    let destdir = TempDir::new("rustup").unwrap();
+    let storedir = TempDir::new("rustup").unwrap();
+    let store = Store::new(storedir.path().to_owned());
+
+    let src = srcdir.path().join("foo");
+    let dest = destdir.path().join("foo");
+    utils::write_file("foo", &src, "foo").unwrap();
+    store.link_file(&src, &dest, true).unwrap();
+
+    // Another process is part way through adding a file
+    std::fs::create_dir(storedir.path().join("00")).unwrap();
+    let partial = storedir.path().join("00").join("0000.1.partial");
+    utils::write_file("partial", &partial, "").unwrap();
+
+    utils::remove_file("foo", &dest).unwrap();
+    assert_eq!(store.collect_garbage().unwrap(), 1);
+    assert!(utils::path_exists(&partial));
+    assert_eq!(store.stats().unwrap().files, 0);
+
+    // A collected file is added again when something links it
+    store.link_file(&src, &dest, false).unwrap();
+    assert!(!utils::path_exists(&src));
+    assert_eq!(utils::read_file("foo", &dest).unwrap(), "foo");
+    assert_eq!(store.stats().unwrap().files, 1);
+}
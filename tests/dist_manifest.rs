@@ -1,4 +1,4 @@
-use rustup::dist::dist::TargetTriple;
+use rustup::dist::dist::{Profile, TargetTriple};
 use rustup::dist::manifest::Manifest;
 use rustup::ErrorKind;
 
@@ -54,6 +54,56 @@ fn renames() {
     assert_eq!(manifest.reverse_renames["cargo"], "cargo-old");
 }
 
+#[test]
+fn profiles() {
+    let x86_64_unknown_linux_gnu = TargetTriple::from_str("x86_64-unknown-linux-gnu");
+    let manifest = format!(
+        "{}\n[profiles]\nminimal = [\"rustc\"]\nunknown = [\"cargo\"]\n",
+        EXAMPLE
+    );
+    let manifest = Manifest::parse(&manifest).unwrap();
+
+    assert_eq!(1, manifest.profiles.len());
+    assert_eq!(manifest.profiles[&Profile::Minimal], vec!["rustc"]);
+
+    let components = manifest
+        .get_profile_components(Profile::Minimal, &x86_64_unknown_linux_gnu)
+        .unwrap();
+    let names: Vec<_> = components
+        .iter()
+        .map(|c| c.short_name_in_manifest())
+        .collect();
+    assert_eq!(names, vec!["rustc"]);
+
+    let serialized = manifest.clone().stringify();
+    assert_eq!(manifest, Manifest::parse(&serialized).unwrap());
+}
+
+#[test]
+fn builtin_profiles() {
+    let x86_64_unknown_linux_gnu = TargetTriple::from_str("x86_64-unknown-linux-gnu");
+    let manifest = Manifest::parse(EXAMPLE).unwrap();
+    assert!(manifest.profiles.is_empty());
+
+    let names = |profile| -> Vec<String> {
+        manifest
+            .get_profile_components(profile, &x86_64_unknown_linux_gnu)
+            .unwrap()
+            .iter()
+            .map(|c| c.short_name_in_manifest().to_owned())
+            .collect()
+    };
+    assert_eq!(names(Profile::Minimal), vec!["rustc", "cargo", "rust-std"]);
+    assert_eq!(
+        names(Profile::Default),
+        vec!["rustc", "rust-docs", "cargo", "rust-std"]
+    );
+    assert_eq!(
+        names(Profile::Complete),
+        vec!["rustc", "rust-docs", "cargo", "rust-std"]
+    );
+}
+
 #[test]
 fn parse_round_trip() {
     let original = Manifest::parse(EXAMPLE).unwrap();
@@ -107,3 +157,130 @@ fn manifest_can_contain_unknown_targets() {
 
     assert!(Manifest::parse(&manifest).is_ok());
 }
+
+#[test]
+fn unknown_keys_round_trip() {
+    let manifest = EXAMPLE
+        .replace(
+            "date = \"2015-10-10\"",
+            "date = \"2015-10-10\"\nmirror-policy = \"nearest\"",
+        )
+        .replace(
+            "[pkg.rust]\n",
+            "[pkg.rust]\n  homepage = \"https://www.rust-lang.org\"\n",
+        )
+        + "\n[signatures]\nkeys = [\"a\", \"b\"]\n"
+        + "\n[profiles]\nminimal = [\"rustc\"]\nembedded = [\"rustc\", \"rust-std\"]\n";
+    let manifest = Manifest::parse(&manifest).unwrap();
+
+    assert_eq!(manifest.unknown["mirror-policy"].as_str(), Some("nearest"));
+    assert!(manifest.unknown["signatures"].is_table());
+    assert_eq!(manifest.profiles.len(), 1);
+    assert!(manifest.unknown["profiles"]["embedded"].is_array());
+    assert!(manifest.get_package("rust").unwrap().unknown["homepage"].is_str());
+
+    let serialized = manifest.clone().stringify();
+    assert!(serialized.contains("[signatures]"));
+    assert!(serialized.contains("homepage"));
+    assert!(serialized.contains("embedded"));
+    assert!(serialized.contains("minimal"));
+    assert_eq!(manifest, Manifest::parse(&serialized).unwrap());
+}
+
+#[test]
+fn parse_artifacts() {
+    let x86_64_unknown_linux_gnu = TargetTriple::from_str("x86_64-unknown-linux-gnu");
+    let x86_64_pc_windows_msvc = TargetTriple::from_str("x86_64-pc-windows-msvc");
+    let manifest = EXAMPLE.replace("manifest-version = \"2\"", "manifest-version = \"2.1\"")
+        + r#"
+[[artifacts.source-code.target."*"]]
+url = "example.com/rustc-src.tar.gz"
+hash-sha256 = "..."
+size = 1024
+
+[[artifacts.source-code.target."*"]]
+url = "example.com/rustc-src.tar.xz"
+hash-sha256 = "..."
+size = 512
+
+[[artifacts.source-code.target."*"]]
+url = "example.com/rustc-src.tar.zst"
+hash-blake3 = "..."
+
+[[artifacts.installer-msi.target.x86_64-pc-windows-msvc]]
+url = "example.com/rust.msi"
+hash-sha256 = "..."
+"#;
+    let manifest = Manifest::parse(&manifest).unwrap();
+    assert_eq!(manifest.manifest_version, "2.1");
+
+    let source = &manifest.artifacts["source-code"];
+    let files = source.get_target(&x86_64_unknown_linux_gnu);
+    let urls: Vec<_> = files.iter().map(|f| &*f.url).collect();
+    assert_eq!(
+        urls,
+        vec![
+            "example.com/rustc-src.tar.gz",
+            "example.com/rustc-src.tar.xz"
+        ]
+    );
+    assert_eq!(files[1].size, Some(512));
+
+    let msi = &manifest.artifacts["installer-msi"];
+    assert_eq!(msi.get_target(&x86_64_pc_windows_msvc).len(), 1);
+    assert!(msi.get_target(&x86_64_unknown_linux_gnu).is_empty());
+
+    let serialized = manifest.clone().stringify();
+    assert!(serialized.contains("hash-blake3"));
+    assert_eq!(manifest, Manifest::parse(&serialized).unwrap());
+}
+
+#[test]
+fn unreadable_artifacts_are_kept() {
+    let x86_64_unknown_linux_gnu = TargetTriple::from_str("x86_64-unknown-linux-gnu");
+    let manifest = EXAMPLE.replace("manifest-version = \"2\"", "manifest-version = \"2.1\"")
+        + r#"
+[[artifacts.source-code.target."*"]]
+url = "example.com/rustc-src.tar.gz"
+hash-sha256 = "..."
+
+[[artifacts.installer-msi.target.x86_64-pc-windows-msvc]]
+hash-sha256 = "..."
+
+[artifacts.installer-pkg.target]
+x86_64-apple-darwin = "example.com/rust.pkg"
+"#;
+    let manifest = Manifest::parse(&manifest).unwrap();
+
+    let source = &manifest.artifacts["source-code"];
+    assert_eq!(source.get_target(&x86_64_unknown_linux_gnu).len(), 1);
+    assert!(!manifest.artifacts.contains_key("installer-msi"));
+    assert!(!manifest.artifacts.contains_key("installer-pkg"));
+
+    let serialized = manifest.clone().stringify();
+    assert!(serialized.contains("installer-msi"));
+    assert!(serialized.contains("example.com/rust.pkg"));
+    assert_eq!(manifest, Manifest::parse(&serialized).unwrap());
+}
+
+#[test]
+fn unknown_versions_are_unsupported() {
+    let manifest = EXAMPLE.replace("manifest-version = \"2\"", "manifest-version = \"2.1\"")
+        + "\n[pkg.rust.target.x86_64-unknown-linux-gnu.signatures]\nminisign = \"...\"\n";
+    let manifest = Manifest::parse(&manifest).unwrap();
+    let serialized = manifest.clone().stringify();
+    assert!(serialized.contains("minisign"));
+    assert_eq!(manifest, Manifest::parse(&serialized).unwrap());
+
+    for version in &["2.7", "3"] {
+        let manifest = EXAMPLE.replace(
+            "manifest-version = \"2\"",
+            &format!("manifest-version = \"{}\"", version),
+        );
+        let err = Manifest::parse(&manifest).unwrap_err();
+        match *err.kind() {
+            ErrorKind::UnsupportedVersion(ref v) if v == version => {}
+            _ => panic!(),
+        }
+    }
+}
@@ -777,5 +777,110 @@ fn rollback_failure_keeps_going() {
 // Test that when a transaction creates intermediate directories that
 // they are deleted during rollback.
 #[test]
-#[ignore]
-fn intermediate_dir_rollback() {}
+fn intermediate_dir_rollback() {
+    let prefixdir = TempDir::new("rustup").unwrap();
+    let txdir = TempDir::new("rustup").unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = |_: Notification<'_>| ();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+
+    fs::create_dir(prefix.path().join("foo")).unwrap();
+    tx.add_file("c", PathBuf::from("foo/bar/baz/quux")).unwrap();
+    tx.modify_file(PathBuf::from("foo/bar/qux/quux")).unwrap();
+    drop(tx);
+
+    assert!(utils::path_exists(prefix.path().join("foo")));
+    assert!(!utils::path_exists(prefix.path().join("foo/bar")));
+    assert!(!utils::path_exists(prefix.path().join("lib")));
+}
+
+// A transaction that was never committed or dropped, as when rustup is
+// killed, is rolled back from its journal.
+#[test]
+fn interrupted_transaction_is_rolled_back() {
+    let prefixdir = TempDir::new("rustup").unwrap();
+    let txdir = TempDir::new("rustup").unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = |_: Notification<'_>| ();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+
+    utils_raw::write_file(&prefix.path().join("foo"), "foo").unwrap();
+    utils_raw::write_file(&prefix.path().join("bar"), "bar").unwrap();
+    tx.remove_file("c", PathBuf::from("foo")).unwrap();
+    tx.modify_file(PathBuf::from("bar")).unwrap();
+    utils_raw::write_file(&prefix.path().join("bar"), "changed").unwrap();
+    tx.write_file("c", PathBuf::from("baz/quux"), "quux".to_owned())
+        .unwrap();
+    std::mem::forget(tx);
+
+    let journal = prefix.manifest_file("rustup-journal");
+    assert!(utils::is_file(&journal));
+
+    Transaction::recover(&prefix, &notify).unwrap();
+
+    assert_eq!(
+        utils_raw::read_file(&prefix.path().join("foo")).unwrap(),
+        "foo"
+    );
+    assert_eq!(
+        utils_raw::read_file(&prefix.path().join("bar")).unwrap(),
+        "bar"
+    );
+    assert!(!utils::path_exists(prefix.path().join("baz")));
+    assert!(!utils::path_exists(&journal));
+    assert!(!utils::path_exists(prefix.path().join("lib")));
+}
+
+// A transaction killed while committing is finished instead.
+#[test]
+fn interrupted_commit_is_finished() {
+    let prefixdir = TempDir::new("rustup").unwrap();
+    let txdir = TempDir::new("rustup").unwrap();
+
+    let tmpcfg = temp::Cfg::new(
+        txdir.path().to_owned(),
+        DEFAULT_DIST_SERVER,
+        Box::new(|_| ()),
+    );
+
+    let prefix = InstallPrefix::from(prefixdir.path().to_owned());
+
+    let notify = |_: Notification<'_>| ();
+    let mut tx = Transaction::new(prefix.clone(), &tmpcfg, &notify);
+
+    utils_raw::write_file(&prefix.path().join("foo"), "foo").unwrap();
+    tx.remove_file("c", PathBuf::from("foo")).unwrap();
+    tx.write_file("c", PathBuf::from("baz/quux"), "quux".to_owned())
+        .unwrap();
+    std::mem::forget(tx);
+
+    let journal = prefix.manifest_file("rustup-journal");
+    utils::append_file("journal", &journal, "committed").unwrap();
+
+    Transaction::recover(&prefix, &notify).unwrap();
+
+    assert!(!utils::path_exists(prefix.path().join("foo")));
+    assert_eq!(
+        utils_raw::read_file(&prefix.path().join("baz/quux")).unwrap(),
+        "quux"
+    );
+    assert!(!utils::path_exists(&journal));
+    // The backup of the removed file is gone as well
+    assert_eq!(fs::read_dir(txdir.path()).unwrap().count(), 0);
+}
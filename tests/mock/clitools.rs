@@ -2,8 +2,8 @@
 //! tests/cli-v2.rs
 
 use crate::mock::dist::{
-    change_channel_date, ManifestVersion, MockChannel, MockComponent, MockDistServer, MockPackage,
-    MockTargetedPackage,
+    change_channel_date, signing_key_path, ManifestVersion, MockChannel, MockComponent,
+    MockDistServer, MockPackage, MockTargetedPackage,
 };
 use crate::mock::{MockComponentBuilder, MockFile, MockInstallerBuilder};
 use lazy_static::lazy_static;
@@ -326,6 +326,10 @@ pub fn env(config: &Config, cmd: &mut Command) {
         format!("file://{}", config.distdir.to_string_lossy()),
     );
     cmd.env("CARGO_HOME", config.cargodir.to_string_lossy().to_string());
+    cmd.env(
+        "RUSTUP_GPG_KEY",
+        signing_key_path().to_string_lossy().to_string(),
+    );
     cmd.env("RUSTUP_OVERRIDE_HOST_TRIPLE", this_host_triple());
 
     // These are used in some installation tests that unset RUSTUP_HOME/CARGO_HOME
@@ -405,7 +409,7 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
         path: path.to_owned(),
         channels: chans,
     }
-    .write(&vs, true);
+    .write(&vs, true, false);
 
     // Also create the manifests for stable releases by version
     if dates_count > 1 {
@@ -417,6 +421,10 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
             path.join("dist/2015-01-01/channel-rust-stable.toml.sha256"),
             path.join("dist/channel-rust-1.0.0.toml.sha256"),
         );
+        let _ = hard_link(
+            path.join("dist/2015-01-01/channel-rust-stable.toml.asc"),
+            path.join("dist/channel-rust-1.0.0.toml.asc"),
+        );
     }
     let _ = hard_link(
         path.join("dist/2015-01-02/channel-rust-stable.toml"),
@@ -426,6 +434,10 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
         path.join("dist/2015-01-02/channel-rust-stable.toml.sha256"),
         path.join("dist/channel-rust-1.1.0.toml.sha256"),
     );
+    let _ = hard_link(
+        path.join("dist/2015-01-02/channel-rust-stable.toml.asc"),
+        path.join("dist/channel-rust-1.1.0.toml.asc"),
+    );
 
     // Same for v1 manifests. These are just the installers.
     let host_triple = this_host_triple();
@@ -446,6 +458,14 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
             path.join(format!("dist/rust-1.0.0-{}.tar.gz.sha256", host_triple)),
         )
         .unwrap();
+        hard_link(
+            path.join(format!(
+                "dist/2015-01-01/rust-stable-{}.tar.gz.asc",
+                host_triple
+            )),
+            path.join(format!("dist/rust-1.0.0-{}.tar.gz.asc", host_triple)),
+        )
+        .unwrap();
     }
     hard_link(
         path.join(format!(
@@ -463,6 +483,14 @@ fn create_mock_dist_server(path: &Path, s: Scenario) {
         path.join(format!("dist/rust-1.1.0-{}.tar.gz.sha256", host_triple)),
     )
     .unwrap();
+    hard_link(
+        path.join(format!(
+            "dist/2015-01-02/rust-stable-{}.tar.gz.asc",
+            host_triple
+        )),
+        path.join(format!("dist/rust-1.1.0-{}.tar.gz.asc", host_triple)),
+    )
+    .unwrap();
 }
 
 fn build_mock_channel(
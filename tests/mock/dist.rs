@@ -3,10 +3,14 @@
 
 use crate::mock::MockInstallerBuilder;
 use lazy_static::lazy_static;
+use pgp::crypto::HashAlgorithm;
+use pgp::packet::{SignatureConfigBuilder, SignatureType, Subpacket};
+use pgp::types::KeyTrait;
+use pgp::{Deserializable, SignedSecretKey, StandaloneSignature};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 use tempdir::TempDir;
@@ -24,25 +28,31 @@ pub fn change_channel_date(dist_server: &Url, channel: &str, date: &str) {
     let manifest_name = format!("dist/channel-rust-{}", channel);
     let manifest_path = path.join(format!("{}.toml", manifest_name));
     let hash_path = path.join(format!("{}.toml.sha256", manifest_name));
+    let sig_path = path.join(format!("{}.toml.asc", manifest_name));
 
     let archive_manifest_name = format!("dist/{}/channel-rust-{}", date, channel);
     let archive_manifest_path = path.join(format!("{}.toml", archive_manifest_name));
     let archive_hash_path = path.join(format!("{}.toml.sha256", archive_manifest_name));
+    let archive_sig_path = path.join(format!("{}.toml.asc", archive_manifest_name));
 
     let _ = hard_link(archive_manifest_path, manifest_path);
     let _ = hard_link(archive_hash_path, hash_path);
+    let _ = hard_link(archive_sig_path, sig_path);
 
     // V1
     let manifest_name = format!("dist/channel-rust-{}", channel);
     let manifest_path = path.join(&manifest_name);
     let hash_path = path.join(format!("{}.sha256", manifest_name));
+    let sig_path = path.join(format!("{}.asc", manifest_name));
 
     let archive_manifest_name = format!("dist/{}/channel-rust-{}", date, channel);
     let archive_manifest_path = path.join(&archive_manifest_name);
     let archive_hash_path = path.join(format!("{}.sha256", archive_manifest_name));
+    let archive_sig_path = path.join(format!("{}.asc", archive_manifest_name));
 
     let _ = hard_link(archive_manifest_path, manifest_path);
     let _ = hard_link(archive_hash_path, hash_path);
+    let _ = hard_link(archive_sig_path, sig_path);
 
     // Copy all files that look like rust-* for the v1 installers
     let archive_path = path.join(format!("dist/{}", date));
@@ -109,6 +119,7 @@ pub struct MockComponent {
 pub struct MockHashes {
     pub gz: String,
     pub xz: Option<String>,
+    pub zst: Option<String>,
 }
 
 pub enum ManifestVersion {
@@ -117,13 +128,13 @@ pub enum ManifestVersion {
 }
 
 impl MockDistServer {
-    pub fn write(&self, vs: &[ManifestVersion], enable_xz: bool) {
+    pub fn write(&self, vs: &[ManifestVersion], enable_xz: bool, enable_zst: bool) {
         fs::create_dir_all(&self.path).unwrap();
 
         for channel in self.channels.iter() {
             let mut hashes = HashMap::new();
             for package in &channel.packages {
-                let new_hashes = self.build_package(&channel, &package, enable_xz);
+                let new_hashes = self.build_package(&channel, &package, enable_xz, enable_zst);
                 hashes.extend(new_hashes.into_iter());
             }
             for v in vs {
@@ -140,6 +151,7 @@ impl MockDistServer {
         channel: &MockChannel,
         package: &MockPackage,
         enable_xz: bool,
+        enable_zst: bool,
     ) -> HashMap<MockComponent, MockHashes> {
         let mut hashes = HashMap::new();
 
@@ -150,6 +162,11 @@ impl MockDistServer {
             } else {
                 None
             };
+            let zst_hash = if enable_zst {
+                Some(self.build_target_package(channel, package, target_package, ".tar.zst"))
+            } else {
+                None
+            };
             let component = MockComponent {
                 name: package.name.to_string(),
                 target: target_package.target.to_string(),
@@ -159,6 +176,7 @@ impl MockDistServer {
                 MockHashes {
                     gz: gz_hash,
                     xz: xz_hash,
+                    zst: zst_hash,
                 },
             );
         }
@@ -194,10 +212,11 @@ impl MockDistServer {
         let installer_dir = workdir.join(&installer_name);
         let installer_tarball = archive_dir.join(format!("{}{}", installer_name, format));
         let installer_hash = archive_dir.join(format!("{}{}.sha256", installer_name, format));
+        let installer_sig = archive_dir.join(format!("{}{}.asc", installer_name, format));
 
         fs::create_dir_all(&installer_dir).unwrap();
 
-        type Tarball = HashMap<(String, MockTargetedPackage, String), (Vec<u8>, String)>;
+        type Tarball = HashMap<(String, MockTargetedPackage, String), (Vec<u8>, String, String)>;
         // Tarball creation can be super slow, so cache created tarballs
         // globally to avoid recreating and recompressing tons of tarballs.
         lazy_static! {
@@ -211,7 +230,7 @@ impl MockDistServer {
         );
         let tarballs = TARBALLS.lock().unwrap();
         let hash = if tarballs.contains_key(&key) {
-            let (ref contents, ref hash) = tarballs[&key];
+            let (ref contents, ref hash, ref sig) = tarballs[&key];
             File::create(&installer_tarball)
                 .unwrap()
                 .write_all(contents)
@@ -220,6 +239,7 @@ impl MockDistServer {
                 .unwrap()
                 .write_all(hash.as_bytes())
                 .unwrap();
+            write_file(&installer_sig, sig);
             hash.clone()
         } else {
             drop(tarballs);
@@ -235,10 +255,11 @@ impl MockDistServer {
                 .read_to_end(&mut contents)
                 .unwrap();
             let hash = create_hash(&installer_tarball, &installer_hash);
+            let sig = create_signature(&installer_tarball, &installer_sig);
             TARBALLS
                 .lock()
                 .unwrap()
-                .insert(key, (contents, hash.clone()));
+                .insert(key, (contents, hash.clone(), sig));
             hash
         };
 
@@ -246,8 +267,10 @@ impl MockDistServer {
         if package.name == "rust" {
             let main_installer_tarball = dist_dir.join(format!("{}{}", installer_name, format));
             let main_installer_hash = dist_dir.join(format!("{}{}.sha256", installer_name, format));
+            let main_installer_sig = dist_dir.join(format!("{}{}.asc", installer_name, format));
             hard_link(installer_tarball, main_installer_tarball).unwrap();
             hard_link(installer_hash, main_installer_hash).unwrap();
+            hard_link(installer_sig, main_installer_sig).unwrap();
         }
 
         hash
@@ -273,13 +296,19 @@ impl MockDistServer {
         let hash_path = self.path.join(format!("{}.sha256", manifest_name));
         create_hash(&manifest_path, &hash_path);
 
-        // Also copy the manifest and hash into the archive folder
+        let sig_path = self.path.join(format!("{}.asc", manifest_name));
+        create_signature(&manifest_path, &sig_path);
+
+        // Also copy the manifest, hash and signature into the archive folder
         let archive_manifest_name = format!("dist/{}/channel-rust-{}", channel.date, channel.name);
         let archive_manifest_path = self.path.join(&archive_manifest_name);
         hard_link(manifest_path, archive_manifest_path).unwrap();
 
         let archive_hash_path = self.path.join(format!("{}.sha256", archive_manifest_name));
         hard_link(&hash_path, archive_hash_path).unwrap();
+
+        let archive_sig_path = self.path.join(format!("{}.asc", archive_manifest_name));
+        hard_link(&sig_path, archive_sig_path).unwrap();
     }
 
     fn write_manifest_v2(
@@ -343,6 +372,13 @@ impl MockDistServer {
                     );
                     toml_target.insert(String::from("xz_hash"), toml::Value::String(xz_hash));
                 }
+                if let Some(zst_hash) = hash.zst {
+                    toml_target.insert(
+                        String::from("zst_url"),
+                        toml::Value::String(url.replace(".tar.gz", ".tar.zst")),
+                    );
+                    toml_target.insert(String::from("zst_hash"), toml::Value::String(zst_hash));
+                }
 
                 // [pkg.*.target.*.components.*]
                 let mut toml_components = toml::value::Array::new();
@@ -405,7 +441,10 @@ impl MockDistServer {
         let hash_path = self.path.join(format!("{}.toml.sha256", manifest_name));
         create_hash(&manifest_path, &hash_path);
 
-        // Also copy the manifest and hash into the archive folder
+        let sig_path = self.path.join(format!("{}.toml.asc", manifest_name));
+        create_signature(&manifest_path, &sig_path);
+
+        // Also copy the manifest, hash and signature into the archive folder
         let archive_manifest_name = format!("dist/{}/channel-rust-{}", channel.date, channel.name);
         let archive_manifest_path = self.path.join(format!("{}.toml", archive_manifest_name));
         hard_link(&manifest_path, archive_manifest_path).unwrap();
@@ -414,6 +453,11 @@ impl MockDistServer {
             .path
             .join(format!("{}.toml.sha256", archive_manifest_name));
         hard_link(hash_path, archive_hash_path).unwrap();
+
+        let archive_sig_path = self
+            .path
+            .join(format!("{}.toml.asc", archive_manifest_name));
+        hard_link(sig_path, archive_sig_path).unwrap();
     }
 }
 
@@ -422,6 +466,8 @@ fn create_tarball(relpath: &Path, src: &Path, dst: &Path) {
     let outfile = File::create(dst).unwrap();
     let mut gzwriter;
     let mut xzwriter;
+    #[cfg(feature = "zstd")]
+    let mut zstwriter;
     let writer: &mut dyn Write = match &dst.to_string_lossy() {
         s if s.ends_with(".tar.gz") => {
             gzwriter = flate2::write::GzEncoder::new(outfile, flate2::Compression::none());
@@ -431,6 +477,13 @@ fn create_tarball(relpath: &Path, src: &Path, dst: &Path) {
             xzwriter = xz2::write::XzEncoder::new(outfile, 0);
             &mut xzwriter
         }
+        #[cfg(feature = "zstd")]
+        s if s.ends_with(".tar.zst") => {
+            zstwriter = zstd::stream::write::Encoder::new(outfile, 0)
+                .unwrap()
+                .auto_finish();
+            &mut zstwriter
+        }
         _ => panic!("Unsupported archive format"),
     };
     let mut tar = tar::Builder::new(writer);
@@ -473,6 +526,39 @@ pub fn create_hash(src: &Path, dst: &Path) -> String {
     hex
 }
 
+// The public half of this key is what the tests hand to rustup via
+// RUSTUP_GPG_KEY, see `signing_key_path`
+const SIGNING_KEY: &str = include_str!("signing-key.asc");
+
+pub fn signing_key_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/mock/signing-key.pub.asc")
+}
+
+// Writes a detached, ASCII-armored signature of `src` to `dst`
+pub fn create_signature(src: &Path, dst: &Path) -> String {
+    lazy_static! {
+        static ref KEY: SignedSecretKey = SignedSecretKey::from_string(SIGNING_KEY).unwrap().0;
+    }
+
+    let mut buf = Vec::new();
+    File::open(src).unwrap().read_to_end(&mut buf).unwrap();
+
+    let config = SignatureConfigBuilder::default()
+        .typ(SignatureType::Binary)
+        .pub_alg(KEY.algorithm())
+        .hash_alg(HashAlgorithm::SHA2_256)
+        .hashed_subpackets(vec![Subpacket::Issuer(KEY.key_id())])
+        .unhashed_subpackets(vec![])
+        .build()
+        .unwrap();
+    let signature = config.sign(&*KEY, String::new, Cursor::new(buf)).unwrap();
+    let armored = StandaloneSignature::new(signature)
+        .to_armored_string(None)
+        .unwrap();
+    write_file(dst, &armored);
+    armored
+}
+
 fn write_file(dst: &Path, contents: &str) {
     drop(fs::remove_file(dst));
     File::create(dst)